@@ -1,4 +1,4 @@
-use bech32::ToBase32;
+use bech32::{FromBase32, ToBase32};
 use hex_literal::hex;
 use chia_bls::{G1Element, DerivableKey};
 use clvm_utils::tree_hash_atom;
@@ -14,15 +14,32 @@ fn master_pk_to_wallet_pk_unhardened_intermediate(master_pk: &G1Element) -> G1El
     DerivableKey::derive_unhardened(&k, 2)
 }
 
-pub fn generate_observe_wallet_address(master_pk: &G1Element, index: u32) -> String {
+pub fn generate_observe_wallet_puzzle_hash(master_pk: &G1Element, index: u32) -> [u8; 32] {
     let intermediate = master_pk_to_wallet_pk_unhardened_intermediate(master_pk);
     let key = DerivableKey::derive_unhardened(&intermediate, index);
     let synthetic_key = crate::derive_synthetic::DeriveSynthetic::derive_synthetic(&key, &DEFAULT_HIDDEN_PUZZLE_HASH);
     let pk_tree_hash = tree_hash_atom(&synthetic_key.to_bytes());
-    let puzzle_hash = curry_tree_hash(STANDARD_PUZZLE_HASH, &[pk_tree_hash]);
+    curry_tree_hash(STANDARD_PUZZLE_HASH, &[pk_tree_hash])
+}
+
+pub fn generate_observe_wallet_address(master_pk: &G1Element, index: u32) -> String {
+    puzzle_hash_to_address(&generate_observe_wallet_puzzle_hash(master_pk, index))
+}
+
+pub fn puzzle_hash_to_address(puzzle_hash: &[u8; 32]) -> String {
     bech32::encode("xch", puzzle_hash.to_vec().to_base32(), bech32::Variant::Bech32m).unwrap()
 }
 
+pub fn address_to_puzzle_hash(address: &str) -> Result<[u8; 32], bech32::Error> {
+    let (_, data, variant) = bech32::decode(address)?;
+    if variant != bech32::Variant::Bech32m {
+        return Err(bech32::Error::InvalidChecksum);
+    }
+    Vec::<u8>::from_base32(&data)?
+        .try_into()
+        .map_err(|_| bech32::Error::InvalidLength)
+}
+
 pub fn generate_multiple_observe_wallet_addresses(master_pk: &G1Element, start_from_index: u32, count: &u32) -> Vec<String> {
     let mut addresses: Vec<String> = Vec::new();
     for i in start_from_index..(start_from_index + count) {
@@ -1,4 +1,5 @@
 pub use chia_bls::G1Element; 
+pub use bech32::Error as AddressError;
 
 mod derive_synthetic;
 mod curry_tree_hash;
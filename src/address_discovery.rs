@@ -0,0 +1,161 @@
+use std::collections::HashMap;
+
+use chia_observer_wallet_generator::derive_wallet::{generate_observe_wallet_puzzle_hash, puzzle_hash_to_address};
+use chia_observer_wallet_generator::G1Element;
+
+use crate::wallet_transactions::WalletTransaction;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DerivedAddress {
+    pub derivation_index: u32,
+    pub address: String,
+    pub puzzle_hash: [u8; 32],
+}
+
+/// Gap limit address discovery: addresses are derived until `gap_limit`
+/// consecutive addresses after the highest used one are unused.
+pub struct AddressDiscovery<'a> {
+    pk: &'a G1Element,
+    gap_limit: u32,
+    derived_addresses: Vec<DerivedAddress>,
+    highest_used_index: Option<u32>,
+    indexes: HashMap<[u8; 32], u32>,
+}
+
+impl<'a> AddressDiscovery<'a> {
+    /// Starts from the addresses cached by an earlier run, `derived_addresses`
+    /// must be in derivation order starting at index 0.
+    pub fn new(pk: &'a G1Element, gap_limit: u32, derived_addresses: Vec<DerivedAddress>, highest_used_index: Option<u32>) -> Self {
+        let mut discovery = Self {
+            pk,
+            gap_limit,
+            derived_addresses: Vec::new(),
+            highest_used_index,
+            indexes: HashMap::new(),
+        };
+        for derived_address in derived_addresses {
+            if derived_address.derivation_index as usize != discovery.derived_addresses.len() {
+                break;
+            }
+            discovery.indexes.insert(derived_address.puzzle_hash, derived_address.derivation_index);
+            discovery.derived_addresses.push(derived_address);
+        }
+        discovery.derive_to_gap_limit();
+
+        discovery
+    }
+
+    pub fn derived_addresses(&self) -> &[DerivedAddress] {
+        &self.derived_addresses
+    }
+
+    pub fn addresses(&self) -> Vec<String> {
+        self.derived_addresses.iter().map(|derived_address| derived_address.address.clone()).collect()
+    }
+
+    pub fn highest_used_index(&self) -> Option<u32> {
+        self.highest_used_index
+    }
+
+    /// Marks the addresses the transactions were sent to or whose coins they
+    /// created or spent as used, and derives the addresses needed to keep the
+    /// gap. Returns true when new addresses were derived, they have to be
+    /// checked for transactions as well.
+    pub fn mark_used(&mut self, wallet_transactions: &[WalletTransaction]) -> bool {
+        let addresses: HashMap<&str, u32> = self.derived_addresses.iter()
+            .map(|derived_address| (derived_address.address.as_str(), derived_address.derivation_index))
+            .collect();
+
+        let mut highest_used_index = self.highest_used_index;
+        for wallet_transaction in wallet_transactions {
+            let used_indexes = wallet_transaction.to_address.as_deref()
+                .and_then(|to_address| addresses.get(to_address).copied())
+                .into_iter()
+                .chain(
+                    wallet_transaction.additions.iter()
+                        .chain(&wallet_transaction.removals)
+                        .filter_map(|coin| self.indexes.get(&coin.puzzle_hash).copied()),
+                );
+            for index in used_indexes {
+                highest_used_index = highest_used_index.max(Some(index));
+            }
+        }
+        self.highest_used_index = highest_used_index;
+
+        let derived_count = self.derived_addresses.len();
+        self.derive_to_gap_limit();
+        self.derived_addresses.len() > derived_count
+    }
+
+    fn derive_to_gap_limit(&mut self) {
+        let target_count = self.highest_used_index.map_or(0, |index| index + 1) + self.gap_limit;
+        for index in self.derived_addresses.len() as u32..target_count {
+            let derived_address = derive_address(self.pk, index);
+            self.indexes.insert(derived_address.puzzle_hash, index);
+            self.derived_addresses.push(derived_address);
+        }
+    }
+}
+
+/// The observer address of the public key at the derivation index
+pub fn derive_address(pk: &G1Element, derivation_index: u32) -> DerivedAddress {
+    let puzzle_hash = generate_observe_wallet_puzzle_hash(pk, derivation_index);
+    DerivedAddress {
+        derivation_index,
+        address: puzzle_hash_to_address(&puzzle_hash),
+        puzzle_hash,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hex::FromHex;
+
+    use super::*;
+    use crate::wallet_transactions::tests::wallet_transaction;
+    use crate::wallet_transactions::{Coin, Flow};
+    use crate::Config;
+
+    fn received_transaction(to_address: Option<String>, additions: Vec<Coin>) -> WalletTransaction {
+        WalletTransaction { to_address, additions, ..wallet_transaction(1, Flow::Incoming, 1, "2023-08-31T00:00:00Z") }
+    }
+
+    #[test]
+    fn test_gap_limit() {
+        let config = Config::default();
+        let pk = G1Element::from_bytes(&<[u8; 48]>::from_hex(&config.wallet_public_key).unwrap()).unwrap();
+
+        let mut discovery = AddressDiscovery::new(&pk, 3, Vec::new(), None);
+        assert_eq!(discovery.derived_addresses().len(), 3);
+        assert!(!discovery.mark_used(&[]));
+
+        let address_2 = discovery.addresses()[2].clone();
+        assert!(discovery.mark_used(&[received_transaction(Some(address_2), Vec::new())]));
+        assert_eq!(discovery.highest_used_index(), Some(2));
+        assert_eq!(discovery.derived_addresses().len(), 6);
+        assert_eq!(discovery.derived_addresses()[5].address, puzzle_hash_to_address(&generate_observe_wallet_puzzle_hash(&pk, 5)));
+
+        // found through the coins of a transaction to someone else
+        let puzzle_hash_4 = discovery.derived_addresses()[4].puzzle_hash;
+        let coin = Coin { parent_coin_info: [2u8; 32], puzzle_hash: puzzle_hash_4, amount: 1 };
+        assert!(discovery.mark_used(&[received_transaction(Some(String::from("xch1other")), vec![coin])]));
+        assert_eq!(discovery.derived_addresses().len(), 8);
+
+        // a lower index doesn't move the gap
+        let address_0 = discovery.addresses()[0].clone();
+        assert!(!discovery.mark_used(&[received_transaction(Some(address_0), Vec::new())]));
+        assert_eq!(discovery.highest_used_index(), Some(4));
+    }
+
+    #[test]
+    fn test_resume_from_cache() {
+        let config = Config::default();
+        let pk = G1Element::from_bytes(&<[u8; 48]>::from_hex(&config.wallet_public_key).unwrap()).unwrap();
+
+        let cached = AddressDiscovery::new(&pk, 5, Vec::new(), Some(4)).derived_addresses().to_vec();
+        assert_eq!(cached.len(), 10);
+
+        let discovery = AddressDiscovery::new(&pk, 5, cached[..7].to_vec(), Some(4));
+        assert_eq!(discovery.derived_addresses(), cached.as_slice());
+    }
+}
@@ -0,0 +1,313 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use chia_observer_wallet_generator::derive_wallet::address_to_puzzle_hash;
+use chia_observer_wallet_generator::AddressError;
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+
+use crate::wallet_transactions::{mojos_to_xch, Flow, WalletTransaction};
+
+/// Where a tracked address comes from, stored in the `kind` column of `addresses`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AddressKind {
+    /// Derived from the wallet public key
+    Derived,
+    /// Listed in `watch_addresses`
+    Watched,
+}
+
+impl AddressKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AddressKind::Derived => "derived",
+            AddressKind::Watched => "watched",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum WatchAddressError {
+    Decode(AddressError),
+    NotXch,
+}
+
+impl fmt::Display for WatchAddressError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WatchAddressError::Decode(e) => write!(f, "not a bech32m address: {}", e),
+            WatchAddressError::NotXch => write!(f, "not an xch address"),
+        }
+    }
+}
+
+impl std::error::Error for WatchAddressError {}
+
+/// Checks that a watched address is a bech32m encoded xch address and
+/// returns its puzzle hash
+pub fn parse_watch_address(address: &str) -> Result<[u8; 32], WatchAddressError> {
+    let puzzle_hash = address_to_puzzle_hash(address).map_err(WatchAddressError::Decode)?;
+    if !address.to_lowercase().starts_with("xch1") {
+        return Err(WatchAddressError::NotXch);
+    }
+
+    Ok(puzzle_hash)
+}
+
+/// Activity of one tracked address
+#[derive(Debug, Clone, PartialEq)]
+pub struct AddressSummary {
+    pub address: String,
+    /// None for watched addresses
+    pub derivation_index: Option<u32>,
+    /// Mojos received in confirmed transactions
+    pub total_received: u64,
+    /// Mojos spent in confirmed transactions, None when the wallet spent in a
+    /// transaction without coins, which doesn't tell the addresses it spent from
+    pub total_sent: Option<u64>,
+    pub first_seen: Option<DateTime<Utc>>,
+    pub last_seen: Option<DateTime<Utc>>,
+    pub tx_count: u32,
+}
+
+impl AddressSummary {
+    fn new(address: &str, derivation_index: Option<u32>) -> Self {
+        Self {
+            address: address.to_string(),
+            derivation_index,
+            total_received: 0,
+            total_sent: Some(0),
+            first_seen: None,
+            last_seen: None,
+            tx_count: 0,
+        }
+    }
+
+    pub fn kind(&self) -> AddressKind {
+        if self.derivation_index.is_some() {
+            AddressKind::Derived
+        } else {
+            AddressKind::Watched
+        }
+    }
+
+    /// Mojos left on the address, None when the sent mojos aren't known
+    pub fn balance(&self) -> Option<u64> {
+        self.total_sent.map(|total_sent| self.total_received.saturating_sub(total_sent))
+    }
+
+    pub fn chia_total_received(&self) -> Decimal {
+        mojos_to_xch(self.total_received)
+    }
+
+    pub fn chia_total_sent(&self) -> Option<Decimal> {
+        self.total_sent.map(mojos_to_xch)
+    }
+
+    pub fn chia_balance(&self) -> Option<Decimal> {
+        self.balance().map(mojos_to_xch)
+    }
+}
+
+/// Breaks the transactions down per address, `derived_addresses` is in
+/// derivation order and the `watch_addresses` come after them. The coins of a
+/// transaction are attributed to the address of their puzzle hash.
+/// Transactions without coins only count towards the address they were sent
+/// to, the sent mojos of every address are unknown once the wallet spent in
+/// one of them.
+pub fn summarize_addresses(
+    derived_addresses: &[String],
+    watch_addresses: &[String],
+    wallet_transactions: &[WalletTransaction],
+) -> Vec<AddressSummary> {
+    let mut summaries: Vec<AddressSummary> = derived_addresses.iter()
+        .enumerate()
+        .map(|(index, address)| AddressSummary::new(address, Some(index as u32)))
+        .collect();
+    for address in watch_addresses {
+        if !derived_addresses.contains(address) {
+            summaries.push(AddressSummary::new(address, None));
+        }
+    }
+    let puzzle_hash_indexes: HashMap<[u8; 32], usize> = summaries.iter()
+        .enumerate()
+        .filter_map(|(index, summary)| Some((address_to_puzzle_hash(&summary.address).ok()?, index)))
+        .collect();
+    let addresses: Vec<String> = summaries.iter().map(|summary| summary.address.clone()).collect();
+    let address_indexes: HashMap<&str, usize> = addresses.iter()
+        .enumerate()
+        .map(|(index, address)| (address.as_str(), index))
+        .collect();
+
+    let mut sent_unknown = false;
+    for wallet_transaction in wallet_transactions {
+        let confirmed = wallet_transaction.status.is_confirmed();
+        let mut touched: Vec<usize> = Vec::new();
+
+        if wallet_transaction.additions.is_empty() && wallet_transaction.removals.is_empty() {
+            sent_unknown |= confirmed && wallet_transaction.flow != Flow::Incoming;
+            if let Some(&index) = wallet_transaction.to_address.as_deref().and_then(|to_address| address_indexes.get(to_address)) {
+                if confirmed && wallet_transaction.flow == Flow::Incoming {
+                    summaries[index].total_received += wallet_transaction.amount;
+                }
+                touched.push(index);
+            }
+        }
+
+        for coin in &wallet_transaction.additions {
+            if let Some(&index) = puzzle_hash_indexes.get(&coin.puzzle_hash) {
+                if confirmed {
+                    summaries[index].total_received += coin.amount;
+                }
+                touched.push(index);
+            }
+        }
+
+        for coin in &wallet_transaction.removals {
+            if let Some(&index) = puzzle_hash_indexes.get(&coin.puzzle_hash) {
+                if confirmed {
+                    summaries[index].total_sent = summaries[index].total_sent.map(|total_sent| total_sent + coin.amount);
+                }
+                touched.push(index);
+            }
+        }
+
+        touched.sort_unstable();
+        touched.dedup();
+        for index in touched {
+            let summary = &mut summaries[index];
+            let created_at_time = wallet_transaction.created_at_time;
+            summary.tx_count += 1;
+            summary.first_seen = Some(summary.first_seen.map_or(created_at_time, |first_seen| first_seen.min(created_at_time)));
+            summary.last_seen = Some(summary.last_seen.map_or(created_at_time, |last_seen| last_seen.max(created_at_time)));
+        }
+    }
+
+    if sent_unknown {
+        for summary in &mut summaries {
+            summary.total_sent = None;
+        }
+    }
+
+    summaries
+}
+
+/// Prints the per address breakdown as a table
+pub fn print_address_report(summaries: &[AddressSummary]) {
+    println!(
+        "{:>5}  {:<62}  {:>18}  {:>18}  {:>18}  {:>5}  {:<20}  {:<20}",
+        "index", "address", "received", "sent", "balance", "txs", "first seen", "last seen"
+    );
+    for summary in summaries {
+        let format_time = |time: Option<DateTime<Utc>>| {
+            time.map_or(String::from("-"), |time| time.format("%Y-%m-%d %H:%M:%S").to_string())
+        };
+        println!(
+            "{:>5}  {:<62}  {:>18}  {:>18}  {:>18}  {:>5}  {:<20}  {:<20}",
+            summary.derivation_index.map_or(String::from("watch"), |index| index.to_string()),
+            summary.address,
+            summary.chia_total_received(),
+            summary.chia_total_sent().map_or(String::from("-"), |total_sent| total_sent.to_string()),
+            summary.chia_balance().map_or(String::from("-"), |balance| balance.to_string()),
+            summary.tx_count,
+            format_time(summary.first_seen),
+            format_time(summary.last_seen),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chia_observer_wallet_generator::derive_wallet::puzzle_hash_to_address;
+
+    use super::*;
+    use crate::wallet_transactions::tests::wallet_transaction;
+    use crate::wallet_transactions::{parse_created_at_time, Coin};
+
+    fn coin_transaction(id: u8, created_at_time: &str, to_address: Option<&str>, additions: Vec<Coin>, removals: Vec<Coin>) -> WalletTransaction {
+        WalletTransaction {
+            to_address: to_address.map(String::from),
+            additions,
+            removals,
+            ..wallet_transaction(id, Flow::Incoming, 1_000, created_at_time)
+        }
+    }
+
+    #[test]
+    fn test_summarize_addresses() {
+        let wallet_addresses = vec![
+            puzzle_hash_to_address(&[1u8; 32]),
+            puzzle_hash_to_address(&[2u8; 32]),
+            puzzle_hash_to_address(&[3u8; 32]),
+        ];
+        let received = Coin { parent_coin_info: [9u8; 32], puzzle_hash: [1u8; 32], amount: 1_000 };
+        let change = Coin { parent_coin_info: [8u8; 32], puzzle_hash: [2u8; 32], amount: 400 };
+
+        let wallet_transactions = vec![
+            coin_transaction(1, "2023-08-31T00:00:00Z", Some(&wallet_addresses[0]), vec![received.clone()], vec![]),
+            coin_transaction(2, "2023-09-01T00:00:00Z", None, vec![Coin { puzzle_hash: [7u8; 32], ..change.clone() }, change], vec![received]),
+            // no coins reported by the source
+            coin_transaction(3, "2023-09-02T00:00:00Z", Some(&wallet_addresses[0]), vec![], vec![]),
+        ];
+
+        let summaries = summarize_addresses(&wallet_addresses, &[], &wallet_transactions);
+
+        assert_eq!(summaries[0].derivation_index, Some(0));
+        assert_eq!((summaries[0].total_received, summaries[0].total_sent, summaries[0].balance()), (2_000, Some(1_000), Some(1_000)));
+        assert_eq!(summaries[0].tx_count, 3);
+        assert_eq!(summaries[0].first_seen, Some(parse_created_at_time("2023-08-31T00:00:00Z").unwrap()));
+        assert_eq!(summaries[0].last_seen, Some(parse_created_at_time("2023-09-02T00:00:00Z").unwrap()));
+        assert_eq!((summaries[1].balance(), summaries[1].tx_count), (Some(400), 1));
+        assert_eq!((summaries[2].tx_count, summaries[2].first_seen), (0, None));
+
+        // a payment without coins could have been paid from any address
+        let payment = WalletTransaction {
+            to_address: Some(String::from("xch1shop")),
+            ..wallet_transaction(4, Flow::Outgoing, 500, "2023-09-03T00:00:00Z")
+        };
+        let summaries = summarize_addresses(&wallet_addresses, &[], &[wallet_transactions, vec![payment]].concat());
+        assert_eq!((summaries[0].total_received, summaries[0].total_sent, summaries[0].balance()), (2_000, None, None));
+        assert_eq!(summaries[1].balance(), None);
+    }
+
+    #[test]
+    fn test_summarize_watched_addresses() {
+        let derived_addresses = vec![puzzle_hash_to_address(&[1u8; 32])];
+        let watch_addresses = vec![puzzle_hash_to_address(&[5u8; 32]), derived_addresses[0].clone()];
+        let payout = Coin { parent_coin_info: [9u8; 32], puzzle_hash: [5u8; 32], amount: 1_750 };
+
+        let wallet_transactions = vec![
+            coin_transaction(1, "2023-08-31T00:00:00Z", Some(&watch_addresses[0]), vec![payout], vec![]),
+        ];
+
+        let summaries = summarize_addresses(&derived_addresses, &watch_addresses, &wallet_transactions);
+
+        // a watched address that is derived anyway is only listed once
+        assert_eq!(summaries.len(), 2);
+        assert_eq!(summaries[1].kind(), AddressKind::Watched);
+        assert_eq!((summaries[1].derivation_index, summaries[1].balance(), summaries[1].tx_count), (None, Some(1_750), 1));
+        assert_eq!(summaries[0].kind(), AddressKind::Derived);
+    }
+
+    #[test]
+    fn test_parse_watch_address() {
+        let address = puzzle_hash_to_address(&[5u8; 32]);
+        assert_eq!(parse_watch_address(&address).unwrap(), [5u8; 32]);
+
+        let mut corrupted = address.clone();
+        corrupted.replace_range(10..11, if &address[10..11] == "q" { "p" } else { "q" });
+        assert!(matches!(parse_watch_address(&corrupted), Err(WatchAddressError::Decode(_))));
+        assert!(matches!(parse_watch_address("xch1notanaddress"), Err(WatchAddressError::Decode(_))));
+
+        // valid bech32m of the same puzzle hash with other prefixes
+        assert_eq!(address, "xch1q5zs2pg9q5zs2pg9q5zs2pg9q5zs2pg9q5zs2pg9q5zs2pg9q5zsn5pv30");
+        assert!(matches!(
+            parse_watch_address("txch1q5zs2pg9q5zs2pg9q5zs2pg9q5zs2pg9q5zs2pg9q5zs2pg9q5zs7nx6su"),
+            Err(WatchAddressError::NotXch)
+        ));
+        assert!(matches!(
+            parse_watch_address("nft1q5zs2pg9q5zs2pg9q5zs2pg9q5zs2pg9q5zs2pg9q5zs2pg9q5zs4v3tdv"),
+            Err(WatchAddressError::NotXch)
+        ));
+    }
+}
@@ -0,0 +1,155 @@
+use std::collections::BTreeMap;
+
+use chrono::{Days, NaiveDate};
+use rust_decimal::Decimal;
+
+use crate::wallet_transactions::{Flow, TxStatus, WalletTransaction};
+
+/// Balance of a wallet at the end of a UTC day
+#[derive(Debug, Clone, PartialEq)]
+pub struct DailyBalance {
+    pub day: NaiveDate,
+    /// Mojos, negative when the transactions before it weren't all tracked
+    pub balance: i64,
+    /// Xch price at the end of the day per quote currency, from the price cache
+    pub prices: BTreeMap<String, Decimal>,
+}
+
+impl DailyBalance {
+    pub fn chia_balance(&self) -> Decimal {
+        Decimal::from_i128_with_scale(self.balance.into(), 12).normalize()
+    }
+
+    /// Value of the balance in `quote_currency`, None without a price
+    pub fn value(&self, quote_currency: &str) -> Option<Decimal> {
+        self.prices.get(quote_currency).map(|price| self.chia_balance() * price)
+    }
+}
+
+/// Balance at the end of every day from the day of the first confirmed
+/// transaction through `through`. Outgoing transactions take their fee along,
+/// internal ones only the fee.
+pub fn daily_balances(wallet_transactions: &[WalletTransaction], through: NaiveDate) -> Vec<DailyBalance> {
+    let mut changes: BTreeMap<NaiveDate, i64> = BTreeMap::new();
+    for wallet_transaction in wallet_transactions.iter().filter(|wallet_transaction| wallet_transaction.status == TxStatus::Confirmed) {
+        let amount = wallet_transaction.amount as i64;
+        let fee_amount = wallet_transaction.fee_amount as i64;
+        let change = match wallet_transaction.flow {
+            Flow::Incoming => amount,
+            Flow::Outgoing => -amount - fee_amount,
+            Flow::Internal => -fee_amount,
+        };
+        *changes.entry(wallet_transaction.created_at_time.date_naive()).or_default() += change;
+    }
+
+    let Some(first_day) = changes.keys().next().copied() else {
+        return Vec::new();
+    };
+    let mut daily_balances = Vec::new();
+    let mut balance = 0;
+    let mut day = first_day;
+    while day <= through {
+        balance += changes.get(&day).copied().unwrap_or_default();
+        daily_balances.push(DailyBalance { day, balance, prices: BTreeMap::new() });
+        day = match day.checked_add_days(Days::new(1)) {
+            Some(next_day) => next_day,
+            None => break,
+        };
+    }
+
+    daily_balances
+}
+
+/// Sum of the balances of several wallets per day, a wallet counts as empty
+/// before its first day. The histories have to end on the same day.
+pub fn total_daily_balances(histories: &[Vec<DailyBalance>]) -> Vec<DailyBalance> {
+    let mut totals: BTreeMap<NaiveDate, DailyBalance> = BTreeMap::new();
+    for history in histories {
+        for daily_balance in history {
+            let total = totals.entry(daily_balance.day).or_insert_with(|| DailyBalance {
+                day: daily_balance.day,
+                balance: 0,
+                prices: BTreeMap::new(),
+            });
+            total.balance += daily_balance.balance;
+            // every wallet is valued from the same price cache
+            total.prices.extend(daily_balance.prices.clone());
+        }
+    }
+
+    totals.into_values().collect()
+}
+
+pub fn print_balance_history(daily_balances: &[DailyBalance], quote_currencies: &[String]) {
+    let mut header = format!("{:<10}  {:>18}", "day", "balance");
+    for quote_currency in quote_currencies {
+        header.push_str(&format!("  {:>14}", format!("{} value", quote_currency)));
+    }
+    println!("{}", header);
+
+    for daily_balance in daily_balances {
+        let mut line = format!("{:<10}  {:>18}", daily_balance.day.format("%Y-%m-%d"), daily_balance.chia_balance());
+        for quote_currency in quote_currencies {
+            let value = daily_balance.value(quote_currency).map_or(String::from("-"), |value| value.round_dp(2).to_string());
+            line.push_str(&format!("  {:>14}", value));
+        }
+        println!("{}", line);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+    use crate::wallet_transactions::tests::wallet_transaction;
+
+    fn day(day: &str) -> NaiveDate {
+        NaiveDate::from_str(day).unwrap()
+    }
+
+    #[test]
+    fn test_daily_balances() {
+        let mut wallet_transactions = vec![
+            wallet_transaction(2, Flow::Outgoing, 500_000_000_000, "2023-09-02T10:00:00Z"),
+            wallet_transaction(1, Flow::Incoming, 2_000_000_000_000, "2023-08-31T23:59:59Z"),
+            wallet_transaction(3, Flow::Internal, 1_000_000_000_000, "2023-09-02T11:00:00Z"),
+            wallet_transaction(4, Flow::Incoming, 1_000_000_000_000, "2023-09-01T10:00:00Z"),
+        ];
+        wallet_transactions[0].fee_amount = 1_000_000;
+        wallet_transactions[2].fee_amount = 1_000_000;
+        wallet_transactions[3].status = TxStatus::Pending;
+
+        let daily_balances = daily_balances(&wallet_transactions, day("2023-09-03"));
+        assert_eq!(
+            daily_balances.iter().map(|daily_balance| (daily_balance.day, daily_balance.balance)).collect::<Vec<(NaiveDate, i64)>>(),
+            vec![
+                (day("2023-08-31"), 2_000_000_000_000),
+                (day("2023-09-01"), 2_000_000_000_000),
+                (day("2023-09-02"), 1_499_998_000_000),
+                (day("2023-09-03"), 1_499_998_000_000),
+            ],
+        );
+        assert_eq!(daily_balances[2].chia_balance(), Decimal::from_str("1.499998").unwrap());
+        assert!(super::daily_balances(&[], day("2023-09-03")).is_empty());
+    }
+
+    #[test]
+    fn test_total_daily_balances() {
+        let first = daily_balances(&[wallet_transaction(1, Flow::Incoming, 2_000_000_000_000, "2023-08-31T10:00:00Z")], day("2023-09-02"));
+        let mut second = daily_balances(&[wallet_transaction(2, Flow::Incoming, 1_000_000_000_000, "2023-09-01T10:00:00Z")], day("2023-09-02"));
+        second[1].prices.insert(String::from("usd"), Decimal::from(30));
+
+        let totals = total_daily_balances(&[first, second]);
+        assert_eq!(
+            totals.iter().map(|daily_balance| (daily_balance.day, daily_balance.balance)).collect::<Vec<(NaiveDate, i64)>>(),
+            vec![
+                (day("2023-08-31"), 2_000_000_000_000),
+                (day("2023-09-01"), 3_000_000_000_000),
+                (day("2023-09-02"), 3_000_000_000_000),
+            ],
+        );
+        assert_eq!(totals[0].value("usd"), None);
+        assert_eq!(totals[2].value("usd"), Some(Decimal::from(90)));
+    }
+}
@@ -0,0 +1,67 @@
+use hex::FromHex;
+
+/// Genesis challenge of mainnet, the reward coins' parent ids are made from it
+const MAINNET_GENESIS_CHALLENGE: &str = "ccd5bb71183532bff220ba46c268991a3ff07eb358e8255a65c30a2dce0e5fbb";
+const MOJO_PER_CHIA: u64 = 1_000_000_000_000;
+/// The rewards halve every 3 years of blocks
+const HALVING_BLOCKS: u32 = 3 * 1_681_920;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RewardCoin {
+    Pool,
+    Farmer,
+}
+
+/// Farmer reward of a block in mojos, without the fees collected by the block
+pub fn calculate_base_farmer_reward(height: u32) -> u64 {
+    let halvings = (height / HALVING_BLOCKS).min(4);
+    (MOJO_PER_CHIA / 4) >> halvings
+}
+
+/// Tells if a coin was minted as a block reward from its parent id, which is
+/// half of the genesis challenge followed by the height of the rewarded block.
+/// Returns the kind of reward and the rewarded height.
+pub fn reward_coin(parent_coin_info: &[u8; 32]) -> Option<(RewardCoin, u32)> {
+    let genesis_challenge = <[u8; 32]>::from_hex(MAINNET_GENESIS_CHALLENGE).expect("invalid genesis challenge");
+    let (prefix, height) = parent_coin_info.split_at(16);
+    if height[..12].iter().any(|b| *b != 0) {
+        return None;
+    }
+    let height = u32::from_be_bytes(height[12..].try_into().expect("invalid height length"));
+
+    if *prefix == genesis_challenge[..16] {
+        Some((RewardCoin::Pool, height))
+    } else if *prefix == genesis_challenge[16..] {
+        Some((RewardCoin::Farmer, height))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rewards() {
+        assert_eq!(calculate_base_farmer_reward(1), 250_000_000_000);
+        assert_eq!(calculate_base_farmer_reward(5_045_759), 250_000_000_000);
+        assert_eq!(calculate_base_farmer_reward(5_045_760), 125_000_000_000);
+        assert_eq!(calculate_base_farmer_reward(4 * 5_045_760), 15_625_000_000);
+        assert_eq!(calculate_base_farmer_reward(u32::MAX), 15_625_000_000);
+    }
+
+    #[test]
+    fn test_reward_coin() {
+        let genesis_challenge = <[u8; 32]>::from_hex(MAINNET_GENESIS_CHALLENGE).unwrap();
+        let mut parent_coin_info = [0u8; 32];
+        parent_coin_info[..16].copy_from_slice(&genesis_challenge[..16]);
+        parent_coin_info[28..].copy_from_slice(&4_500_000u32.to_be_bytes());
+        assert_eq!(reward_coin(&parent_coin_info), Some((RewardCoin::Pool, 4_500_000)));
+
+        parent_coin_info[..16].copy_from_slice(&genesis_challenge[16..]);
+        assert_eq!(reward_coin(&parent_coin_info), Some((RewardCoin::Farmer, 4_500_000)));
+
+        assert_eq!(reward_coin(&[2u8; 32]), None);
+    }
+}
@@ -0,0 +1,201 @@
+use std::fmt;
+use std::path::Path;
+
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+#[derive(Debug)]
+pub enum RpcError {
+    Io(std::io::Error),
+    Http(reqwest::Error),
+    Json(serde_json::Error),
+    Rpc(String),
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RpcError::Io(e) => write!(f, "failed to read rpc certificate: {}", e),
+            RpcError::Http(e) => write!(f, "rpc request failed: {}", e),
+            RpcError::Json(e) => write!(f, "failed to parse rpc response: {}", e),
+            RpcError::Rpc(e) => write!(f, "rpc returned an error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for RpcError {}
+
+impl From<std::io::Error> for RpcError {
+    fn from(e: std::io::Error) -> Self {
+        RpcError::Io(e)
+    }
+}
+
+impl From<reqwest::Error> for RpcError {
+    fn from(e: reqwest::Error) -> Self {
+        RpcError::Http(e)
+    }
+}
+
+impl From<serde_json::Error> for RpcError {
+    fn from(e: serde_json::Error) -> Self {
+        RpcError::Json(e)
+    }
+}
+
+/// HTTPS client for the chia daemons' RPC interface, authenticated with the
+/// daemon's private certificate and key (mutual TLS).
+pub struct ChiaRpcClient {
+    base_url: String,
+    client: reqwest::Client,
+}
+
+impl ChiaRpcClient {
+    pub fn new(host: &str, port: u16, cert_path: &Path, key_path: &Path) -> Result<Self, RpcError> {
+        let mut pem = std::fs::read(cert_path)?;
+        pem.push(b'\n');
+        pem.extend(std::fs::read(key_path)?);
+        let identity = reqwest::Identity::from_pem(&pem)?;
+
+        // The daemon certificates are signed by the private CA of the chia install
+        // and issued for "chia.net", so the server certificate can't be verified.
+        let client = reqwest::Client::builder()
+            .use_rustls_tls()
+            .identity(identity)
+            .danger_accept_invalid_certs(true)
+            .build()?;
+
+        Ok(Self {
+            base_url: format!("https://{}:{}", host, port),
+            client,
+        })
+    }
+
+    pub async fn request<T: DeserializeOwned>(&self, endpoint: &str, body: Value) -> Result<T, RpcError> {
+        let response: Value = self.client
+            .post(format!("{}/{}", self.base_url, endpoint))
+            .json(&body)
+            .send()
+            .await?
+            .json()
+            .await?;
+
+        if response.get("success").and_then(Value::as_bool) != Some(true) {
+            let error = response.get("error").and_then(Value::as_str).unwrap_or("unknown error");
+            return Err(RpcError::Rpc(format!("{}: {}", endpoint, error)));
+        }
+
+        Ok(serde_json::from_value(response)?)
+    }
+}
+
+/// Stand-in for a chia daemon RPC server, used by the RPC client tests.
+#[cfg(test)]
+pub mod test_server {
+    use std::collections::HashMap;
+    use std::path::Path;
+    use std::sync::{Arc, Mutex};
+
+    use serde_json::Value;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio_rustls::rustls;
+    use tokio_rustls::TlsAcceptor;
+
+    pub type RecordedRequests = Arc<Mutex<Vec<(String, Value)>>>;
+
+    /// Writes a `private_<service>.crt/key` pair signed by a fresh private CA
+    /// into `<chia_root>/config/ssl/<service>`, like `chia init` does, and
+    /// returns the CA certificate in DER form.
+    pub fn write_private_certs(chia_root: &Path, service: &str) -> Vec<u8> {
+        let mut ca_params = rcgen::CertificateParams::new(vec![]);
+        ca_params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        let ca = rcgen::Certificate::from_params(ca_params).unwrap();
+
+        let cert = rcgen::Certificate::from_params(rcgen::CertificateParams::new(vec![String::from("chia.net")])).unwrap();
+
+        let ssl_dir = chia_root.join("config/ssl").join(service);
+        std::fs::create_dir_all(&ssl_dir).unwrap();
+        std::fs::write(ssl_dir.join(format!("private_{}.crt", service)), cert.serialize_pem_with_signer(&ca).unwrap()).unwrap();
+        std::fs::write(ssl_dir.join(format!("private_{}.key", service)), cert.serialize_private_key_pem()).unwrap();
+
+        ca.serialize_der().unwrap()
+    }
+
+    /// Serves `responses` keyed by endpoint over HTTPS, only accepting clients
+    /// presenting a certificate signed by `ca_der`. Returns the listening port
+    /// and the requests received so far.
+    pub async fn serve(ca_der: Vec<u8>, responses: HashMap<&'static str, Value>) -> (u16, RecordedRequests) {
+        let server_cert = rcgen::generate_simple_self_signed(vec![String::from("localhost")]).unwrap();
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(&rustls::Certificate(ca_der)).unwrap();
+        let tls_config = rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_client_cert_verifier(rustls::server::AllowAnyAuthenticatedClient::new(roots).boxed())
+            .with_single_cert(
+                vec![rustls::Certificate(server_cert.serialize_der().unwrap())],
+                rustls::PrivateKey(server_cert.serialize_private_key_der()),
+            )
+            .unwrap();
+        let acceptor = TlsAcceptor::from(Arc::new(tls_config));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let requests: RecordedRequests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = requests.clone();
+
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let mut stream = match acceptor.accept(stream).await {
+                    Ok(stream) => stream,
+                    Err(_) => continue,
+                };
+
+                let (endpoint, body) = read_request(&mut stream).await;
+                let response = responses.get(endpoint.as_str()).cloned()
+                    .unwrap_or(serde_json::json!({ "success": false, "error": "unknown endpoint" }))
+                    .to_string();
+                recorded.lock().unwrap().push((endpoint, body));
+
+                let http_response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    response.len(),
+                    response
+                );
+                stream.write_all(http_response.as_bytes()).await.unwrap();
+                stream.shutdown().await.ok();
+            }
+        });
+
+        (port, requests)
+    }
+
+    async fn read_request<S: AsyncReadExt + Unpin>(stream: &mut S) -> (String, Value) {
+        let mut buf = Vec::new();
+        let mut chunk = [0u8; 4096];
+        let header_end = loop {
+            let n = stream.read(&mut chunk).await.unwrap();
+            buf.extend_from_slice(&chunk[..n]);
+            if let Some(pos) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+                break pos + 4;
+            }
+        };
+
+        let headers = String::from_utf8_lossy(&buf[..header_end]).to_string();
+        let endpoint = headers.split_whitespace().nth(1).unwrap().trim_start_matches('/').to_string();
+        let content_length = headers.lines()
+            .find_map(|line| {
+                let (name, value) = line.split_once(':')?;
+                name.eq_ignore_ascii_case("content-length").then(|| value.trim().parse::<usize>().unwrap())
+            })
+            .unwrap_or(0);
+
+        while buf.len() < header_end + content_length {
+            let n = stream.read(&mut chunk).await.unwrap();
+            buf.extend_from_slice(&chunk[..n]);
+        }
+
+        (endpoint, serde_json::from_slice(&buf[header_end..]).unwrap_or(Value::Null))
+    }
+}
@@ -0,0 +1,173 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+
+use crate::price_provider::{PriceGranularity, PriceProvider};
+use crate::prices::{PriceError, PricePoint};
+
+#[derive(Debug, Deserialize)]
+struct HistoryResponse {
+    market_data: Option<MarketData>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MarketData {
+    current_price: HashMap<String, Decimal>,
+    market_cap: HashMap<String, Decimal>,
+    total_volume: HashMap<String, Decimal>,
+}
+
+/// `[unix milliseconds, value]` pairs
+#[derive(Debug, Deserialize)]
+struct MarketChartResponse {
+    prices: Vec<(i64, Decimal)>,
+    #[serde(default)]
+    market_caps: Vec<(i64, Decimal)>,
+    #[serde(default)]
+    total_volumes: Vec<(i64, Decimal)>,
+}
+
+/// Prices from the free CoinGecko api. The daily history endpoint has one day
+/// per request, so only the day of `start` is returned. Hourly prices come from
+/// the market chart, which is hourly for ranges of two days or more.
+pub struct CoinGecko;
+
+#[async_trait]
+impl PriceProvider for CoinGecko {
+    fn name(&self) -> &'static str {
+        "coingecko"
+    }
+
+    async fn get_current_price(&self, quote_currency: &str) -> Result<Decimal, PriceError> {
+        let url = format!("https://api.coingecko.com/api/v3/simple/price?ids=chia&vs_currencies={}", quote_currency);
+        let response: HashMap<String, HashMap<String, Decimal>> = reqwest::get(&url).await?.error_for_status()?.json().await?;
+        response.get("chia")
+            .and_then(|prices| prices.get(quote_currency))
+            .copied()
+            .ok_or_else(|| PriceError::UnsupportedQuoteCurrency(quote_currency.to_string()))
+    }
+
+    async fn get_historical_prices(
+        &self,
+        quote_currency: &str,
+        start: DateTime<Utc>,
+        granularity: PriceGranularity,
+    ) -> Result<Vec<PricePoint>, PriceError> {
+        match granularity {
+            PriceGranularity::Daily => {
+                let url = format!(
+                    "https://api.coingecko.com/api/v3/coins/chia/history?date={}&localization=false",
+                    start.format("%d-%m-%Y")
+                );
+                let response_text = reqwest::get(&url).await?.error_for_status()?.text().await?;
+                parse_history(&response_text, quote_currency, start, self.name())
+            }
+            PriceGranularity::Hourly => {
+                let url = format!(
+                    "https://api.coingecko.com/api/v3/coins/chia/market_chart/range?vs_currency={}&from={}&to={}",
+                    quote_currency,
+                    start.timestamp(),
+                    (start + Duration::days(2)).timestamp()
+                );
+                let response_text = reqwest::get(&url).await?.error_for_status()?.text().await?;
+                parse_market_chart(&response_text, self.name())
+            }
+        }
+    }
+}
+
+fn parse_history(response_text: &str, quote_currency: &str, day: DateTime<Utc>, source: &str) -> Result<Vec<PricePoint>, PriceError> {
+    let response: HistoryResponse = serde_json::from_str(response_text)?;
+    let Some(market_data) = response.market_data else {
+        // no trading data that day
+        return Ok(Vec::new());
+    };
+    let Some(price) = market_data.current_price.get(quote_currency).copied() else {
+        return Ok(Vec::new());
+    };
+
+    Ok(vec![PricePoint {
+        timestamp: Utc.from_utc_datetime(&day.date_naive().and_hms_opt(0, 0, 0).unwrap()),
+        price,
+        volume_24h: market_data.total_volume.get(quote_currency).copied(),
+        market_cap: market_data.market_cap.get(quote_currency).copied(),
+        source: source.to_string(),
+    }])
+}
+
+fn parse_market_chart(response_text: &str, source: &str) -> Result<Vec<PricePoint>, PriceError> {
+    let response: MarketChartResponse = serde_json::from_str(response_text)?;
+    let value_at = |values: &[(i64, Decimal)], timestamp: i64| {
+        values.iter().find(|(value_timestamp, _)| *value_timestamp == timestamp).map(|(_, value)| *value)
+    };
+
+    response.prices.iter()
+        .map(|(timestamp, price)| {
+            Ok(PricePoint {
+                timestamp: Utc.timestamp_millis_opt(*timestamp)
+                    .single()
+                    .ok_or_else(|| PriceError::Parse(format!("invalid timestamp {}", timestamp)))?,
+                price: *price,
+                volume_24h: value_at(&response.total_volumes, *timestamp),
+                market_cap: value_at(&response.market_caps, *timestamp),
+                source: source.to_string(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+    use crate::wallet_transactions::parse_created_at_time;
+
+    #[test]
+    fn test_parse_history() {
+        let response_text = r#"{
+            "id": "chia",
+            "market_data": {
+                "current_price": {"eur": 27.3, "usd": 29.61},
+                "market_cap": {"usd": 190000000},
+                "total_volume": {"usd": 1520000.5}
+            }
+        }"#;
+        let day = parse_created_at_time("2023-08-31T13:00:00Z").unwrap();
+
+        let price_points = parse_history(response_text, "usd", day, "coingecko").unwrap();
+        assert_eq!(price_points, vec![PricePoint {
+            timestamp: parse_created_at_time("2023-08-31T00:00:00Z").unwrap(),
+            price: Decimal::from_str("29.61").unwrap(),
+            volume_24h: Some(Decimal::from_str("1520000.5").unwrap()),
+            market_cap: Some(Decimal::from(190_000_000)),
+            source: String::from("coingecko"),
+        }]);
+
+        let price_points = parse_history(response_text, "eur", day, "coingecko").unwrap();
+        assert_eq!(price_points[0].price, Decimal::from_str("27.3").unwrap());
+        assert_eq!(price_points[0].market_cap, None);
+
+        assert_eq!(parse_history(r#"{"id": "chia"}"#, "usd", day, "coingecko").unwrap(), Vec::new());
+    }
+
+    #[test]
+    fn test_parse_market_chart() {
+        let response_text = r#"{
+            "prices": [[1693440000000, 29.61], [1693443612000, 29.8]],
+            "market_caps": [[1693440000000, 190000000]],
+            "total_volumes": [[1693440000000, 1520000.5], [1693443612000, 1530000]]
+        }"#;
+
+        let price_points = parse_market_chart(response_text, "coingecko").unwrap();
+        assert_eq!(price_points.len(), 2);
+        assert_eq!(price_points[0].timestamp, parse_created_at_time("2023-08-31T00:00:00Z").unwrap());
+        assert_eq!(price_points[0].market_cap, Some(Decimal::from(190_000_000)));
+        assert_eq!(price_points[1].timestamp, parse_created_at_time("2023-08-31T01:00:12Z").unwrap());
+        assert_eq!(price_points[1].price, Decimal::from_str("29.8").unwrap());
+        assert_eq!(price_points[1].market_cap, None);
+    }
+}
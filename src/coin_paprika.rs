@@ -0,0 +1,118 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+
+use crate::price_provider::{PriceGranularity, PriceProvider};
+use crate::prices::{PriceError, PricePoint};
+use crate::wallet_transactions::parse_created_at_time;
+
+#[derive(Debug, Deserialize)]
+struct PriceConverterResponse {
+    price: Decimal,
+}
+
+#[derive(Debug, Deserialize)]
+struct HistoricalResponse {
+    timestamp: String,
+    price: Decimal,
+    volume_24h: Option<Decimal>,
+    market_cap: Option<Decimal>,
+}
+
+/// Prices from the free CoinPaprika api, the history is daily at 00:00 UTC or
+/// hourly and only in usd
+pub struct CoinPaprika;
+
+/// CoinPaprika id of the fiat `quote_currency`
+fn quote_currency_id(quote_currency: &str) -> Option<&'static str> {
+    match quote_currency {
+        "usd" => Some("usd-us-dollars"),
+        "eur" => Some("eur-euro"),
+        "gbp" => Some("gbp-pound-sterling"),
+        "jpy" => Some("jpy-japanese-yen"),
+        "cad" => Some("cad-canadian-dollar"),
+        "aud" => Some("aud-australian-dollar"),
+        "chf" => Some("chf-swiss-franc"),
+        _ => None,
+    }
+}
+
+#[async_trait]
+impl PriceProvider for CoinPaprika {
+    fn name(&self) -> &'static str {
+        "coinpaprika"
+    }
+
+    async fn get_current_price(&self, quote_currency: &str) -> Result<Decimal, PriceError> {
+        let quote_currency_id = quote_currency_id(quote_currency)
+            .ok_or_else(|| PriceError::UnsupportedQuoteCurrency(quote_currency.to_string()))?;
+        let url = format!(
+            "https://api.coinpaprika.com/v1/price-converter?base_currency_id=xch-chia-&quote_currency_id={}&amount=1",
+            quote_currency_id
+        );
+        let response: PriceConverterResponse = reqwest::get(&url).await?.error_for_status()?.json().await?;
+        Ok(response.price)
+    }
+
+    async fn get_historical_prices(
+        &self,
+        quote_currency: &str,
+        start: DateTime<Utc>,
+        granularity: PriceGranularity,
+    ) -> Result<Vec<PricePoint>, PriceError> {
+        if quote_currency != "usd" {
+            return Err(PriceError::UnsupportedQuoteCurrency(quote_currency.to_string()));
+        }
+        let url = match granularity {
+            PriceGranularity::Daily => format!(
+                "https://api.coinpaprika.com/v1/tickers/xch-chia-/historical?interval=1d&start={}",
+                start.format("%Y-%m-%d")
+            ),
+            // a day of hourly candles is plenty for the transaction they're fetched for
+            PriceGranularity::Hourly => format!(
+                "https://api.coinpaprika.com/v1/tickers/xch-chia-/historical?interval=1h&start={}&end={}",
+                start.timestamp(),
+                (start + Duration::days(1)).timestamp()
+            ),
+        };
+        let response_text = reqwest::get(&url).await?.error_for_status()?.text().await?;
+        parse_history(&response_text, self.name())
+    }
+}
+
+fn parse_history(response_text: &str, source: &str) -> Result<Vec<PricePoint>, PriceError> {
+    serde_json::from_str::<Vec<HistoricalResponse>>(response_text)?
+        .into_iter()
+        .map(|item| {
+            Ok(PricePoint {
+                timestamp: parse_created_at_time(&item.timestamp).map_err(|e| PriceError::Parse(e.to_string()))?,
+                price: item.price,
+                volume_24h: item.volume_24h,
+                market_cap: item.market_cap,
+                source: source.to_string(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_history() {
+        let response_text = r#"[
+            {"timestamp": "2023-08-31T00:00:00Z", "price": 29.54, "volume_24h": 1520000.5, "market_cap": 190000000},
+            {"timestamp": "2023-09-01T00:00:00Z", "price": 30.1, "volume_24h": 1410000, "market_cap": 195000000}
+        ]"#;
+
+        let price_points = parse_history(response_text, "coinpaprika").unwrap();
+        assert_eq!(price_points.len(), 2);
+        assert_eq!(price_points[0].timestamp, parse_created_at_time("2023-08-31T00:00:00Z").unwrap());
+        assert_eq!(price_points[0].price, Decimal::from_str("29.54").unwrap());
+        assert_eq!(price_points[1].market_cap, Some(Decimal::from(195_000_000)));
+    }
+}
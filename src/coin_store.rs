@@ -0,0 +1,188 @@
+use std::collections::{BTreeMap, HashSet};
+
+use chia_observer_wallet_generator::derive_wallet::address_to_puzzle_hash;
+use rust_decimal::Decimal;
+
+use crate::wallet_transactions::{mojos_to_xch, Coin, WalletTransaction};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CoinState {
+    pub coin: Coin,
+    /// The transaction creating the coin is confirmed
+    pub confirmed: bool,
+    /// None while the coin isn't confirmed or when the source doesn't report
+    /// heights
+    pub created_height: Option<u32>,
+    /// Spent by a confirmed transaction
+    pub spent: bool,
+    pub spent_height: Option<u32>,
+    /// Spent by a pending transaction
+    pub pending_spend: bool,
+}
+
+impl CoinState {
+    fn new(coin: &Coin) -> Self {
+        Self {
+            coin: coin.clone(),
+            confirmed: false,
+            created_height: None,
+            spent: false,
+            spent_height: None,
+            pending_spend: false,
+        }
+    }
+
+    pub fn is_unspent(&self) -> bool {
+        !self.spent
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Balances {
+    /// Unspent coins confirmed on chain
+    pub confirmed: u64,
+    /// Confirmed coins not being spent by a pending transaction
+    pub spendable: u64,
+    /// Balance once the pending transactions are confirmed
+    pub pending: u64,
+}
+
+impl Balances {
+    pub fn chia_confirmed(&self) -> Decimal {
+        mojos_to_xch(self.confirmed)
+    }
+
+    pub fn chia_spendable(&self) -> Decimal {
+        mojos_to_xch(self.spendable)
+    }
+
+    pub fn chia_pending(&self) -> Decimal {
+        mojos_to_xch(self.pending)
+    }
+}
+
+/// The coins of our puzzle hashes, built from the additions and removals of
+/// the wallet transactions and keyed by coin id.
+#[derive(Debug, Default)]
+pub struct CoinSet {
+    coins: BTreeMap<[u8; 32], CoinState>,
+}
+
+impl CoinSet {
+    pub fn from_transactions(wallet_transactions: &[WalletTransaction], puzzle_hashes: &HashSet<[u8; 32]>) -> Self {
+        let mut coins: BTreeMap<[u8; 32], CoinState> = BTreeMap::new();
+
+        for wallet_transaction in wallet_transactions {
+            let confirmed = wallet_transaction.status.is_confirmed();
+            // a confirmed transaction without a height comes from a source that doesn't report it
+            let height = wallet_transaction.confirmed_at_height.filter(|_| confirmed);
+
+            for coin in wallet_transaction.additions.iter().filter(|coin| puzzle_hashes.contains(&coin.puzzle_hash)) {
+                let coin_state = coins.entry(coin.coin_id()).or_insert_with(|| CoinState::new(coin));
+                if confirmed && !coin_state.confirmed {
+                    coin_state.confirmed = true;
+                    coin_state.created_height = height;
+                }
+            }
+
+            for coin in wallet_transaction.removals.iter().filter(|coin| puzzle_hashes.contains(&coin.puzzle_hash)) {
+                let coin_state = coins.entry(coin.coin_id()).or_insert_with(|| CoinState::new(coin));
+                if confirmed {
+                    coin_state.spent = true;
+                    coin_state.spent_height = height;
+                    coin_state.pending_spend = false;
+                } else if coin_state.is_unspent() {
+                    coin_state.pending_spend = true;
+                }
+            }
+        }
+
+        Self { coins }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.coins.is_empty()
+    }
+
+    pub fn coins(&self) -> impl Iterator<Item = &CoinState> {
+        self.coins.values()
+    }
+
+    pub fn balances(&self) -> Balances {
+        let mut balances = Balances::default();
+        for coin_state in self.coins.values().filter(|coin_state| coin_state.is_unspent()) {
+            if coin_state.confirmed {
+                balances.confirmed += coin_state.coin.amount;
+                if !coin_state.pending_spend {
+                    balances.spendable += coin_state.coin.amount;
+                }
+            }
+            if !coin_state.pending_spend {
+                balances.pending += coin_state.coin.amount;
+            }
+        }
+
+        balances
+    }
+}
+
+/// Puzzle hashes of the wallet addresses, addresses that can't be decoded are
+/// left out.
+pub fn puzzle_hashes(wallet_addresses: &[String]) -> HashSet<[u8; 32]> {
+    wallet_addresses.iter()
+        .filter_map(|address| address_to_puzzle_hash(address).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wallet_transactions::tests::wallet_transaction;
+    use crate::wallet_transactions::{Flow, TxStatus};
+
+    fn coin_transaction(id: u8, status: TxStatus, height: Option<u32>, additions: Vec<Coin>, removals: Vec<Coin>) -> WalletTransaction {
+        WalletTransaction {
+            status,
+            confirmed_at_height: height,
+            additions,
+            removals,
+            ..wallet_transaction(id, Flow::Incoming, 0, "2023-08-31T00:00:00Z")
+        }
+    }
+
+    #[test]
+    fn test_balances() {
+        let ours = [1u8; 32];
+        let theirs = [2u8; 32];
+        let coin = |parent: u8, puzzle_hash: [u8; 32], amount: u64| Coin { parent_coin_info: [parent; 32], puzzle_hash, amount };
+
+        let received = coin(10, ours, 1_000);
+        let farmed = coin(11, ours, 250);
+        let change = coin(12, ours, 300);
+        let pending_received = coin(13, ours, 50);
+
+        let wallet_transactions = vec![
+            coin_transaction(1, TxStatus::Confirmed, Some(100), vec![received.clone()], vec![]),
+            coin_transaction(2, TxStatus::Confirmed, Some(110), vec![farmed.clone()], vec![]),
+            // 690 sent with a fee of 10, the change comes back to us
+            coin_transaction(3, TxStatus::Confirmed, Some(120), vec![coin(14, theirs, 690), change.clone()], vec![received]),
+            // the farmed coin is being spent
+            coin_transaction(4, TxStatus::Pending, None, vec![coin(15, theirs, 250)], vec![farmed]),
+            coin_transaction(5, TxStatus::Pending, None, vec![pending_received], vec![]),
+        ];
+
+        let coin_set = CoinSet::from_transactions(&wallet_transactions, &HashSet::from([ours]));
+        assert_eq!(coin_set.coins().count(), 4);
+        assert_eq!(coin_set.balances(), Balances { confirmed: 550, spendable: 300, pending: 350 });
+
+        let change_state = coin_set.coins().find(|coin_state| coin_state.coin == change).unwrap();
+        assert_eq!(change_state.created_height, Some(120));
+        assert!(change_state.is_unspent());
+
+        // a source without heights still confirms the coins
+        let coin_set = CoinSet::from_transactions(&[coin_transaction(6, TxStatus::Confirmed, None, vec![change], vec![])], &HashSet::from([ours]));
+        let change_state = coin_set.coins().next().unwrap();
+        assert_eq!((change_state.confirmed, change_state.created_height), (true, None));
+        assert_eq!(coin_set.balances().confirmed, 300);
+    }
+}
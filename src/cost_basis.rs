@@ -0,0 +1,483 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::wallet_transactions::{mojos_to_xch, Flow, TxStatus, WalletTransaction};
+
+/// Which lots a disposal is taken from
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CostBasisMethod {
+    /// First in, first out
+    Fifo,
+    /// Last in, first out
+    Lifo,
+    /// Highest cost first
+    Hifo,
+    /// Every lot at the average cost of the holdings
+    Average,
+}
+
+impl CostBasisMethod {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CostBasisMethod::Fifo => "fifo",
+            CostBasisMethod::Lifo => "lifo",
+            CostBasisMethod::Hifo => "hifo",
+            CostBasisMethod::Average => "average",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum CostBasisError {
+    /// A transaction without a price in the quote currency
+    MissingPrice { transaction: String, quote_currency: String },
+}
+
+impl fmt::Display for CostBasisError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CostBasisError::MissingPrice { transaction, quote_currency } => {
+                write!(f, "no {} price of transaction {}", quote_currency, transaction)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CostBasisError {}
+
+/// Xch acquired by an incoming transaction that isn't disposed of yet
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lot {
+    pub transaction: [u8; 32],
+    pub acquired_at: DateTime<Utc>,
+    /// Mojos left
+    pub amount: u64,
+    /// Cost of the mojos left
+    pub cost_basis: Decimal,
+}
+
+/// Part of a disposal and the lot it was taken from, a row of `tax_lots`
+#[derive(Debug, Clone, PartialEq)]
+pub struct LotAssignment {
+    pub disposal_transaction: [u8; 32],
+    pub disposed_at: DateTime<Utc>,
+    /// None for xch disposed of beyond the tracked acquisitions, it has no cost
+    pub acquisition_transaction: Option<[u8; 32]>,
+    pub acquired_at: Option<DateTime<Utc>>,
+    /// Amount in mojos
+    pub amount: u64,
+    pub cost_basis: Decimal,
+    pub proceeds: Decimal,
+}
+
+impl LotAssignment {
+    pub fn gain(&self) -> Decimal {
+        self.proceeds - self.cost_basis
+    }
+}
+
+/// Lot assignments of every disposal of a wallet and the lots still held
+#[derive(Debug, Clone, PartialEq)]
+pub struct CostBasis {
+    pub quote_currency: String,
+    pub method: CostBasisMethod,
+    pub assignments: Vec<LotAssignment>,
+    pub open_lots: Vec<Lot>,
+}
+
+impl CostBasis {
+    pub fn realized_gain(&self) -> Decimal {
+        self.assignments.iter().map(LotAssignment::gain).sum()
+    }
+
+    /// Gain of the lots still held if they were sold at `price`
+    pub fn unrealized_gain(&self, price: Decimal) -> Decimal {
+        self.open_lots.iter().map(|lot| mojos_to_xch(lot.amount) * price - lot.cost_basis).sum()
+    }
+}
+
+/// Xch moved by a transaction from one tracked wallet to another, the
+/// transaction is stored for both of them
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transfer {
+    /// Index of the sending wallet
+    pub from: usize,
+    /// Index of the receiving wallet
+    pub to: usize,
+    /// Mojos sent, without the fee
+    pub sent: u64,
+    /// Mojos received
+    pub received: u64,
+    /// Fee in mojos
+    pub fee: u64,
+}
+
+/// Confirmed transactions outgoing from one of the wallets and incoming to
+/// another one, by transaction
+pub fn find_transfers(wallets_transactions: &[&[WalletTransaction]]) -> HashMap<[u8; 32], Transfer> {
+    let mut incoming: HashMap<[u8; 32], (usize, u64)> = HashMap::new();
+    for (index, wallet_transactions) in wallets_transactions.iter().enumerate() {
+        for wallet_transaction in confirmed_with_flow(wallet_transactions, Flow::Incoming) {
+            incoming.entry(wallet_transaction.transaction).or_insert((index, wallet_transaction.amount));
+        }
+    }
+
+    let mut transfers = HashMap::new();
+    for (index, wallet_transactions) in wallets_transactions.iter().enumerate() {
+        for wallet_transaction in confirmed_with_flow(wallet_transactions, Flow::Outgoing) {
+            if let Some(&(to, received)) = incoming.get(&wallet_transaction.transaction).filter(|(to, _)| *to != index) {
+                transfers.insert(wallet_transaction.transaction, Transfer {
+                    from: index,
+                    to,
+                    sent: wallet_transaction.amount,
+                    received,
+                    fee: wallet_transaction.fee_amount,
+                });
+            }
+        }
+    }
+
+    transfers
+}
+
+fn confirmed_with_flow(wallet_transactions: &[WalletTransaction], flow: Flow) -> impl Iterator<Item = &WalletTransaction> {
+    wallet_transactions.iter()
+        .filter(move |wallet_transaction| wallet_transaction.status == TxStatus::Confirmed && wallet_transaction.flow == flow)
+}
+
+/// Matches every disposal of the confirmed transactions of each wallet to
+/// the lots of the earlier incoming ones with `method`, valued in
+/// `quote_currency`, in the order of `wallets_transactions`. Outgoing
+/// transactions dispose of their amount and fee, the fee has no proceeds.
+/// Internal transactions only dispose of their fee. Every wallet has its own
+/// lots, xch moved to another of the wallets isn't disposed of, its lots move
+/// along with their acquisition time and cost. The part of a transfer beyond
+/// the lots of the sender arrives as a lot without cost.
+pub fn calculate_cost_bases(
+    wallets_transactions: &[&[WalletTransaction]],
+    quote_currency: &str,
+    method: CostBasisMethod,
+) -> Result<Vec<CostBasis>, CostBasisError> {
+    let transfers = find_transfers(wallets_transactions);
+    let mut confirmed: Vec<(usize, &WalletTransaction)> = wallets_transactions.iter().enumerate()
+        .flat_map(|(index, wallet_transactions)| wallet_transactions.iter().map(move |wallet_transaction| (index, wallet_transaction)))
+        .filter(|(_, wallet_transaction)| wallet_transaction.status == TxStatus::Confirmed)
+        .collect();
+    // acquisitions first when they happened at the same time
+    confirmed.sort_by_key(|(_, wallet_transaction)| (wallet_transaction.created_at_time, wallet_transaction.flow != Flow::Incoming));
+
+    let price = |wallet_transaction: &WalletTransaction| {
+        wallet_transaction.fiat_at_time.get(quote_currency).copied().ok_or_else(|| CostBasisError::MissingPrice {
+            transaction: wallet_transaction.transaction_id(),
+            quote_currency: quote_currency.to_string(),
+        })
+    };
+
+    let mut open_lots: Vec<Vec<Lot>> = vec![Vec::new(); wallets_transactions.len()];
+    let mut assignments: Vec<Vec<LotAssignment>> = vec![Vec::new(); wallets_transactions.len()];
+    for (index, wallet_transaction) in confirmed {
+        let transfer = transfers.get(&wallet_transaction.transaction);
+        let (disposed, proceeds) = match wallet_transaction.flow {
+            // the lots arrive with the sender's side of the transfer
+            Flow::Incoming if transfer.is_some_and(|transfer| transfer.to == index) => continue,
+            Flow::Incoming => {
+                open_lots[index].push(Lot {
+                    transaction: wallet_transaction.transaction,
+                    acquired_at: wallet_transaction.created_at_time,
+                    amount: wallet_transaction.amount,
+                    cost_basis: wallet_transaction.chia_amount() * price(wallet_transaction)?,
+                });
+                continue;
+            }
+            Flow::Outgoing => match transfer.filter(|transfer| transfer.from == index) {
+                Some(transfer) => {
+                    let moved = transfer.sent.min(transfer.received);
+                    let (mut lots, beyond) = take_lots(&mut open_lots[index], moved, method);
+                    let unknown_lot = |amount: u64, cost_basis: Decimal| Lot {
+                        transaction: wallet_transaction.transaction,
+                        acquired_at: wallet_transaction.created_at_time,
+                        amount,
+                        cost_basis,
+                    };
+                    if beyond > 0 {
+                        lots.push(unknown_lot(beyond, Decimal::ZERO));
+                    }
+                    if transfer.received > moved {
+                        let extra = transfer.received - moved;
+                        lots.push(unknown_lot(extra, mojos_to_xch(extra) * price(wallet_transaction)?));
+                    }
+                    for lot in lots {
+                        receive_lot(&mut open_lots[transfer.to], lot);
+                    }
+
+                    // the part that didn't reach the other wallet is disposed of like any payment
+                    let not_received = transfer.sent - moved;
+                    (not_received + transfer.fee, mojos_to_xch(not_received) * price(wallet_transaction)?)
+                }
+                None => (
+                    wallet_transaction.amount + wallet_transaction.fee_amount,
+                    wallet_transaction.chia_amount() * price(wallet_transaction)?,
+                ),
+            },
+            Flow::Internal => (wallet_transaction.fee_amount, Decimal::ZERO),
+        };
+        if disposed == 0 {
+            continue;
+        }
+
+        assignments[index].extend(dispose(&mut open_lots[index], wallet_transaction, disposed, proceeds, method));
+    }
+
+    Ok(assignments.into_iter().zip(open_lots)
+        .map(|(assignments, open_lots)| CostBasis {
+            quote_currency: quote_currency.to_string(),
+            method,
+            assignments,
+            open_lots,
+        })
+        .collect())
+}
+
+/// Assigns `disposed` mojos of a disposal to the lots they're taken from,
+/// each part gets its share of the proceeds
+fn dispose(
+    open_lots: &mut Vec<Lot>,
+    wallet_transaction: &WalletTransaction,
+    disposed: u64,
+    proceeds: Decimal,
+    method: CostBasisMethod,
+) -> Vec<LotAssignment> {
+    let (lots, beyond) = take_lots(open_lots, disposed, method);
+
+    let mut assignments = Vec::new();
+    let mut remaining = disposed;
+    let mut remaining_proceeds = proceeds;
+    for lot in lots {
+        let part_proceeds = if lot.amount == remaining { remaining_proceeds } else { share(proceeds, lot.amount, disposed) };
+        assignments.push(LotAssignment {
+            disposal_transaction: wallet_transaction.transaction,
+            disposed_at: wallet_transaction.created_at_time,
+            acquisition_transaction: Some(lot.transaction),
+            acquired_at: Some(lot.acquired_at),
+            amount: lot.amount,
+            cost_basis: lot.cost_basis,
+            proceeds: part_proceeds,
+        });
+        remaining -= lot.amount;
+        remaining_proceeds -= part_proceeds;
+    }
+    if beyond > 0 {
+        assignments.push(LotAssignment {
+            disposal_transaction: wallet_transaction.transaction,
+            disposed_at: wallet_transaction.created_at_time,
+            acquisition_transaction: None,
+            acquired_at: None,
+            amount: beyond,
+            cost_basis: Decimal::ZERO,
+            proceeds: remaining_proceeds,
+        });
+    }
+
+    assignments
+}
+
+/// Takes `amount` mojos from the lots with `method`, returns the parts of
+/// the lots taken and the mojos beyond them
+fn take_lots(open_lots: &mut Vec<Lot>, amount: u64, method: CostBasisMethod) -> (Vec<Lot>, u64) {
+    if method == CostBasisMethod::Average {
+        pool_lots(open_lots);
+    }
+
+    let mut taken = Vec::new();
+    let mut remaining = amount;
+    while remaining > 0 {
+        let Some(index) = next_lot(open_lots, method) else {
+            break;
+        };
+
+        let lot = &mut open_lots[index];
+        let part = remaining.min(lot.amount);
+        let cost_basis = if part == lot.amount { lot.cost_basis } else { share(lot.cost_basis, part, lot.amount) };
+        taken.push(Lot { amount: part, cost_basis, ..lot.clone() });
+
+        lot.amount -= part;
+        lot.cost_basis -= cost_basis;
+        if lot.amount == 0 {
+            open_lots.remove(index);
+        }
+        remaining -= part;
+    }
+
+    (taken, remaining)
+}
+
+/// Adds a lot moved from another wallet to the lots, which stay in the order
+/// they were acquired
+fn receive_lot(open_lots: &mut Vec<Lot>, lot: Lot) {
+    let index = open_lots.partition_point(|open_lot| open_lot.acquired_at <= lot.acquired_at);
+    open_lots.insert(index, lot);
+}
+
+/// Index of the lot the next disposal is taken from
+fn next_lot(open_lots: &[Lot], method: CostBasisMethod) -> Option<usize> {
+    if open_lots.is_empty() {
+        return None;
+    }
+
+    match method {
+        CostBasisMethod::Fifo | CostBasisMethod::Average => Some(0),
+        CostBasisMethod::Lifo => Some(open_lots.len() - 1),
+        CostBasisMethod::Hifo => {
+            let mut highest = 0;
+            for (index, lot) in open_lots.iter().enumerate().skip(1) {
+                // compares the cost per mojo without dividing, the earlier lot wins a tie
+                let highest_lot = &open_lots[highest];
+                if lot.cost_basis * Decimal::from(highest_lot.amount) > highest_lot.cost_basis * Decimal::from(lot.amount) {
+                    highest = index;
+                }
+            }
+            Some(highest)
+        }
+    }
+}
+
+/// Spreads the cost of the holdings evenly over the lots, so every mojo has
+/// the average cost
+fn pool_lots(open_lots: &mut [Lot]) {
+    let total_amount: u64 = open_lots.iter().map(|lot| lot.amount).sum();
+    let mut remaining_cost: Decimal = open_lots.iter().map(|lot| lot.cost_basis).sum();
+    let mut remaining_amount = total_amount;
+    for lot in open_lots.iter_mut() {
+        lot.cost_basis = if lot.amount == remaining_amount { remaining_cost } else { share(remaining_cost, lot.amount, remaining_amount) };
+        remaining_cost -= lot.cost_basis;
+        remaining_amount -= lot.amount;
+    }
+}
+
+/// `part` mojos of `total` mojos worth `value`
+fn share(value: Decimal, part: u64, total: u64) -> Decimal {
+    value * Decimal::from(part) / Decimal::from(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::str::FromStr;
+
+    use super::*;
+    use crate::wallet_transactions::tests::wallet_transaction;
+
+    fn calculate_cost_basis(wallet_transactions: &[WalletTransaction], quote_currency: &str, method: CostBasisMethod) -> Result<CostBasis, CostBasisError> {
+        calculate_cost_bases(&[wallet_transactions], quote_currency, method).map(|mut cost_bases| cost_bases.remove(0))
+    }
+
+    fn priced_transaction(id: u8, flow: Flow, xch: u64, price: &str, created_at_time: &str) -> WalletTransaction {
+        WalletTransaction {
+            fiat_at_time: BTreeMap::from([(String::from("usd"), Decimal::from_str(price).unwrap())]),
+            ..wallet_transaction(id, flow, xch * 1_000_000_000_000, created_at_time)
+        }
+    }
+
+    fn wallet_transactions() -> Vec<WalletTransaction> {
+        vec![
+            priced_transaction(1, Flow::Incoming, 2, "10", "2023-01-01T00:00:00Z"),
+            priced_transaction(2, Flow::Incoming, 2, "30", "2023-02-01T00:00:00Z"),
+            priced_transaction(3, Flow::Incoming, 2, "20", "2023-03-01T00:00:00Z"),
+            priced_transaction(4, Flow::Outgoing, 3, "40", "2023-04-01T00:00:00Z"),
+        ]
+    }
+
+    /// Acquisition transactions, mojos and cost basis of the assignments
+    fn assignments(cost_basis: &CostBasis) -> Vec<(u8, u64, Decimal)> {
+        cost_basis.assignments.iter()
+            .map(|assignment| (assignment.acquisition_transaction.unwrap()[0], assignment.amount, assignment.cost_basis))
+            .collect()
+    }
+
+    #[test]
+    fn test_lot_methods() {
+        let xch = 1_000_000_000_000;
+
+        let fifo = calculate_cost_basis(&wallet_transactions(), "usd", CostBasisMethod::Fifo).unwrap();
+        assert_eq!(assignments(&fifo), vec![(1, 2 * xch, Decimal::from(20)), (2, xch, Decimal::from(30))]);
+        assert_eq!(fifo.realized_gain(), Decimal::from(70));
+
+        let lifo = calculate_cost_basis(&wallet_transactions(), "usd", CostBasisMethod::Lifo).unwrap();
+        assert_eq!(assignments(&lifo), vec![(3, 2 * xch, Decimal::from(40)), (2, xch, Decimal::from(30))]);
+        assert_eq!(lifo.realized_gain(), Decimal::from(50));
+
+        let hifo = calculate_cost_basis(&wallet_transactions(), "usd", CostBasisMethod::Hifo).unwrap();
+        assert_eq!(assignments(&hifo), vec![(2, 2 * xch, Decimal::from(60)), (3, xch, Decimal::from(20))]);
+        assert_eq!(hifo.realized_gain(), Decimal::from(40));
+        assert_eq!(hifo.open_lots.iter().map(|lot| (lot.transaction[0], lot.amount)).collect::<Vec<_>>(), vec![(1, 2 * xch), (3, xch)]);
+        // 3 xch left at 50 usd, bought for 40 usd
+        assert_eq!(hifo.unrealized_gain(Decimal::from(50)), Decimal::from(110));
+
+        let average = calculate_cost_basis(&wallet_transactions(), "usd", CostBasisMethod::Average).unwrap();
+        assert_eq!(average.assignments.iter().map(|assignment| assignment.cost_basis).sum::<Decimal>(), Decimal::from(60));
+        assert_eq!(average.realized_gain(), Decimal::from(60));
+        assert_eq!(average.open_lots.iter().map(|lot| lot.cost_basis).sum::<Decimal>(), Decimal::from(60));
+    }
+
+    #[test]
+    fn test_disposal_beyond_holdings() {
+        let mut wallet_transactions = vec![
+            priced_transaction(1, Flow::Incoming, 1, "10", "2023-01-01T00:00:00Z"),
+            priced_transaction(2, Flow::Outgoing, 2, "40", "2023-04-01T00:00:00Z"),
+            priced_transaction(3, Flow::Incoming, 5, "20", "2023-05-01T00:00:00Z"),
+        ];
+        wallet_transactions[1].fee_amount = 1_000_000;
+        // not confirmed, doesn't count
+        wallet_transactions[2].status = TxStatus::Pending;
+
+        let cost_basis = calculate_cost_basis(&wallet_transactions, "usd", CostBasisMethod::Fifo).unwrap();
+        assert_eq!(cost_basis.assignments.len(), 2);
+        assert_eq!(cost_basis.assignments.iter().map(|assignment| assignment.proceeds).sum::<Decimal>(), Decimal::from(80));
+        assert_eq!(cost_basis.assignments[1].acquisition_transaction, None);
+        assert_eq!(cost_basis.assignments[1].amount, 1_000_001_000_000);
+        assert_eq!(cost_basis.assignments[1].cost_basis, Decimal::ZERO);
+        // the fee has no proceeds
+        assert_eq!(cost_basis.realized_gain(), Decimal::from(70));
+        assert!(cost_basis.open_lots.is_empty());
+
+        let result = calculate_cost_basis(&wallet_transactions, "eur", CostBasisMethod::Fifo);
+        assert!(matches!(result, Err(CostBasisError::MissingPrice { quote_currency, .. }) if quote_currency == "eur"));
+    }
+
+    #[test]
+    fn test_transfer_between_wallets() {
+        let xch = 1_000_000_000_000;
+        let mut sender = vec![
+            priced_transaction(1, Flow::Incoming, 2, "10", "2023-01-01T00:00:00Z"),
+            priced_transaction(2, Flow::Outgoing, 1, "40", "2023-04-01T00:00:00Z"),
+        ];
+        sender[1].fee_amount = 1_000_000;
+        let receiver = vec![
+            priced_transaction(3, Flow::Incoming, 1, "20", "2023-03-01T00:00:00Z"),
+            priced_transaction(2, Flow::Incoming, 1, "40", "2023-04-01T00:00:00Z"),
+            priced_transaction(4, Flow::Outgoing, 1, "50", "2023-05-01T00:00:00Z"),
+        ];
+
+        let transfers = find_transfers(&[&sender, &receiver]);
+        assert_eq!(transfers.get(&[2u8; 32]), Some(&Transfer { from: 0, to: 1, sent: xch, received: xch, fee: 1_000_000 }));
+
+        let cost_bases = calculate_cost_bases(&[&sender, &receiver], "usd", CostBasisMethod::Fifo).unwrap();
+        // only the fee is disposed of by the sender
+        assert_eq!(assignments(&cost_bases[0]), vec![(1, 1_000_000, Decimal::from_str("0.00001").unwrap())]);
+        assert_eq!(cost_bases[0].assignments[0].proceeds, Decimal::ZERO);
+        // the lot arrives with its acquisition time and cost, before the receiver's own lot
+        assert_eq!(assignments(&cost_bases[1]), vec![(1, xch, Decimal::from(10))]);
+        assert_eq!(cost_bases[1].assignments[0].acquired_at, Some(DateTime::from_str("2023-01-01T00:00:00Z").unwrap()));
+        assert_eq!(cost_bases[1].realized_gain(), Decimal::from(40));
+        assert_eq!(cost_bases[1].open_lots.iter().map(|lot| (lot.transaction[0], lot.amount)).collect::<Vec<_>>(), vec![(3, xch)]);
+
+        // tracked alone the sender sells at 40
+        let cost_basis = calculate_cost_basis(&sender, "usd", CostBasisMethod::Fifo).unwrap();
+        assert_eq!(cost_basis.realized_gain(), Decimal::from_str("29.99999").unwrap());
+    }
+}
@@ -0,0 +1,290 @@
+use std::collections::{hash_map::Entry, BTreeMap, HashMap, HashSet};
+use std::path::Path;
+
+use async_trait::async_trait;
+use chia_observer_wallet_generator::derive_wallet::{address_to_puzzle_hash, puzzle_hash_to_address};
+use serde::Deserialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+use crate::block_rewards::{self, RewardCoin};
+use crate::chia_rpc::{ChiaRpcClient, RpcError};
+use crate::transaction_source::{SourceError, TransactionSource};
+use crate::wallet_transactions::{
+    local_time_string, mojos_to_chia_amount, Coin, RawCoin, RawWalletTransaction, TransactionType,
+};
+use crate::Config;
+
+#[derive(Debug, Deserialize)]
+struct GetCoinRecordsResponse {
+    coin_records: Vec<CoinRecord>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CoinRecord {
+    coin: RawCoin,
+    confirmed_block_index: u32,
+    spent_block_index: u32,
+    coinbase: bool,
+    timestamp: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct GetBlockRecordResponse {
+    block_record: BlockRecord,
+}
+
+#[derive(Debug, Deserialize)]
+struct BlockRecord {
+    timestamp: Option<i64>,
+}
+
+/// Builds the wallet transactions from the coin records of the derived puzzle
+/// hashes, so a stock full node can be used instead of a wallet with the
+/// master public key loaded.
+///
+/// Every coin created for one of the puzzle hashes is an incoming transaction
+/// named by its coin id, and all the coins spent in the same block make up one
+/// outgoing transaction named by the hash of the spent coin ids. Coins created
+/// by spending our own coins are change or self transfers, so they are sent to
+/// ourselves as an outgoing transaction to our address and are taken off the
+/// amount of the spend. Coinbase coins are farming rewards and keep the
+/// COINBASE_REWARD or FEE_REWARD type of the wallet.
+pub struct FullNodeRpcClient {
+    rpc: ChiaRpcClient,
+}
+
+impl FullNodeRpcClient {
+    pub fn new(config: &Config) -> Result<Self, RpcError> {
+        let ssl_dir = Path::new(&config.chia_root).join("config/ssl/full_node");
+        let rpc = ChiaRpcClient::new(
+            &config.full_node_rpc_host,
+            config.full_node_rpc_port,
+            &ssl_dir.join("private_full_node.crt"),
+            &ssl_dir.join("private_full_node.key"),
+        )?;
+
+        Ok(Self { rpc })
+    }
+
+    pub async fn get_wallet_transactions(&self, wallet_addresses: &[String]) -> Result<Vec<RawWalletTransaction>, RpcError> {
+        let puzzle_hashes = wallet_addresses.iter()
+            .map(|address| {
+                address_to_puzzle_hash(address)
+                    .map(|puzzle_hash| format!("0x{}", hex::encode(puzzle_hash)))
+                    .map_err(|e| RpcError::Rpc(format!("invalid wallet address {}: {}", address, e)))
+            })
+            .collect::<Result<Vec<String>, RpcError>>()?;
+
+        let response: GetCoinRecordsResponse = self.rpc
+            .request("get_coin_records_by_puzzle_hashes", json!({
+                "puzzle_hashes": puzzle_hashes,
+                "include_spent_coins": true,
+            }))
+            .await?;
+
+        let mut coins: Vec<(CoinRecord, Coin)> = Vec::new();
+        for coin_record in response.coin_records {
+            let coin = Coin::from_raw(&coin_record.coin).map_err(|e| RpcError::Rpc(e.to_string()))?;
+            coins.push((coin_record, coin));
+        }
+
+        let spent_coin_ids: HashSet<[u8; 32]> = coins.iter()
+            .filter(|(coin_record, _)| coin_record.spent_block_index > 0)
+            .map(|(_, coin)| coin.coin_id())
+            .collect();
+
+        let mut raw_transactions: Vec<RawWalletTransaction> = Vec::new();
+        let mut spent_coins: BTreeMap<u32, Vec<([u8; 32], &RawCoin)>> = BTreeMap::new();
+        let mut change_coins: HashMap<u32, Vec<&RawCoin>> = HashMap::new();
+
+        for (coin_record, coin) in &coins {
+            let is_change = spent_coin_ids.contains(&coin.parent_coin_info);
+            if is_change {
+                change_coins.entry(coin_record.confirmed_block_index).or_default().push(&coin_record.coin);
+            }
+
+            let transaction_type = match block_rewards::reward_coin(&coin.parent_coin_info) {
+                Some((RewardCoin::Pool, _)) if coin_record.coinbase => TransactionType::CoinbaseReward,
+                _ if coin_record.coinbase => TransactionType::FeeReward,
+                _ if is_change => TransactionType::OutgoingTx,
+                _ => TransactionType::IncomingTx,
+            };
+            raw_transactions.push(RawWalletTransaction {
+                transaction: Some(format!("0x{}", hex::encode(coin.coin_id()))),
+                status: Some(true),
+                chia_amount: Some(mojos_to_chia_amount(coin.amount)),
+                to_address: Some(puzzle_hash_to_address(&coin.puzzle_hash)),
+                created_at_time: local_time_string(coin_record.timestamp),
+                transaction_type: Some(transaction_type.as_u32()),
+                confirmed_at_height: Some(coin_record.confirmed_block_index),
+                additions: Some(vec![coin_record.coin.clone()]),
+                ..Default::default()
+            });
+
+            if coin_record.spent_block_index > 0 {
+                spent_coins.entry(coin_record.spent_block_index)
+                    .or_default()
+                    .push((coin.coin_id(), &coin_record.coin));
+            }
+        }
+
+        let mut block_timestamps: HashMap<u32, Option<i64>> = HashMap::new();
+        for (height, mut coins) in spent_coins {
+            let spent_amount: u64 = coins.iter().map(|(_, coin)| coin.amount).sum();
+            let change = change_coins.remove(&height).unwrap_or_default();
+            let change_amount: u64 = change.iter().map(|coin| coin.amount).sum();
+            let sent_amount = spent_amount.saturating_sub(change_amount);
+            if sent_amount == 0 {
+                continue;
+            }
+
+            if let Entry::Vacant(entry) = block_timestamps.entry(height) {
+                let response: GetBlockRecordResponse = self.rpc
+                    .request("get_block_record_by_height", json!({ "height": height }))
+                    .await?;
+                entry.insert(response.block_record.timestamp);
+            }
+
+            coins.sort_by_key(|(coin_id, _)| *coin_id);
+            let mut hasher = Sha256::new();
+            for (coin_id, _) in &coins {
+                hasher.update(coin_id);
+            }
+            let spend_name: [u8; 32] = hasher.finalize().into();
+
+            raw_transactions.push(RawWalletTransaction {
+                transaction: Some(format!("0x{}", hex::encode(spend_name))),
+                status: Some(true),
+                chia_amount: Some(mojos_to_chia_amount(sent_amount)),
+                to_address: None,
+                created_at_time: block_timestamps[&height].and_then(local_time_string),
+                transaction_type: Some(TransactionType::OutgoingTx.as_u32()),
+                confirmed_at_height: Some(height),
+                additions: Some(change.into_iter().cloned().collect()),
+                removals: Some(coins.into_iter().map(|(_, coin)| coin.clone()).collect()),
+                ..Default::default()
+            });
+        }
+
+        Ok(raw_transactions)
+    }
+}
+
+#[async_trait]
+impl TransactionSource for FullNodeRpcClient {
+    async fn get_wallet_transactions(&self, wallet_addresses: &[String]) -> Result<Vec<RawWalletTransaction>, SourceError> {
+        Ok(FullNodeRpcClient::get_wallet_transactions(self, wallet_addresses).await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::chia_rpc::test_server;
+
+    #[tokio::test]
+    async fn test_get_wallet_transactions() {
+        let chia_root = std::env::temp_dir().join("full_node_rpc_test");
+        let ca_der = test_server::write_private_certs(&chia_root, "full_node");
+
+        let puzzle_hash = [1u8; 32];
+        let parent = [2u8; 32];
+        let spent_coin_id = Coin { parent_coin_info: parent, puzzle_hash, amount: 1750000000000 }.coin_id();
+        let responses = HashMap::from([
+            ("get_coin_records_by_puzzle_hashes", json!({
+                "success": true,
+                "coin_records": [
+                    {
+                        "coin": {
+                            "parent_coin_info": format!("0x{}", hex::encode(parent)),
+                            "puzzle_hash": format!("0x{}", hex::encode(puzzle_hash)),
+                            "amount": 1750000000000u64,
+                        },
+                        "confirmed_block_index": 100,
+                        "spent_block_index": 200,
+                        "spent": true,
+                        "coinbase": false,
+                        "timestamp": 1700000000,
+                    },
+                    {
+                        "coin": {
+                            "parent_coin_info": format!("0x{}", hex::encode(parent)),
+                            "puzzle_hash": format!("0x{}", hex::encode(puzzle_hash)),
+                            "amount": 250000000000u64,
+                        },
+                        "confirmed_block_index": 150,
+                        "spent_block_index": 0,
+                        "spent": false,
+                        "coinbase": false,
+                        "timestamp": 1700001000,
+                    },
+                    {
+                        "coin": {
+                            "parent_coin_info": format!("0x{}", hex::encode(spent_coin_id)),
+                            "puzzle_hash": format!("0x{}", hex::encode(puzzle_hash)),
+                            "amount": 500000000000u64,
+                        },
+                        "confirmed_block_index": 200,
+                        "spent_block_index": 0,
+                        "spent": false,
+                        "coinbase": false,
+                        "timestamp": 1700002000,
+                    },
+                    {
+                        "coin": {
+                            "parent_coin_info": "0x3ff07eb358e8255a65c30a2dce0e5fbb000000000000000000000000000000fa",
+                            "puzzle_hash": format!("0x{}", hex::encode(puzzle_hash)),
+                            "amount": 250000000000u64,
+                        },
+                        "confirmed_block_index": 251,
+                        "spent_block_index": 0,
+                        "spent": false,
+                        "coinbase": true,
+                        "timestamp": 1700003000,
+                    },
+                ],
+            })),
+            ("get_block_record_by_height", json!({
+                "success": true,
+                "block_record": { "height": 200, "timestamp": 1700002000 },
+            })),
+        ]);
+        let (port, requests) = test_server::serve(ca_der, responses).await;
+
+        let mut config = Config::default();
+        config.chia_root = chia_root.display().to_string();
+        config.full_node_rpc_host = String::from("127.0.0.1");
+        config.full_node_rpc_port = port;
+
+        let address = puzzle_hash_to_address(&puzzle_hash);
+        let client = FullNodeRpcClient::new(&config).unwrap();
+        let raw_w_txs = client.get_wallet_transactions(std::slice::from_ref(&address)).await.unwrap();
+
+        assert_eq!(raw_w_txs.len(), 5);
+        assert_eq!(raw_w_txs[0].transaction, Some(format!("0x{}", hex::encode(spent_coin_id))));
+        assert_eq!(raw_w_txs[0].chia_amount.as_deref(), Some("1.75"));
+        assert_eq!(raw_w_txs[0].to_address, Some(address.clone()));
+        assert_eq!(raw_w_txs[0].transaction_type, Some(TransactionType::IncomingTx.as_u32()));
+        assert_eq!(raw_w_txs[1].chia_amount.as_deref(), Some("0.25"));
+        // change of the spend at height 200
+        assert_eq!(raw_w_txs[2].chia_amount.as_deref(), Some("0.5"));
+        assert_eq!(raw_w_txs[2].transaction_type, Some(TransactionType::OutgoingTx.as_u32()));
+        assert_eq!(raw_w_txs[2].to_address, Some(address.clone()));
+        // farmer reward of block 250
+        assert_eq!(raw_w_txs[3].transaction_type, Some(TransactionType::FeeReward.as_u32()));
+        // the spend without the change
+        assert_eq!(raw_w_txs[4].chia_amount.as_deref(), Some("1.25"));
+        assert_eq!(raw_w_txs[4].to_address, None);
+        assert_eq!(raw_w_txs[4].created_at_time, local_time_string(1700002000));
+        assert_eq!(raw_w_txs[4].confirmed_at_height, Some(200));
+        assert_eq!(raw_w_txs[4].removals.as_ref().unwrap().len(), 1);
+        assert_eq!(raw_w_txs[4].additions.as_ref().unwrap()[0].amount, 500000000000);
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests[0].1["puzzle_hashes"], json!([format!("0x{}", hex::encode(puzzle_hash))]));
+        assert_eq!(requests[1].1["height"], json!(200));
+    }
+}
@@ -0,0 +1,232 @@
+use std::collections::BTreeSet;
+
+use rust_decimal::Decimal;
+
+use crate::wallet_transactions::{Flow, TxStatus, WalletTransaction};
+
+/// Plain text accounting format of `export journal`
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum JournalFormat {
+    Ledger,
+    Hledger,
+    Beancount,
+}
+
+impl JournalFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JournalFormat::Ledger => "ledger",
+            JournalFormat::Hledger => "hledger",
+            JournalFormat::Beancount => "beancount",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            JournalFormat::Ledger => "ledger",
+            JournalFormat::Hledger => "journal",
+            JournalFormat::Beancount => "beancount",
+        }
+    }
+}
+
+/// Counterpart accounts of the wallet postings
+#[derive(Debug, Clone, PartialEq)]
+pub struct JournalAccounts {
+    pub farming_income: String,
+    pub receipts: String,
+    pub payments: String,
+    pub fees: String,
+}
+
+/// A transaction and the account of the wallet it belongs to
+pub struct JournalEntry<'a> {
+    pub wallet_account: &'a str,
+    pub wallet_transaction: &'a WalletTransaction,
+}
+
+/// Journal of the confirmed transactions in time order. Every transaction
+/// carries its id as metadata and its xch price in `quote_currency` as the
+/// price of the postings, fees are posted to their own expense account.
+/// Internal transactions are only journaled when they paid a fee.
+pub fn journal(entries: &[JournalEntry], accounts: &JournalAccounts, quote_currency: &str, format: JournalFormat) -> String {
+    let mut entries: Vec<&JournalEntry> = entries.iter()
+        .filter(|entry| entry.wallet_transaction.status == TxStatus::Confirmed)
+        .filter(|entry| entry.wallet_transaction.flow != Flow::Internal || entry.wallet_transaction.fee_amount > 0)
+        .collect();
+    // ties are ordered by id, so exporting again gives the same file
+    entries.sort_by_key(|entry| (entry.wallet_transaction.created_at_time, entry.wallet_transaction.transaction, entry.wallet_account));
+
+    let commodity = quote_currency.to_uppercase();
+    let mut journal = String::new();
+    if format == JournalFormat::Beancount {
+        // beancount needs every account opened before it's used
+        if let Some(first_entry) = entries.first() {
+            let opened_at = first_entry.wallet_transaction.created_at_time.format("%Y-%m-%d");
+            let mut account_names: BTreeSet<&str> = entries.iter().map(|entry| entry.wallet_account).collect();
+            account_names.extend([
+                accounts.farming_income.as_str(),
+                accounts.receipts.as_str(),
+                accounts.payments.as_str(),
+                accounts.fees.as_str(),
+            ]);
+            for account_name in account_names {
+                journal.push_str(&format!("{} open {}\n", opened_at, account_name));
+            }
+            journal.push('\n');
+        }
+    }
+
+    for entry in entries {
+        let wallet_transaction = entry.wallet_transaction;
+        let price = wallet_transaction.fiat_at_time.get(quote_currency)
+            .filter(|_| wallet_transaction.flow != Flow::Internal)
+            .map(|price| format!(" @ {} {}", price.normalize(), commodity))
+            .unwrap_or_default();
+        let fee = wallet_transaction.chia_fee_amount();
+
+        let mut postings: Vec<(&str, Option<Decimal>)> = Vec::new();
+        match wallet_transaction.flow {
+            Flow::Incoming => {
+                postings.push((entry.wallet_account, Some(wallet_transaction.chia_amount())));
+                let counterpart = if wallet_transaction.category.is_farming_reward() { &accounts.farming_income } else { &accounts.receipts };
+                postings.push((counterpart, None));
+            }
+            Flow::Outgoing => {
+                if !fee.is_zero() {
+                    postings.push((&accounts.fees, Some(fee)));
+                }
+                postings.push((entry.wallet_account, Some(-(wallet_transaction.chia_amount() + fee))));
+                postings.push((&accounts.payments, None));
+            }
+            Flow::Internal => {
+                postings.push((&accounts.fees, Some(fee)));
+                postings.push((entry.wallet_account, None));
+            }
+        }
+
+        let transaction_id = wallet_transaction.transaction_id();
+        let date = match format {
+            JournalFormat::Ledger => wallet_transaction.created_at_time.format("%Y/%m/%d").to_string(),
+            JournalFormat::Hledger | JournalFormat::Beancount => wallet_transaction.created_at_time.format("%Y-%m-%d").to_string(),
+        };
+        match format {
+            JournalFormat::Ledger | JournalFormat::Hledger => {
+                journal.push_str(&format!("{} * {}\n", date, wallet_transaction.category.as_str()));
+                journal.push_str(&format!("    ; txid: {}\n", transaction_id));
+                if let Some(to_address) = &wallet_transaction.to_address {
+                    journal.push_str(&format!("    ; address: {}\n", to_address));
+                }
+            }
+            JournalFormat::Beancount => {
+                journal.push_str(&format!("{} * \"{}\"\n", date, wallet_transaction.category.as_str()));
+                journal.push_str(&format!("  txid: \"{}\"\n", transaction_id));
+                if let Some(to_address) = &wallet_transaction.to_address {
+                    journal.push_str(&format!("  address: \"{}\"\n", to_address));
+                }
+            }
+        }
+
+        let indent = if format == JournalFormat::Beancount { "  " } else { "    " };
+        for (account, amount) in postings {
+            match amount {
+                Some(amount) => journal.push_str(&format!("{}{}  {} XCH{}\n", indent, account, amount.normalize(), price)),
+                None => journal.push_str(&format!("{}{}\n", indent, account)),
+            }
+        }
+        journal.push('\n');
+    }
+
+    journal
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::str::FromStr;
+
+    use super::*;
+    use crate::wallet_transactions::tests::wallet_transaction;
+    use crate::wallet_transactions::TxCategory;
+
+    fn accounts() -> JournalAccounts {
+        JournalAccounts {
+            farming_income: String::from("Income:Chia:Farming"),
+            receipts: String::from("Income:Chia:Receipts"),
+            payments: String::from("Expenses:Chia:Payments"),
+            fees: String::from("Expenses:Chia:Fees"),
+        }
+    }
+
+    fn wallet_transactions() -> Vec<WalletTransaction> {
+        let priced_transaction = |id: u8, flow: Flow, category: TxCategory, amount: u64, created_at_time: &str| WalletTransaction {
+            category,
+            fiat_at_time: BTreeMap::from([(String::from("usd"), Decimal::from_str("29.54").unwrap())]),
+            ..wallet_transaction(id, flow, amount, created_at_time)
+        };
+
+        let mut wallet_transactions = vec![
+            priced_transaction(2, Flow::Outgoing, TxCategory::Payment, 500_000_000_000, "2023-09-01T10:00:00Z"),
+            priced_transaction(1, Flow::Incoming, TxCategory::PoolReward, 1_750_000_000_000, "2023-08-31T10:00:00Z"),
+            priced_transaction(3, Flow::Internal, TxCategory::Transfer, 100_000_000_000, "2023-09-02T10:00:00Z"),
+            priced_transaction(4, Flow::Incoming, TxCategory::Receipt, 100_000_000_000, "2023-09-03T10:00:00Z"),
+        ];
+        wallet_transactions[0].fee_amount = 1_000_000;
+        wallet_transactions[0].to_address = Some(String::from("xch1shop"));
+        // no fee, nothing to journal
+        wallet_transactions[2].fee_amount = 0;
+        wallet_transactions[3].status = TxStatus::Dropped;
+        wallet_transactions
+    }
+
+    #[test]
+    fn test_ledger_journal() {
+        let wallet_transactions = wallet_transactions();
+        let entries: Vec<JournalEntry> = wallet_transactions.iter()
+            .map(|wallet_transaction| JournalEntry { wallet_account: "Assets:Crypto:Chia", wallet_transaction })
+            .collect();
+
+        let journal = journal(&entries, &accounts(), "usd", JournalFormat::Hledger);
+        assert_eq!(journal, format!(
+            concat!(
+                "2023-08-31 * pool_reward\n",
+                "    ; txid: 0x{}\n",
+                "    Assets:Crypto:Chia  1.75 XCH @ 29.54 USD\n",
+                "    Income:Chia:Farming\n",
+                "\n",
+                "2023-09-01 * payment\n",
+                "    ; txid: 0x{}\n",
+                "    ; address: xch1shop\n",
+                "    Expenses:Chia:Fees  0.000001 XCH @ 29.54 USD\n",
+                "    Assets:Crypto:Chia  -0.500001 XCH @ 29.54 USD\n",
+                "    Expenses:Chia:Payments\n",
+                "\n",
+            ),
+            hex::encode([1u8; 32]),
+            hex::encode([2u8; 32]),
+        ));
+        assert!(super::journal(&entries, &accounts(), "usd", JournalFormat::Ledger).starts_with("2023/08/31 * pool_reward\n"));
+    }
+
+    #[test]
+    fn test_beancount_journal() {
+        let wallet_transactions = wallet_transactions();
+        let entries: Vec<JournalEntry> = wallet_transactions.iter()
+            .map(|wallet_transaction| JournalEntry { wallet_account: "Assets:Crypto:Chia", wallet_transaction })
+            .collect();
+
+        let journal = journal(&entries, &accounts(), "eur", JournalFormat::Beancount);
+        assert!(journal.starts_with(concat!(
+            "2023-08-31 open Assets:Crypto:Chia\n",
+            "2023-08-31 open Expenses:Chia:Fees\n",
+            "2023-08-31 open Expenses:Chia:Payments\n",
+            "2023-08-31 open Income:Chia:Farming\n",
+            "2023-08-31 open Income:Chia:Receipts\n",
+            "\n",
+            "2023-08-31 * \"pool_reward\"\n",
+        )));
+        assert!(journal.contains(&format!("  txid: \"0x{}\"\n", hex::encode([2u8; 32]))));
+        // no eur prices, the postings have no price
+        assert!(journal.contains("  Assets:Crypto:Chia  -0.500001 XCH\n"));
+    }
+}
@@ -1,43 +1,303 @@
-use chia_observer_wallet_generator::{derive_wallet, G1Element};
-use clap::Parser;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use chia_observer_wallet_generator::G1Element;
+use clap::{Parser, Subcommand};
 use hex::FromHex;
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 
+mod address_discovery;
+mod addresses;
+mod balance_history;
+mod block_rewards;
+mod chia_rpc;
+mod coin_gecko;
+mod coin_paprika;
+mod coin_store;
+mod cost_basis;
+mod full_node_rpc;
+mod journal_export;
+mod migrations;
+mod price_provider;
+mod prices;
+mod tax_export;
+mod transaction_list;
+mod transaction_source;
 mod wallet_cmnds;
+mod wallet_rpc;
 mod wallet_transactions;
 mod wallet_transactions_save;
 
-use wallet_transactions_save::WalletTransactionsSave;
+use address_discovery::AddressDiscovery;
+use coin_store::CoinSet;
+use cost_basis::CostBasisMethod;
+use journal_export::{JournalAccounts, JournalEntry, JournalFormat};
+use price_provider::{PriceGranularity, PriceProviderKind};
+use tax_export::TaxFormat;
+use transaction_list::{ListedTransaction, TransactionFilter, TransactionFormat};
+use transaction_source::{SourceError, TransactionSource, TransactionSourceKind};
+use wallet_transactions::{Flow, TxStatus, WalletTransaction};
+use wallet_transactions_save::{SheetsError, WalletTransactionsSave};
 
+/// Tracks the transactions and balances of chia wallets, without a command
+/// it runs the tracker
 #[derive(Parser, Debug)]
+#[command(args_conflicts_with_subcommands = true)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+    #[command(flatten)]
+    run: RunArgs,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Track the wallets, refreshing them every `refresh_interval` seconds
+    Run(RunArgs),
+    /// Refresh the wallets once and print their balances
+    Status(StatusArgs),
+    /// Print the stored transactions
+    List(ListArgs),
+    /// Write the stored transactions to a file
+    Export {
+        #[command(subcommand)]
+        export: ExportCommand,
+    },
+    /// Print the addresses of the wallets at a range of derivation indexes
+    Derive(DeriveArgs),
+    /// Create, check or print the config file
+    Config {
+        #[command(subcommand)]
+        config: ConfigCommand,
+    },
+}
+
+#[derive(clap::Args, Debug)]
+struct RunArgs {
+    /// Write the transactions to the Google sheet after every refresh
     #[arg(long)]
     save_to_gsheets: bool,
+    /// Refresh once and exit: 0 when everything worked, 2 when a wallet's
+    /// transactions couldn't be read, 3 when the db failed, 4 when the sheet failed
+    #[arg(long)]
+    once: bool,
+}
+
+#[derive(clap::Args, Debug)]
+struct StatusArgs {
+    /// Print the per address breakdown of every wallet too
+    #[arg(long)]
+    addresses: bool,
+    /// Print the end of day balances of every wallet too
+    #[arg(long)]
+    history: bool,
+}
+
+#[derive(clap::Args, Debug)]
+struct ListArgs {
+    /// Name or fingerprint of the wallet to list, every wallet by default
+    #[arg(long)]
+    wallet: Option<String>,
+    #[command(flatten)]
+    filter: TransactionFilter,
+    /// Print only this many of the most recent transactions
+    #[arg(long)]
+    limit: Option<usize>,
+}
+
+#[derive(Subcommand, Debug)]
+enum ExportCommand {
+    /// Every stored transaction with its prices
+    Transactions(TransactionExportArgs),
+    /// Tax report of a tax year, farming rewards are income at their value when received
+    Tax(TaxExportArgs),
+    /// Ledger, hledger or beancount journal of every confirmed transaction
+    Journal(JournalExportArgs),
+}
+
+#[derive(clap::Args, Debug)]
+struct TransactionExportArgs {
+    #[arg(long, value_enum)]
+    format: TransactionFormat,
+    /// Name or fingerprint of the wallet to export, every wallet by default
+    #[arg(long)]
+    wallet: Option<String>,
+    #[command(flatten)]
+    filter: TransactionFilter,
+    /// File to write, `transactions.<format>` by default
+    #[arg(long)]
+    output: Option<String>,
+}
+
+#[derive(clap::Args, Debug)]
+struct TaxExportArgs {
+    #[arg(long, value_enum)]
+    format: TaxFormat,
+    /// Calendar year of the report, in UTC
+    #[arg(long)]
+    year: i32,
+    /// Quote currency of the values, the first of `quote_currencies` by default
+    #[arg(long)]
+    currency: Option<String>,
+    /// Name or fingerprint of the wallet to export, every wallet by default
+    #[arg(long)]
+    wallet: Option<String>,
+    /// Csv file to write, `<format>-<year>.csv` by default
+    #[arg(long)]
+    output: Option<String>,
+}
+
+#[derive(clap::Args, Debug)]
+struct JournalExportArgs {
+    #[arg(long, value_enum)]
+    format: JournalFormat,
+    /// Quote currency of the prices, the first of `quote_currencies` by default
+    #[arg(long)]
+    currency: Option<String>,
+    /// Name or fingerprint of the wallet to export, every wallet by default
+    #[arg(long)]
+    wallet: Option<String>,
+    /// Journal file to write, `chia.<ledger|journal|beancount>` by default
+    #[arg(long)]
+    output: Option<String>,
+}
+
+#[derive(clap::Args, Debug)]
+struct DeriveArgs {
+    /// Name or fingerprint of the wallet, every wallet by default
+    #[arg(long)]
+    wallet: Option<String>,
+    /// First derivation index
+    #[arg(long, default_value_t = 0)]
+    from: u32,
+    /// Last derivation index, `check_count` addresses from `from` by default
+    #[arg(long)]
+    to: Option<u32>,
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigCommand {
+    /// Create config.toml with the defaults
+    Init {
+        /// Overwrite an existing config.toml with the defaults
+        #[arg(long)]
+        force: bool,
+    },
+    /// Check config.toml, exits with 1 when something is wrong
+    Validate,
+    /// Print config.toml
+    Show,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
 struct Config {
+    source: TransactionSourceKind,
     chia_blockchain_path: String,
+    chia_root: String,
+    wallet_rpc_host: String,
+    wallet_rpc_port: u16,
+    full_node_rpc_host: String,
+    full_node_rpc_port: u16,
+    json_source_path: Option<String>,
     wallet_public_key: String,
     wallet_fingerprint: u32,
     check_count: u32,
+    /// Addresses tracked along with the derived ones, like exchange deposit addresses
+    watch_addresses: Vec<String>,
     db_path: String,
     db_name: String,
     refresh_interval: u32,
     spreadsheet_id: Option<String>,
     sheet_name: Option<String>,
     sheet_range: Option<String>,
+    address_sheet_name: Option<String>,
+    /// Tab for the daily balance of every wallet together, written from A1
+    balance_sheet_name: Option<String>,
     google_service_account_key_path: Option<String>,
+    /// Where prices are fetched from, each one is tried in order until one has the price
+    price_providers: Vec<PriceProviderKind>,
+    /// Csv or json prices for the `file` price provider
+    price_file_path: Option<String>,
+    /// Resolution of the prices of transactions older than a day
+    price_granularity: PriceGranularity,
+    /// Lots the disposals are taken from for the realized gains
+    cost_basis_method: CostBasisMethod,
+    /// Fiat currencies every transaction is valued in, lowercase ISO 4217 codes
+    quote_currencies: Vec<String>,
+    /// Account of the wallets in the journal export, unless set per wallet
+    journal_wallet_account: String,
+    journal_farming_income_account: String,
+    journal_receipts_account: String,
+    journal_payments_account: String,
+    journal_fees_account: String,
+    /// Wallets to track, `wallet_public_key` is the only wallet when empty
+    wallets: Vec<WalletConfig>,
+}
+
+/// One of the `[[wallets]]` of the config
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct WalletConfig {
+    /// Shown in the output and the sheets instead of the fingerprint
+    name: Option<String>,
+    wallet_public_key: String,
+    #[serde(default)]
+    wallet_fingerprint: u32,
+    /// Tab for the transactions of this wallet only, written at `sheet_range`
+    sheet_name: Option<String>,
+    /// Overrides `json_source_path` for this wallet
+    json_source_path: Option<String>,
+    #[serde(default)]
+    watch_addresses: Vec<String>,
+    /// Overrides `journal_wallet_account` for this wallet
+    journal_account: Option<String>,
+}
+
+impl WalletConfig {
+    fn label(&self) -> String {
+        self.name.clone().unwrap_or_else(|| self.wallet_fingerprint.to_string())
+    }
+}
+
+impl Config {
+    /// The configured wallets, configs from before `[[wallets]]` track the
+    /// single `wallet_public_key`
+    fn wallets(&self) -> Vec<WalletConfig> {
+        if !self.wallets.is_empty() {
+            return self.wallets.clone();
+        }
+
+        vec![WalletConfig {
+            name: None,
+            wallet_public_key: self.wallet_public_key.clone(),
+            wallet_fingerprint: self.wallet_fingerprint,
+            sheet_name: None,
+            json_source_path: None,
+            watch_addresses: self.watch_addresses.clone(),
+            journal_account: None,
+        }]
+    }
 }
 
 impl ::std::default::Default for Config {
     fn default() -> Self {
         Self {
+            source: TransactionSourceKind::WalletRpc,
             chia_blockchain_path: String::from("/opt/chia-blockchain"),
+            chia_root: format!(
+                "{}/.chia/mainnet",
+                home::home_dir().unwrap().display()
+            ),
+            wallet_rpc_host: String::from("localhost"),
+            wallet_rpc_port: 9256,
+            full_node_rpc_host: String::from("localhost"),
+            full_node_rpc_port: 8555,
+            json_source_path: None,
             wallet_public_key: String::from("9181836e0f5e552f9cc9c25d7a10f73539dae30487f7be2fd9f1a929822917faa2949a5cd6147a09296fee68a9334b3f"),
             wallet_fingerprint: 4121996123,
             check_count: 100,
+            watch_addresses: Vec::new(),
             db_path: format!(
                 "{}/.chia-wallet-tracker",
                 home::home_dir().unwrap().display()
@@ -47,7 +307,20 @@ impl ::std::default::Default for Config {
             spreadsheet_id: None,
             sheet_name: None,
             sheet_range: None,
+            address_sheet_name: None,
+            balance_sheet_name: None,
             google_service_account_key_path: None,
+            price_providers: vec![PriceProviderKind::CoinPaprika, PriceProviderKind::CoinGecko],
+            price_file_path: None,
+            price_granularity: PriceGranularity::Daily,
+            cost_basis_method: CostBasisMethod::Fifo,
+            quote_currencies: vec![String::from("usd")],
+            journal_wallet_account: String::from("Assets:Crypto:Chia"),
+            journal_farming_income_account: String::from("Income:Chia:Farming"),
+            journal_receipts_account: String::from("Income:Chia:Receipts"),
+            journal_payments_account: String::from("Expenses:Chia:Payments"),
+            journal_fees_account: String::from("Expenses:Chia:Fees"),
+            wallets: Vec::new(),
         }
     }
 }
@@ -55,81 +328,650 @@ impl ::std::default::Default for Config {
 #[tokio::main]
 async fn main() {
     let args = Args::parse();
+    let command = args.command.unwrap_or(Command::Run(args.run));
 
-    let mut cfg: Config = confy::load_path(
-        format!(
-            "{}/.chia-wallet-tracker/config.toml",
-            home::home_dir().unwrap().display()
-        )
-        .as_str(),
-    )
-    .expect("failed to load config");
+    let config_path = format!(
+        "{}/.chia-wallet-tracker/config.toml",
+        home::home_dir().unwrap().display()
+    );
+    let config_existed = std::path::Path::new(&config_path).exists();
+    let loaded_cfg: Result<Config, confy::ConfyError> = confy::load_path(&config_path);
 
-    check_configs(&cfg, &args);
+    // the config commands have to work on a config that doesn't load or pass the checks
+    if let Command::Config { config } = &command {
+        run_config_command(&loaded_cfg, &config_path, config_existed, config);
+        return;
+    }
 
-    let pk = G1Element::from_bytes(
-        &<[u8; 48]>::from_hex(&cfg.wallet_public_key).expect("failed to parse wallet_public_key: "),
-    )
-    .expect("failed to parse wallet_public_key: ");
-
-    let fingerprint = pk.get_fingerprint();
-    if fingerprint != cfg.wallet_fingerprint {
-        cfg.wallet_fingerprint = fingerprint;
-        confy::store_path(
-            format!(
-                "{}/.chia-wallet-tracker/config.toml",
-                home::home_dir().unwrap().display()
-            )
-            .as_str(),
-            &cfg,
-        )
-        .expect("failed to store config");
+    let mut cfg = match loaded_cfg {
+        Ok(cfg) => cfg,
+        Err(e) => {
+            println!("failed to load config: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    check_configs(&cfg, matches!(&command, Command::Run(run_args) if run_args.save_to_gsheets));
+
+    let config_changed = match set_fingerprints(&mut cfg) {
+        Ok(config_changed) => config_changed,
+        Err(e) => {
+            println!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    if config_changed {
+        confy::store_path(&config_path, &cfg).expect("failed to store config");
     }
 
+    let wallets = cfg.wallets();
     let mut w_trans_saver = WalletTransactionsSave::new(&cfg);
 
-    let mut wallet_addresses: Vec<String> =
-        derive_wallet::generate_multiple_observe_wallet_addresses(&pk, 0, &cfg.check_count);
-    let w_cmds = wallet_cmnds::WalletCommands::new(&cfg);
+    match &command {
+        Command::Run(run_args) => {
+            if let Err(e) = run(&cfg, &mut w_trans_saver, &wallets, run_args).await {
+                std::process::exit(e.exit_code());
+            }
+        }
+        Command::Status(status_args) => status(&cfg, &mut w_trans_saver, &wallets, status_args).await,
+        Command::List(list_args) => list_transactions(&cfg, &mut w_trans_saver, &wallets, list_args),
+        Command::Export { export: ExportCommand::Transactions(transaction_export_args) } => {
+            export_transactions(&cfg, &mut w_trans_saver, &wallets, transaction_export_args)
+        }
+        Command::Export { export: ExportCommand::Tax(tax_export_args) } => {
+            export_tax(&cfg, &mut w_trans_saver, &wallets, tax_export_args)
+        }
+        Command::Export { export: ExportCommand::Journal(journal_export_args) } => {
+            export_journal(&cfg, &mut w_trans_saver, &wallets, journal_export_args)
+        }
+        Command::Derive(derive_args) => derive(&cfg, &wallets, derive_args),
+        // handled before the config checks
+        Command::Config { .. } => {}
+    }
+}
+
+/// Refreshes the wallets every `refresh_interval` seconds, forever unless
+/// `--once` is given. A failed refresh is retried at the next one, with
+/// `--once` the first failure is returned. The failures are printed as they
+/// happen.
+async fn run(cfg: &Config, w_trans_saver: &mut WalletTransactionsSave<'_>, wallets: &[WalletConfig], run_args: &RunArgs) -> Result<(), RefreshError> {
+    let pks: Vec<G1Element> = wallets.iter().map(|wallet| parse_public_key(&wallet.wallet_public_key)).collect();
+    let mut tracked_wallets = match track_wallets(cfg, w_trans_saver, wallets, &pks) {
+        Ok(tracked_wallets) => tracked_wallets,
+        Err(e) => {
+            println!("{}", e);
+            return Err(e);
+        }
+    };
 
     loop {
-        let raw_w_txs = w_cmds.get_wallet_transactions();
-        let mut w_txs = wallet_transactions::process_raw_transactions(
-            &raw_w_txs,
-            &mut wallet_addresses,
-            &cfg,
-            &pk,
+        let mut refreshed = refresh_wallets(w_trans_saver, &mut tracked_wallets).await;
+
+        if run_args.save_to_gsheets {
+            if let Err(e) = w_trans_saver.save_to_googlesheets().await {
+                println!("failed to save to google sheets: {}", e);
+                refreshed = refreshed.and(Err(e.into()));
+            }
+        }
+
+        if run_args.once {
+            return refreshed;
+        }
+
+        std::thread::sleep(std::time::Duration::from_secs(cfg.refresh_interval.into()));
+    }
+}
+
+/// Refreshes the wallets once and prints the reports asked for
+async fn status(cfg: &Config, w_trans_saver: &mut WalletTransactionsSave<'_>, wallets: &[WalletConfig], status_args: &StatusArgs) {
+    let pks: Vec<G1Element> = wallets.iter().map(|wallet| parse_public_key(&wallet.wallet_public_key)).collect();
+    let mut tracked_wallets = track_wallets(cfg, w_trans_saver, wallets, &pks).unwrap_or_else(|e| exit_with(&e));
+    let refreshed = refresh_wallets(w_trans_saver, &mut tracked_wallets).await;
+
+    if status_args.addresses {
+        for wallet in wallets {
+            let addresses = w_trans_saver.load_addresses(wallet.wallet_fingerprint).unwrap_or_else(|e| exit_with(&RefreshError::Db(e)));
+            println!("wallet {}", wallet.label());
+            addresses::print_address_report(&addresses);
+        }
+    }
+
+    if status_args.history {
+        let mut histories = Vec::new();
+        for wallet in wallets {
+            let history = w_trans_saver.load_balance_history(wallet.wallet_fingerprint).unwrap_or_else(|e| exit_with(&RefreshError::Db(e)));
+            println!("wallet {}", wallet.label());
+            balance_history::print_balance_history(&history, &cfg.quote_currencies);
+            histories.push(history);
+        }
+        if histories.len() > 1 {
+            println!("all wallets");
+            balance_history::print_balance_history(&balance_history::total_daily_balances(&histories), &cfg.quote_currencies);
+        }
+    }
+
+    if let Err(e) = refreshed {
+        std::process::exit(e.exit_code());
+    }
+}
+
+/// Sets up the address discovery and transaction source of every wallet
+fn track_wallets<'a>(
+    cfg: &'a Config,
+    w_trans_saver: &mut WalletTransactionsSave<'_>,
+    wallets: &'a [WalletConfig],
+    pks: &'a [G1Element],
+) -> Result<Vec<TrackedWallet<'a>>, RefreshError> {
+    let mut tracked_wallets: Vec<TrackedWallet> = Vec::new();
+    for (wallet, pk) in wallets.iter().zip(pks) {
+        let (derived_addresses, highest_used_index) = w_trans_saver.load_derivation_state(wallet.wallet_fingerprint)?;
+        let address_discovery = AddressDiscovery::new(pk, cfg.check_count, derived_addresses, highest_used_index);
+        w_trans_saver.save_derivation_state(wallet.wallet_fingerprint, &address_discovery)?;
+        tracked_wallets.push(TrackedWallet {
+            config: wallet,
+            address_discovery,
+            source: transaction_source::create_transaction_source(cfg, wallet)?,
+        });
+    }
+
+    Ok(tracked_wallets)
+}
+
+/// Refreshes every wallet and prints the totals of all of them. A wallet
+/// that fails doesn't stop the others, the first failure is returned.
+async fn refresh_wallets(w_trans_saver: &mut WalletTransactionsSave<'_>, tracked_wallets: &mut [TrackedWallet<'_>]) -> Result<(), RefreshError> {
+    let mut refreshed = Ok(());
+    let mut consolidated = WalletTotals::default();
+    for tracked_wallet in tracked_wallets.iter_mut() {
+        match refresh_wallet(w_trans_saver, tracked_wallet).await {
+            Ok(totals) => consolidated = consolidated + totals,
+            Err(e) => {
+                println!("failed to refresh wallet {}: {}", tracked_wallet.config.label(), e);
+                refreshed = refreshed.and(Err(e));
+            }
+        }
+    }
+
+    // the lots of xch moved between the wallets move along, so every wallet is done at once
+    match w_trans_saver.save_tax_lots() {
+        Ok(cost_bases) => {
+            // the current price isn't cached, so every currency is only fetched once per refresh
+            let quote_currencies: BTreeSet<&str> = cost_bases.iter().map(|(_, cost_basis)| cost_basis.quote_currency.as_str()).collect();
+            let mut current_prices: BTreeMap<&str, Decimal> = BTreeMap::new();
+            for quote_currency in quote_currencies {
+                match w_trans_saver.get_current_price(quote_currency).await {
+                    Ok(price) => {
+                        current_prices.insert(quote_currency, price);
+                    }
+                    Err(e) => println!("failed to get the current {} price: {}", quote_currency, e),
+                }
+            }
+
+            for (wallet_fingerprint, cost_basis) in &cost_bases {
+                let unrealized_gain = current_prices.get(cost_basis.quote_currency.as_str())
+                    .map_or(String::from("unknown"), |price| cost_basis.unrealized_gain(*price).round_dp(2).to_string());
+                let label = tracked_wallets.iter()
+                    .find(|tracked_wallet| tracked_wallet.config.wallet_fingerprint == *wallet_fingerprint)
+                    .map_or_else(|| wallet_fingerprint.to_string(), |tracked_wallet| tracked_wallet.config.label());
+                println!(
+                    "wallet {}: realized gain {} {}, unrealized gain {} {} ({})",
+                    label,
+                    cost_basis.realized_gain().round_dp(2),
+                    cost_basis.quote_currency,
+                    unrealized_gain,
+                    cost_basis.quote_currency,
+                    cost_basis.method.as_str()
+                );
+            }
+        }
+        Err(e) => {
+            let e = RefreshError::Db(e);
+            println!("failed to save the tax lots: {}", e);
+            refreshed = refreshed.and(Err(e));
+        }
+    }
+
+    if tracked_wallets.len() > 1 {
+        println!(
+            "all wallets: confirmed {} xch, spendable {} xch, pending {} xch",
+            consolidated.confirmed,
+            consolidated.spendable,
+            consolidated.pending
         );
-        wallet_transactions::sort_wallet_transactions_by_created_at_time(&mut w_txs);
+    }
+
+    refreshed
+}
+
+/// A configured wallet with its address discovery and transaction source
+struct TrackedWallet<'a> {
+    config: &'a WalletConfig,
+    address_discovery: AddressDiscovery<'a>,
+    source: Box<dyn TransactionSource + Send + Sync + 'a>,
+}
+
+/// Xch totals of a wallet, summed up over every wallet
+#[derive(Debug, Default, Clone, Copy)]
+struct WalletTotals {
+    confirmed: Decimal,
+    spendable: Decimal,
+    pending: Decimal,
+}
+
+impl std::ops::Add for WalletTotals {
+    type Output = Self;
 
-        w_trans_saver.save_to_db(&w_txs).await.expect("failed to save to db");
-        if args.save_to_gsheets {
-            w_trans_saver.save_to_googlesheets().await;
+    fn add(self, other: Self) -> Self {
+        Self {
+            confirmed: self.confirmed + other.confirmed,
+            spendable: self.spendable + other.spendable,
+            pending: self.pending + other.pending,
         }
+    }
+}
 
-        let mut ammount_total: Decimal = Decimal::new(0, 0);
-        for tx in w_txs {
-            let ammount = tx.chia_amount.unwrap().parse::<Decimal>().unwrap();
-            if tx.flow.unwrap() == "incoming" {
-                ammount_total += ammount;
-            } else {
-                ammount_total -= ammount;
+/// Why tracking the wallets failed
+#[derive(Debug)]
+enum RefreshError {
+    Source(SourceError),
+    Db(rusqlite::Error),
+    Sheets(SheetsError),
+}
+
+impl RefreshError {
+    /// Exit code of `run --once` and `status`
+    fn exit_code(&self) -> i32 {
+        match self {
+            RefreshError::Source(_) => 2,
+            RefreshError::Db(_) => 3,
+            RefreshError::Sheets(_) => 4,
+        }
+    }
+}
+
+impl fmt::Display for RefreshError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RefreshError::Source(e) => write!(f, "failed to get transactions: {}", e),
+            RefreshError::Db(e) => write!(f, "failed to access the db: {}", e),
+            RefreshError::Sheets(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for RefreshError {}
+
+impl From<SourceError> for RefreshError {
+    fn from(e: SourceError) -> Self {
+        RefreshError::Source(e)
+    }
+}
+
+impl From<rusqlite::Error> for RefreshError {
+    fn from(e: rusqlite::Error) -> Self {
+        RefreshError::Db(e)
+    }
+}
+
+/// The db failing while the sheet values are loaded is a db failure
+impl From<SheetsError> for RefreshError {
+    fn from(e: SheetsError) -> Self {
+        match e {
+            SheetsError::Db(e) => RefreshError::Db(e),
+            e => RefreshError::Sheets(e),
+        }
+    }
+}
+
+fn exit_with(e: &RefreshError) -> ! {
+    println!("{}", e);
+    std::process::exit(e.exit_code());
+}
+
+/// Fetches and saves the transactions of one wallet and prints its totals
+async fn refresh_wallet(
+    w_trans_saver: &mut WalletTransactionsSave<'_>,
+    tracked_wallet: &mut TrackedWallet<'_>,
+) -> Result<WalletTotals, RefreshError> {
+    let wallet = tracked_wallet.config;
+    loop {
+        let derived_addresses = tracked_wallet.address_discovery.addresses();
+        let mut wallet_addresses = derived_addresses.clone();
+        for address in &wallet.watch_addresses {
+            if !wallet_addresses.contains(address) {
+                wallet_addresses.push(address.clone());
             }
         }
+        let raw_w_txs = tracked_wallet.source.get_wallet_transactions(&wallet_addresses).await?;
+        let mut w_txs = wallet_transactions::process_raw_transactions(&raw_w_txs, &wallet_addresses);
+        wallet_transactions::sort_wallet_transactions_by_created_at_time(&mut w_txs);
+
+        // the addresses past the gap may have transactions too, check them right away
+        let derived_new_addresses = tracked_wallet.address_discovery.mark_used(&w_txs);
+        w_trans_saver.save_derivation_state(wallet.wallet_fingerprint, &tracked_wallet.address_discovery)?;
+        if derived_new_addresses {
+            continue;
+        }
+
+        let coin_set = CoinSet::from_transactions(&w_txs, &coin_store::puzzle_hashes(&wallet_addresses));
+
+        w_trans_saver.save_to_db(wallet.wallet_fingerprint, &w_txs).await?;
+        w_trans_saver.save_coins(wallet.wallet_fingerprint, &coin_set)?;
+        w_trans_saver.save_addresses(wallet.wallet_fingerprint, &addresses::summarize_addresses(&derived_addresses, &wallet.watch_addresses, &w_txs))?;
+        w_trans_saver.save_balance_history(wallet.wallet_fingerprint).await?;
 
+        if coin_set.is_empty() {
+            // the cli source doesn't report the coins, fall back to the transaction amounts
+            let totals = transaction_totals(&w_txs);
+            println!(
+                "wallet {}: total {} xch amount from total {} checked addresses",
+                wallet.label(),
+                totals.pending,
+                wallet_addresses.len()
+            );
+            return Ok(totals);
+        }
+
+        let balances = coin_set.balances();
         println!(
-            "total {} xch amount from total {} checked addresses",
-            ammount_total,
+            "wallet {}: confirmed {} xch, spendable {} xch, pending {} xch from total {} checked addresses",
+            wallet.label(),
+            balances.chia_confirmed(),
+            balances.chia_spendable(),
+            balances.chia_pending(),
             wallet_addresses.len()
         );
+        return Ok(WalletTotals {
+            confirmed: balances.chia_confirmed(),
+            spendable: balances.chia_spendable(),
+            pending: balances.chia_pending(),
+        });
+    }
+}
 
-        std::thread::sleep(std::time::Duration::from_secs(cfg.refresh_interval.into()));
+/// Totals of a wallet from its transaction amounts, for sources without the
+/// coins. The unconfirmed transactions only count towards the pending
+/// balance, like pending coins.
+fn transaction_totals(wallet_transactions: &[WalletTransaction]) -> WalletTotals {
+    let mut totals = WalletTotals::default();
+    for wallet_transaction in wallet_transactions {
+        let amount = match wallet_transaction.flow {
+            Flow::Incoming => wallet_transaction.chia_amount(),
+            Flow::Outgoing => -wallet_transaction.chia_amount(),
+            Flow::Internal => continue,
+        };
+        totals.pending += amount;
+        if wallet_transaction.status == TxStatus::Confirmed {
+            totals.confirmed += amount;
+            totals.spendable += amount;
+        }
+    }
+
+    totals
+}
+
+/// The wallet named by its label or fingerprint, or every wallet without a
+/// name. Exits when there's no such wallet.
+fn select_wallets<'a>(wallets: &'a [WalletConfig], name: &Option<String>) -> Vec<&'a WalletConfig> {
+    let wallets: Vec<&WalletConfig> = wallets.iter()
+        .filter(|wallet| name.as_ref().map_or(true, |name| *name == wallet.label() || *name == wallet.wallet_fingerprint.to_string()))
+        .collect();
+    if wallets.is_empty() {
+        println!("no wallet {} in config.toml file", name.clone().unwrap_or_default());
+        std::process::exit(1);
+    }
+
+    wallets
+}
+
+/// Stored transactions of a wallet, exits when the db can't be read
+fn load_wallet_transactions(w_trans_saver: &mut WalletTransactionsSave<'_>, wallet: &WalletConfig) -> Vec<WalletTransaction> {
+    match w_trans_saver.load_wallet_transactions(wallet.wallet_fingerprint) {
+        Ok(wallet_transactions) => wallet_transactions,
+        Err(e) => {
+            println!("failed to load the transactions of wallet {}: {}", wallet.label(), e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Stored transactions of the wallets passing the filter, in time order
+fn load_listed_transactions(
+    w_trans_saver: &mut WalletTransactionsSave<'_>,
+    wallets: &[&WalletConfig],
+    filter: &TransactionFilter,
+) -> Vec<ListedTransaction> {
+    let mut transactions = Vec::new();
+    for wallet in wallets {
+        let wallet_transactions = load_wallet_transactions(w_trans_saver, wallet);
+        transactions.extend(wallet_transactions.into_iter().map(|wallet_transaction| ListedTransaction {
+            wallet: wallet.label(),
+            wallet_transaction,
+        }));
+    }
+
+    transaction_list::filter_transactions(transactions, filter)
+}
+
+fn list_transactions(cfg: &Config, w_trans_saver: &mut WalletTransactionsSave<'_>, wallets: &[WalletConfig], args: &ListArgs) {
+    let wallets = select_wallets(wallets, &args.wallet);
+    let mut transactions = load_listed_transactions(w_trans_saver, &wallets, &args.filter);
+    if let Some(limit) = args.limit {
+        transactions.drain(..transactions.len().saturating_sub(limit));
+    }
+
+    transaction_list::print_transactions(&transactions, &cfg.quote_currencies);
+}
+
+/// Writes the transactions of the wallets for `export transactions`, exits
+/// when it fails
+fn export_transactions(cfg: &Config, w_trans_saver: &mut WalletTransactionsSave<'_>, wallets: &[WalletConfig], args: &TransactionExportArgs) {
+    let wallets = select_wallets(wallets, &args.wallet);
+    let transactions = load_listed_transactions(w_trans_saver, &wallets, &args.filter);
+
+    let contents = match args.format {
+        TransactionFormat::Csv => transaction_list::transactions_csv(&transactions, &cfg.quote_currencies),
+        TransactionFormat::Json => transaction_list::transactions_json(&transactions),
+    };
+    let output = args.output.clone().unwrap_or_else(|| format!("transactions.{}", args.format.as_str()));
+    if let Err(e) = std::fs::write(&output, contents) {
+        println!("failed to write {}: {}", output, e);
+        std::process::exit(1);
+    }
+
+    println!("wrote {} transactions to {}", transactions.len(), output);
+}
+
+/// Writes the tax report of the wallets for `export tax`, exits when it fails
+fn export_tax(cfg: &Config, w_trans_saver: &mut WalletTransactionsSave<'_>, wallets: &[WalletConfig], args: &TaxExportArgs) {
+    let quote_currency = args.currency.clone().unwrap_or_else(|| cfg.quote_currencies[0].clone());
+    let selected_wallets = select_wallets(wallets, &args.wallet);
+
+    // transfers between the wallets aren't sales, so the wallets that aren't exported
+    // are taken into account too
+    let wallets_transactions: Vec<Vec<WalletTransaction>> = wallets.iter()
+        .map(|wallet| load_wallet_transactions(w_trans_saver, wallet))
+        .collect();
+    let wallet_transaction_slices: Vec<&[WalletTransaction]> = wallets_transactions.iter().map(Vec::as_slice).collect();
+    let transfers = cost_basis::find_transfers(&wallet_transaction_slices);
+    let mut assignments = Vec::new();
+    if args.format == TaxFormat::Form8949 {
+        match cost_basis::calculate_cost_bases(&wallet_transaction_slices, &quote_currency, cfg.cost_basis_method) {
+            Ok(cost_bases) => {
+                for (wallet, cost_basis) in wallets.iter().zip(cost_bases) {
+                    if selected_wallets.contains(&wallet) {
+                        assignments.extend(cost_basis.assignments);
+                    }
+                }
+            }
+            Err(e) => {
+                println!("failed to calculate the {} cost basis: {}", quote_currency, e);
+                std::process::exit(1);
+            }
+        }
+    }
+    let w_txs: Vec<WalletTransaction> = wallets.iter().zip(wallets_transactions)
+        .filter(|(wallet, _)| selected_wallets.contains(wallet))
+        .flat_map(|(_, wallet_transactions)| wallet_transactions)
+        .collect();
+
+    let csv = match args.format {
+        TaxFormat::Form8949 => tax_export::form_8949_csv(&assignments, args.year),
+        TaxFormat::Koinly => tax_export::koinly_csv(&w_txs, &transfers, &quote_currency, args.year),
+        TaxFormat::CoinTracker => tax_export::cointracker_csv(&w_txs, &transfers, args.year),
+    };
+    let output = args.output.clone().unwrap_or_else(|| format!("{}-{}.csv", args.format.as_str(), args.year));
+    if let Err(e) = std::fs::write(&output, &csv) {
+        println!("failed to write {}: {}", output, e);
+        std::process::exit(1);
+    }
+
+    println!("wrote {} rows to {}", csv.lines().count() - 1, output);
+    println!(
+        "farming income in {}: {} {}",
+        args.year,
+        tax_export::farming_income(&w_txs, &quote_currency, args.year).round_dp(2),
+        quote_currency
+    );
+}
+
+/// Writes the journal of the wallets for `export journal`, exits when it fails
+fn export_journal(cfg: &Config, w_trans_saver: &mut WalletTransactionsSave<'_>, wallets: &[WalletConfig], args: &JournalExportArgs) {
+    let quote_currency = args.currency.clone().unwrap_or_else(|| cfg.quote_currencies[0].clone());
+    let wallets = select_wallets(wallets, &args.wallet);
+
+    let mut wallet_transactions = Vec::new();
+    for wallet in &wallets {
+        let w_txs = load_wallet_transactions(w_trans_saver, wallet);
+        let wallet_account = wallet.journal_account.as_deref().unwrap_or(&cfg.journal_wallet_account);
+        wallet_transactions.push((wallet_account, w_txs));
+    }
+    let entries: Vec<JournalEntry> = wallet_transactions.iter()
+        .flat_map(|(wallet_account, w_txs)| {
+            w_txs.iter().map(|wallet_transaction| JournalEntry { wallet_account, wallet_transaction })
+        })
+        .collect();
+    let accounts = JournalAccounts {
+        farming_income: cfg.journal_farming_income_account.clone(),
+        receipts: cfg.journal_receipts_account.clone(),
+        payments: cfg.journal_payments_account.clone(),
+        fees: cfg.journal_fees_account.clone(),
+    };
+
+    let journal = journal_export::journal(&entries, &accounts, &quote_currency, args.format);
+    let output = args.output.clone().unwrap_or_else(|| format!("chia.{}", args.format.extension()));
+    if let Err(e) = std::fs::write(&output, &journal) {
+        println!("failed to write {}: {}", output, e);
+        std::process::exit(1);
+    }
+
+    println!("wrote {} journal to {}", args.format.as_str(), output);
+}
+
+/// Prints the addresses of the wallets from `--from` through `--to`
+fn derive(cfg: &Config, wallets: &[WalletConfig], args: &DeriveArgs) {
+    let to = args.to.unwrap_or_else(|| args.from.saturating_add(cfg.check_count.max(1) - 1));
+    if to < args.from {
+        println!("--to {} is before --from {}", to, args.from);
+        std::process::exit(1);
+    }
+
+    for wallet in select_wallets(wallets, &args.wallet) {
+        let pk = parse_public_key(&wallet.wallet_public_key);
+        println!("wallet {}", wallet.label());
+        for derivation_index in args.from..=to {
+            let derived_address = address_discovery::derive_address(&pk, derivation_index);
+            println!(
+                "{:>5}  {}  0x{}",
+                derived_address.derivation_index,
+                derived_address.address,
+                hex::encode(derived_address.puzzle_hash)
+            );
+        }
     }
 }
 
-fn check_configs(cfg: &Config, args: &Args) {
+/// Runs a `config` command, `init --force` and `show` work on a config that
+/// doesn't load too
+fn run_config_command(loaded_cfg: &Result<Config, confy::ConfyError>, config_path: &str, config_existed: bool, command: &ConfigCommand) {
+    match command {
+        ConfigCommand::Init { force } => {
+            if !config_existed {
+                println!("created {}", config_path);
+            } else if *force {
+                confy::store_path(config_path, Config::default()).expect("failed to store config");
+                println!("reset {} to the defaults", config_path);
+            } else {
+                println!("{} already exists, --force overwrites it with the defaults", config_path);
+            }
+        }
+        ConfigCommand::Validate => match loaded_cfg {
+            Ok(cfg) => {
+                check_configs(cfg, cfg.spreadsheet_id.is_some());
+                println!("{} is valid", config_path);
+            }
+            Err(e) => {
+                println!("failed to load {}: {}", config_path, e);
+                std::process::exit(1);
+            }
+        },
+        ConfigCommand::Show => {
+            match std::fs::read_to_string(config_path) {
+                Ok(contents) => print!("{}", contents),
+                Err(e) => {
+                    println!("failed to read {}: {}", config_path, e);
+                    std::process::exit(1);
+                }
+            }
+            if let Err(e) = loaded_cfg {
+                println!("failed to load {}: {}", config_path, e);
+            }
+        }
+    }
+}
+
+fn try_parse_public_key(wallet_public_key: &str) -> Option<G1Element> {
+    <[u8; 48]>::from_hex(wallet_public_key).ok().and_then(|bytes| G1Element::from_bytes(&bytes).ok())
+}
+
+fn parse_public_key(wallet_public_key: &str) -> G1Element {
+    G1Element::from_bytes(
+        &<[u8; 48]>::from_hex(wallet_public_key).expect("failed to parse wallet_public_key: "),
+    )
+    .expect("failed to parse wallet_public_key: ")
+}
+
+/// Sets the fingerprint of every tracked wallet from its public key,
+/// returns true when one of them was wrong. The top level key is only
+/// tracked without `[[wallets]]`.
+fn set_fingerprints(cfg: &mut Config) -> Result<bool, String> {
+    if cfg.wallets.is_empty() {
+        return set_fingerprint(&cfg.wallet_public_key, &mut cfg.wallet_fingerprint);
+    }
+
+    let mut changed = false;
+    for wallet in &mut cfg.wallets {
+        changed |= set_fingerprint(&wallet.wallet_public_key, &mut wallet.wallet_fingerprint)?;
+    }
+    Ok(changed)
+}
+
+/// Sets the fingerprint of the public key, returns true when it was wrong
+fn set_fingerprint(wallet_public_key: &str, wallet_fingerprint: &mut u32) -> Result<bool, String> {
+    let fingerprint = try_parse_public_key(wallet_public_key)
+        .ok_or_else(|| format!("invalid wallet_public_key {}", wallet_public_key))?
+        .get_fingerprint();
+    if fingerprint == *wallet_fingerprint {
+        return Ok(false);
+    }
+
+    *wallet_fingerprint = fingerprint;
+    Ok(true)
+}
+
+fn check_configs(cfg: &Config, save_to_gsheets: bool) {
     let mut config_ok = true;
-    if args.save_to_gsheets {
+    if save_to_gsheets {
         if cfg.google_service_account_key_path.is_none() {
             config_ok = false;
             println!("google_service_account_key_path is not set in config.toml file");
@@ -148,7 +990,130 @@ fn check_configs(cfg: &Config, args: &Args) {
         }
     }
 
+    if cfg.source == TransactionSourceKind::Json
+        && cfg.json_source_path.is_none()
+        && cfg.wallets().iter().any(|wallet| wallet.json_source_path.is_none())
+    {
+        config_ok = false;
+        println!("json_source_path is not set in config.toml file");
+    }
+
+    if cfg.price_providers.contains(&PriceProviderKind::File) && cfg.price_file_path.is_none() {
+        config_ok = false;
+        println!("price_file_path is not set in config.toml file");
+    }
+
+    if cfg.quote_currencies.is_empty() {
+        config_ok = false;
+        println!("quote_currencies is empty in config.toml file");
+    }
+    for quote_currency in &cfg.quote_currencies {
+        if quote_currency.len() != 3 || !quote_currency.chars().all(|c| c.is_ascii_lowercase()) {
+            config_ok = false;
+            println!("invalid quote currency {}, use lowercase ISO 4217 codes like 'eur'", quote_currency);
+        }
+    }
+
+    if !cfg.wallets.is_empty() && !cfg.watch_addresses.is_empty() {
+        config_ok = false;
+        println!("watch_addresses has to be set per wallet when [[wallets]] is set in config.toml file");
+    }
+    for wallet in cfg.wallets() {
+        if try_parse_public_key(&wallet.wallet_public_key).is_none() {
+            config_ok = false;
+            println!("invalid wallet_public_key {}", wallet.wallet_public_key);
+        }
+        for address in &wallet.watch_addresses {
+            if let Err(e) = addresses::parse_watch_address(address) {
+                config_ok = false;
+                println!("invalid watch address {}: {}", address, e);
+            }
+        }
+    }
+    if cfg.source != TransactionSourceKind::FullNodeRpc && cfg.wallets().iter().any(|wallet| !wallet.watch_addresses.is_empty()) {
+        // they still mark transactions to them as the wallet's own, so it's not an error
+        println!("warning: watch_addresses are only checked for transactions with source = \"full_node_rpc\"");
+    }
+
     if !config_ok {
         std::process::exit(1);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wallet_transactions::tests::wallet_transaction;
+
+    #[test]
+    fn test_exit_codes() {
+        let cases = [
+            (RefreshError::Source(SourceError::Command(String::from("chia not found"))), 2),
+            (RefreshError::Db(rusqlite::Error::InvalidQuery), 3),
+            (RefreshError::Sheets(SheetsError::Auth(std::io::Error::from(std::io::ErrorKind::NotFound))), 4),
+            (RefreshError::from(SheetsError::Auth(std::io::Error::from(std::io::ErrorKind::NotFound))), 4),
+            (RefreshError::from(SheetsError::Db(rusqlite::Error::InvalidQuery)), 3),
+        ];
+
+        for (e, exit_code) in cases {
+            assert_eq!(e.exit_code(), exit_code, "{}", e);
+        }
+    }
+
+    #[test]
+    fn test_transaction_totals() {
+        let xch = 1_000_000_000_000;
+        let wallet_transactions = vec![
+            wallet_transaction(1, Flow::Incoming, 3 * xch, "2023-01-01T00:00:00Z"),
+            wallet_transaction(2, Flow::Outgoing, xch, "2023-02-01T00:00:00Z"),
+            wallet_transaction(3, Flow::Internal, xch, "2023-03-01T00:00:00Z"),
+            WalletTransaction { status: TxStatus::Pending, ..wallet_transaction(4, Flow::Incoming, 5 * xch, "2023-04-01T00:00:00Z") },
+        ];
+
+        let totals = transaction_totals(&wallet_transactions);
+        assert_eq!((totals.confirmed, totals.spendable, totals.pending), (Decimal::from(2), Decimal::from(2), Decimal::from(7)));
+    }
+
+    #[test]
+    fn test_set_fingerprints() {
+        let mut cfg = Config { wallet_fingerprint: 0, ..Config::default() };
+        let fingerprint = parse_public_key(&cfg.wallet_public_key).get_fingerprint();
+        assert_eq!(set_fingerprints(&mut cfg), Ok(true));
+        assert_eq!(cfg.wallet_fingerprint, fingerprint);
+        assert_eq!(set_fingerprints(&mut cfg), Ok(false));
+
+        // the top level key isn't tracked with [[wallets]], it doesn't have to be valid
+        let wallet = WalletConfig { wallet_fingerprint: 0, ..cfg.wallets()[0].clone() };
+        cfg.wallet_public_key = String::new();
+        cfg.wallets = vec![wallet];
+        assert_eq!(set_fingerprints(&mut cfg), Ok(true));
+        assert_eq!(cfg.wallets[0].wallet_fingerprint, fingerprint);
+
+        cfg.wallets[0].wallet_public_key = String::from("00");
+        assert_eq!(set_fingerprints(&mut cfg), Err(String::from("invalid wallet_public_key 00")));
+    }
+
+    #[tokio::test]
+    async fn test_run_once() {
+        let json_source_path = std::env::temp_dir().join("main_run_once_test.json");
+        std::fs::write(&json_source_path, "[]").unwrap();
+        let mut cfg = Config::default();
+        cfg.source = TransactionSourceKind::Json;
+        cfg.json_source_path = Some(json_source_path.display().to_string());
+        cfg.db_path = String::from("/tmp");
+        cfg.db_name = String::from("main_run_once_test.db");
+        std::fs::remove_file(format!("{}/{}", cfg.db_path, cfg.db_name)).ok();
+        let run_args = RunArgs { save_to_gsheets: false, once: true };
+
+        // returns after the first refresh instead of waiting for the next one
+        let wallets = cfg.wallets();
+        let mut w_trans_saver = WalletTransactionsSave::new(&cfg);
+        assert!(run(&cfg, &mut w_trans_saver, &wallets, &run_args).await.is_ok());
+
+        cfg.db_path = String::from("/nonexistent/main_run_once_test");
+        let wallets = cfg.wallets();
+        let mut w_trans_saver = WalletTransactionsSave::new(&cfg);
+        let e = run(&cfg, &mut w_trans_saver, &wallets, &run_args).await.unwrap_err();
+        assert_eq!(e.exit_code(), 3);
+    }
+}
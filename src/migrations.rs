@@ -0,0 +1,569 @@
+use std::str::FromStr;
+
+use rust_decimal::Decimal;
+
+use crate::wallet_transactions::chia_amount_to_mojos;
+
+type Migration = fn(&rusqlite::Transaction) -> Result<(), rusqlite::Error>;
+
+/// Schema migrations in order, the db `user_version` is the number of
+/// migrations applied. Never edit a released migration, add a new one.
+const MIGRATIONS: &[Migration] = &[
+    create_tables,
+    amounts_to_mojos,
+    wallet_fingerprint_columns,
+    watched_addresses,
+    prices,
+    transaction_prices,
+    price_timestamps,
+    tax_lots,
+    balance_history,
+    unknown_address_balances,
+    coin_confirmations,
+];
+
+/// `user_version` of the dbs whose rows have a wallet fingerprint, the rows of
+/// older dbs are stored with fingerprint 0
+pub const WALLET_FINGERPRINT_VERSION: usize = 3;
+
+/// Brings the db schema up to date, every migration runs in its own
+/// transaction together with the `user_version` bump. Returns the
+/// `user_version` from before.
+pub fn run(db_connection: &mut rusqlite::Connection) -> Result<usize, rusqlite::Error> {
+    run_migrations(db_connection, MIGRATIONS)
+}
+
+fn run_migrations(db_connection: &mut rusqlite::Connection, migrations: &[Migration]) -> Result<usize, rusqlite::Error> {
+    let user_version: usize = db_connection.query_row("PRAGMA user_version", rusqlite::params![], |row| row.get(0))?;
+
+    for (version, migration) in migrations.iter().enumerate().skip(user_version) {
+        let transaction = db_connection.transaction()?;
+        migration(&transaction)?;
+        transaction.pragma_update(None, "user_version", version + 1)?;
+        transaction.commit()?;
+    }
+
+    Ok(user_version)
+}
+
+/// The schema before versioning. Dbs from before then may have any subset of
+/// the columns, so the missing ones are added.
+fn create_tables(db_connection: &rusqlite::Transaction) -> Result<(), rusqlite::Error> {
+    db_connection.execute_batch(
+        "CREATE TABLE IF NOT EXISTS wallet_transactions (
+            `transaction` TEXT PRIMARY KEY,
+            status BOOLEAN,
+            chia_amount TEXT,
+            to_address TEXT,
+            created_at_time TEXT,
+            flow TEXT,
+            usd_at_time TEXT
+        );
+        CREATE TABLE IF NOT EXISTS transaction_coins (
+            `transaction` TEXT NOT NULL,
+            direction TEXT NOT NULL,
+            coin_id TEXT NOT NULL,
+            parent_coin_info TEXT NOT NULL,
+            puzzle_hash TEXT NOT NULL,
+            amount INTEGER NOT NULL,
+            PRIMARY KEY (`transaction`, direction, coin_id)
+        );
+        CREATE TABLE IF NOT EXISTS transaction_memos (
+            `transaction` TEXT NOT NULL,
+            coin_id TEXT NOT NULL,
+            memo TEXT NOT NULL,
+            PRIMARY KEY (`transaction`, coin_id, memo)
+        );
+        CREATE TABLE IF NOT EXISTS coins (
+            coin_id TEXT PRIMARY KEY,
+            parent_coin_info TEXT NOT NULL,
+            puzzle_hash TEXT NOT NULL,
+            amount INTEGER NOT NULL,
+            created_height INTEGER,
+            spent_height INTEGER,
+            pending_spend BOOLEAN NOT NULL DEFAULT 0
+        );
+        CREATE TABLE IF NOT EXISTS addresses (
+            address TEXT PRIMARY KEY,
+            derivation_index INTEGER NOT NULL,
+            total_received INTEGER NOT NULL,
+            total_sent INTEGER NOT NULL,
+            balance INTEGER NOT NULL,
+            first_seen TEXT,
+            last_seen TEXT,
+            tx_count INTEGER NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS derived_addresses (
+            wallet_fingerprint INTEGER NOT NULL,
+            derivation_index INTEGER NOT NULL,
+            address TEXT NOT NULL,
+            puzzle_hash TEXT NOT NULL,
+            PRIMARY KEY (wallet_fingerprint, derivation_index)
+        );
+        CREATE TABLE IF NOT EXISTS derivation_state (
+            wallet_fingerprint INTEGER PRIMARY KEY,
+            highest_used_index INTEGER
+        );
+        CREATE TABLE IF NOT EXISTS tx_status_history (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            `transaction` TEXT NOT NULL,
+            old_status TEXT,
+            new_status TEXT NOT NULL,
+            confirmed_at_height INTEGER,
+            observed_at TEXT NOT NULL
+        );",
+    )?;
+
+    ensure_column(db_connection, "wallet_transactions", "transaction_type", "INTEGER")?;
+    ensure_column(db_connection, "wallet_transactions", "fee_amount", "INTEGER NOT NULL DEFAULT 0")?;
+    ensure_column(db_connection, "wallet_transactions", "confirmed_at_height", "INTEGER")?;
+    ensure_column(db_connection, "wallet_transactions", "spend_bundle", "TEXT")?;
+    ensure_column(db_connection, "wallet_transactions", "category", "TEXT")?;
+    ensure_column(db_connection, "wallet_transactions", "tx_status", "TEXT")?;
+
+    // rows saved before the categories can only be told apart by their flow
+    db_connection.execute(
+        "UPDATE wallet_transactions SET category = CASE flow
+            WHEN 'outgoing' THEN 'payment'
+            WHEN 'internal' THEN 'transfer'
+            ELSE 'receipt'
+        END WHERE category IS NULL",
+        rusqlite::params![],
+    )?;
+    db_connection.execute(
+        "UPDATE wallet_transactions SET tx_status = CASE WHEN status THEN 'confirmed' ELSE 'pending' END
+        WHERE tx_status IS NULL",
+        rusqlite::params![],
+    )?;
+
+    Ok(())
+}
+
+/// Moves the xch amounts stored as decimal text to integer mojos and the usd
+/// prices to exact decimal strings, and drops the `status` boolean replaced
+/// by `tx_status`. SQLite can't change column types so the table is rebuilt.
+fn amounts_to_mojos(db_connection: &rusqlite::Transaction) -> Result<(), rusqlite::Error> {
+    db_connection.execute_batch(
+        "CREATE TABLE wallet_transactions_new (
+            `transaction` TEXT PRIMARY KEY,
+            tx_status TEXT NOT NULL,
+            amount INTEGER NOT NULL,
+            to_address TEXT,
+            created_at_time TEXT NOT NULL,
+            flow TEXT NOT NULL,
+            category TEXT NOT NULL,
+            usd_at_time TEXT,
+            transaction_type INTEGER,
+            fee_amount INTEGER NOT NULL DEFAULT 0,
+            confirmed_at_height INTEGER,
+            spend_bundle TEXT
+        );",
+    )?;
+
+    let mut stmt = db_connection.prepare(
+        "SELECT `transaction`, tx_status, chia_amount, to_address, created_at_time, flow, category, usd_at_time,
+            transaction_type, fee_amount, confirmed_at_height, spend_bundle
+        FROM wallet_transactions",
+    )?;
+    let mut rows = stmt.query(rusqlite::params![])?;
+    while let Some(row) = rows.next()? {
+        let amount = chia_amount_to_mojos(&row.get::<_, String>(2)?)
+            .map_err(|e| rusqlite::Error::FromSqlConversionFailure(2, rusqlite::types::Type::Text, e.into()))?;
+        // prices weren't fetched yet when missing or zero
+        let usd_at_time = row.get::<_, Option<String>>(7)?
+            .and_then(|usd_at_time| Decimal::from_str(usd_at_time.trim()).ok())
+            .filter(|usd_at_time| !usd_at_time.is_zero())
+            .map(|usd_at_time| usd_at_time.normalize().to_string());
+
+        db_connection.execute(
+            "INSERT INTO wallet_transactions_new VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+            rusqlite::params![
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                amount,
+                row.get::<_, Option<String>>(3)?,
+                row.get::<_, String>(4)?,
+                row.get::<_, String>(5)?,
+                row.get::<_, String>(6)?,
+                usd_at_time,
+                row.get::<_, Option<u32>>(8)?,
+                row.get::<_, u64>(9)?,
+                row.get::<_, Option<u32>>(10)?,
+                row.get::<_, Option<String>>(11)?,
+            ],
+        )?;
+    }
+    drop(rows);
+    drop(stmt);
+
+    db_connection.execute_batch(
+        "DROP TABLE wallet_transactions;
+        ALTER TABLE wallet_transactions_new RENAME TO wallet_transactions;",
+    )?;
+
+    Ok(())
+}
+
+/// Adds the wallet the rows belong to, so several wallets can share the db.
+/// The existing rows get fingerprint 0 until the saver assigns them to the
+/// first configured wallet.
+fn wallet_fingerprint_columns(db_connection: &rusqlite::Transaction) -> Result<(), rusqlite::Error> {
+    rebuild_table(
+        db_connection,
+        "wallet_transactions",
+        "`transaction` TEXT NOT NULL,
+        tx_status TEXT NOT NULL,
+        amount INTEGER NOT NULL,
+        to_address TEXT,
+        created_at_time TEXT NOT NULL,
+        flow TEXT NOT NULL,
+        category TEXT NOT NULL,
+        usd_at_time TEXT,
+        transaction_type INTEGER,
+        fee_amount INTEGER NOT NULL DEFAULT 0,
+        confirmed_at_height INTEGER,
+        spend_bundle TEXT,
+        PRIMARY KEY (wallet_fingerprint, `transaction`)",
+        "`transaction`, tx_status, amount, to_address, created_at_time, flow, category, usd_at_time,
+        transaction_type, fee_amount, confirmed_at_height, spend_bundle",
+    )?;
+    rebuild_table(
+        db_connection,
+        "transaction_coins",
+        "`transaction` TEXT NOT NULL,
+        direction TEXT NOT NULL,
+        coin_id TEXT NOT NULL,
+        parent_coin_info TEXT NOT NULL,
+        puzzle_hash TEXT NOT NULL,
+        amount INTEGER NOT NULL,
+        PRIMARY KEY (wallet_fingerprint, `transaction`, direction, coin_id)",
+        "`transaction`, direction, coin_id, parent_coin_info, puzzle_hash, amount",
+    )?;
+    rebuild_table(
+        db_connection,
+        "transaction_memos",
+        "`transaction` TEXT NOT NULL,
+        coin_id TEXT NOT NULL,
+        memo TEXT NOT NULL,
+        PRIMARY KEY (wallet_fingerprint, `transaction`, coin_id, memo)",
+        "`transaction`, coin_id, memo",
+    )?;
+    rebuild_table(
+        db_connection,
+        "coins",
+        "coin_id TEXT NOT NULL,
+        parent_coin_info TEXT NOT NULL,
+        puzzle_hash TEXT NOT NULL,
+        amount INTEGER NOT NULL,
+        created_height INTEGER,
+        spent_height INTEGER,
+        pending_spend BOOLEAN NOT NULL DEFAULT 0,
+        PRIMARY KEY (wallet_fingerprint, coin_id)",
+        "coin_id, parent_coin_info, puzzle_hash, amount, created_height, spent_height, pending_spend",
+    )?;
+    rebuild_table(
+        db_connection,
+        "addresses",
+        "address TEXT NOT NULL,
+        derivation_index INTEGER NOT NULL,
+        total_received INTEGER NOT NULL,
+        total_sent INTEGER NOT NULL,
+        balance INTEGER NOT NULL,
+        first_seen TEXT,
+        last_seen TEXT,
+        tx_count INTEGER NOT NULL,
+        PRIMARY KEY (wallet_fingerprint, address)",
+        "address, derivation_index, total_received, total_sent, balance, first_seen, last_seen, tx_count",
+    )?;
+    ensure_column(db_connection, "tx_status_history", "wallet_fingerprint", "INTEGER NOT NULL DEFAULT 0")?;
+
+    Ok(())
+}
+
+/// Watched addresses have no derivation index, the `kind` column tells them
+/// apart from the derived ones.
+fn watched_addresses(db_connection: &rusqlite::Transaction) -> Result<(), rusqlite::Error> {
+    db_connection.execute_batch(
+        "CREATE TABLE addresses_new (
+            wallet_fingerprint INTEGER NOT NULL DEFAULT 0,
+            address TEXT NOT NULL,
+            kind TEXT NOT NULL DEFAULT 'derived',
+            derivation_index INTEGER,
+            total_received INTEGER NOT NULL,
+            total_sent INTEGER NOT NULL,
+            balance INTEGER NOT NULL,
+            first_seen TEXT,
+            last_seen TEXT,
+            tx_count INTEGER NOT NULL,
+            PRIMARY KEY (wallet_fingerprint, address)
+        );
+        INSERT INTO addresses_new (
+            wallet_fingerprint, address, derivation_index, total_received, total_sent, balance, first_seen, last_seen, tx_count
+        )
+        SELECT wallet_fingerprint, address, derivation_index, total_received, total_sent, balance, first_seen, last_seen, tx_count
+        FROM addresses;
+        DROP TABLE addresses;
+        ALTER TABLE addresses_new RENAME TO addresses;",
+    )
+}
+
+/// Price history cache, prices are exact decimal strings
+fn prices(db_connection: &rusqlite::Transaction) -> Result<(), rusqlite::Error> {
+    db_connection.execute_batch(
+        "CREATE TABLE prices (
+            asset TEXT NOT NULL,
+            quote_currency TEXT NOT NULL,
+            timestamp TEXT NOT NULL,
+            price TEXT NOT NULL,
+            volume_24h TEXT,
+            market_cap TEXT,
+            source TEXT NOT NULL,
+            PRIMARY KEY (asset, quote_currency, timestamp)
+        );",
+    )
+}
+
+/// Moves the usd price of the transactions to `transaction_prices`, which has
+/// a price per quote currency.
+fn transaction_prices(db_connection: &rusqlite::Transaction) -> Result<(), rusqlite::Error> {
+    db_connection.execute_batch(
+        "CREATE TABLE transaction_prices (
+            wallet_fingerprint INTEGER NOT NULL DEFAULT 0,
+            `transaction` TEXT NOT NULL,
+            quote_currency TEXT NOT NULL,
+            price TEXT NOT NULL,
+            PRIMARY KEY (wallet_fingerprint, `transaction`, quote_currency)
+        );
+        INSERT INTO transaction_prices (wallet_fingerprint, `transaction`, quote_currency, price)
+        SELECT wallet_fingerprint, `transaction`, 'usd', usd_at_time FROM wallet_transactions WHERE usd_at_time IS NOT NULL;",
+    )?;
+    rebuild_table(
+        db_connection,
+        "wallet_transactions",
+        "`transaction` TEXT NOT NULL,
+        tx_status TEXT NOT NULL,
+        amount INTEGER NOT NULL,
+        to_address TEXT,
+        created_at_time TEXT NOT NULL,
+        flow TEXT NOT NULL,
+        category TEXT NOT NULL,
+        transaction_type INTEGER,
+        fee_amount INTEGER NOT NULL DEFAULT 0,
+        confirmed_at_height INTEGER,
+        spend_bundle TEXT,
+        PRIMARY KEY (wallet_fingerprint, `transaction`)",
+        "wallet_fingerprint, `transaction`, tx_status, amount, to_address, created_at_time, flow, category,
+        transaction_type, fee_amount, confirmed_at_height, spend_bundle",
+    )
+}
+
+/// Time of the price each transaction was valued at. Prices stored before
+/// are left without one.
+fn price_timestamps(db_connection: &rusqlite::Transaction) -> Result<(), rusqlite::Error> {
+    ensure_column(db_connection, "transaction_prices", "price_timestamp", "TEXT")
+}
+
+/// Disposals matched to the lots they were taken from, recalculated on every
+/// refresh
+fn tax_lots(db_connection: &rusqlite::Transaction) -> Result<(), rusqlite::Error> {
+    db_connection.execute_batch(
+        "CREATE TABLE tax_lots (
+            wallet_fingerprint INTEGER NOT NULL,
+            quote_currency TEXT NOT NULL,
+            cost_basis_method TEXT NOT NULL,
+            disposal_transaction TEXT NOT NULL,
+            disposed_at TEXT NOT NULL,
+            acquisition_transaction TEXT,
+            acquired_at TEXT,
+            amount INTEGER NOT NULL,
+            cost_basis TEXT NOT NULL,
+            proceeds TEXT NOT NULL,
+            gain TEXT NOT NULL
+        );
+        CREATE INDEX tax_lots_wallet ON tax_lots (wallet_fingerprint, quote_currency);",
+    )
+}
+
+/// End of day balance of every wallet valued in each quote currency,
+/// recalculated on every refresh
+fn balance_history(db_connection: &rusqlite::Transaction) -> Result<(), rusqlite::Error> {
+    db_connection.execute_batch(
+        "CREATE TABLE balance_history (
+            wallet_fingerprint INTEGER NOT NULL,
+            day TEXT NOT NULL,
+            quote_currency TEXT NOT NULL,
+            balance INTEGER NOT NULL,
+            price TEXT,
+            value TEXT,
+            PRIMARY KEY (wallet_fingerprint, day, quote_currency)
+        );",
+    )
+}
+
+/// Sources without coins don't tell which addresses a payment was made from,
+/// the sent mojos and balance of the addresses are NULL then.
+fn unknown_address_balances(db_connection: &rusqlite::Transaction) -> Result<(), rusqlite::Error> {
+    rebuild_table(
+        db_connection,
+        "addresses",
+        "address TEXT NOT NULL,
+        kind TEXT NOT NULL DEFAULT 'derived',
+        derivation_index INTEGER,
+        total_received INTEGER NOT NULL,
+        total_sent INTEGER,
+        balance INTEGER,
+        first_seen TEXT,
+        last_seen TEXT,
+        tx_count INTEGER NOT NULL,
+        PRIMARY KEY (wallet_fingerprint, address)",
+        "wallet_fingerprint, address, kind, derivation_index, total_received, total_sent, balance, first_seen, last_seen, tx_count",
+    )
+}
+
+/// Sources without heights confirm coins at an unknown height, which was
+/// stored as height 0
+fn coin_confirmations(db_connection: &rusqlite::Transaction) -> Result<(), rusqlite::Error> {
+    db_connection.execute_batch(
+        "ALTER TABLE coins ADD COLUMN confirmed BOOLEAN NOT NULL DEFAULT 0;
+        ALTER TABLE coins ADD COLUMN spent BOOLEAN NOT NULL DEFAULT 0;
+        UPDATE coins SET confirmed = created_height IS NOT NULL, spent = spent_height IS NOT NULL;
+        UPDATE coins SET created_height = NULL WHERE created_height = 0;
+        UPDATE coins SET spent_height = NULL WHERE spent_height = 0;",
+    )
+}
+
+/// Recreates `table` with a leading `wallet_fingerprint` column and copies
+/// `columns` over, SQLite can't change the primary key of a table in place.
+fn rebuild_table(db_connection: &rusqlite::Connection, table: &str, definition: &str, columns: &str) -> Result<(), rusqlite::Error> {
+    db_connection.execute_batch(&format!(
+        "CREATE TABLE {table}_new (
+            wallet_fingerprint INTEGER NOT NULL DEFAULT 0,
+            {definition}
+        );
+        INSERT INTO {table}_new ({columns}) SELECT {columns} FROM {table};
+        DROP TABLE {table};
+        ALTER TABLE {table}_new RENAME TO {table};",
+    ))
+}
+
+fn ensure_column(db_connection: &rusqlite::Connection, table: &str, column: &str, definition: &str) -> Result<(), rusqlite::Error> {
+    let mut stmt = db_connection.prepare(&format!("PRAGMA table_info({})", table))?;
+    let columns = stmt.query_map(rusqlite::params![], |row| row.get::<_, String>(1))?
+        .collect::<Result<Vec<String>, rusqlite::Error>>()?;
+
+    if !columns.iter().any(|existing| existing == column) {
+        db_connection.execute(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition), rusqlite::params![])?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user_version(db_connection: &rusqlite::Connection) -> usize {
+        db_connection.query_row("PRAGMA user_version", rusqlite::params![], |row| row.get(0)).unwrap()
+    }
+
+    #[test]
+    fn test_migrate_new_db() {
+        let mut db_connection = rusqlite::Connection::open_in_memory().unwrap();
+        assert_eq!(run(&mut db_connection).unwrap(), 0);
+        assert_eq!(user_version(&db_connection), MIGRATIONS.len());
+
+        // nothing left to run
+        assert_eq!(run(&mut db_connection).unwrap(), MIGRATIONS.len());
+        assert_eq!(user_version(&db_connection), MIGRATIONS.len());
+    }
+
+    #[test]
+    fn test_migrate_first_release_db() {
+        let mut db_connection = rusqlite::Connection::open_in_memory().unwrap();
+        db_connection.execute_batch(
+            "CREATE TABLE wallet_transactions (
+                `transaction` TEXT PRIMARY KEY,
+                status BOOLEAN,
+                chia_amount TEXT,
+                to_address TEXT,
+                created_at_time TEXT,
+                flow TEXT,
+                usd_at_time TEXT
+            );
+            INSERT INTO wallet_transactions VALUES ('0x01', 1, '39.39', 'xch1test', '2023-08-31T00:00:00Z', 'incoming', '29.540000');
+            INSERT INTO wallet_transactions VALUES ('0x02', 0, '0.000000000001', 'xch1other', '2023-09-01T00:00:00Z', 'outgoing', '0.0');",
+        ).unwrap();
+
+        run(&mut db_connection).unwrap();
+        assert_eq!(user_version(&db_connection), MIGRATIONS.len());
+
+        let rows = db_connection
+            .prepare(
+                "SELECT `transaction`, tx_status, amount, category, price, fee_amount FROM wallet_transactions
+                LEFT JOIN transaction_prices USING (wallet_fingerprint, `transaction`) ORDER BY `transaction`",
+            ).unwrap()
+            .query_map(rusqlite::params![], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?, row.get(5)?))).unwrap()
+            .collect::<Result<Vec<(String, String, u64, String, Option<String>, u64)>, rusqlite::Error>>().unwrap();
+        assert_eq!(rows, vec![
+            (String::from("0x01"), String::from("confirmed"), 39_390_000_000_000, String::from("receipt"), Some(String::from("29.54")), 0),
+            (String::from("0x02"), String::from("pending"), 1, String::from("payment"), None, 0),
+        ]);
+        let quote_currency: String = db_connection
+            .query_row("SELECT quote_currency FROM transaction_prices", rusqlite::params![], |row| row.get(0))
+            .unwrap();
+        assert_eq!(quote_currency, "usd");
+    }
+
+    #[test]
+    fn test_migrate_wallet_fingerprint() {
+        let mut db_connection = rusqlite::Connection::open_in_memory().unwrap();
+        run_migrations(&mut db_connection, &MIGRATIONS[..2]).unwrap();
+        db_connection.execute_batch(
+            "INSERT INTO wallet_transactions (`transaction`, tx_status, amount, created_at_time, flow, category)
+                VALUES ('0x01', 'confirmed', 1000, '2023-08-31T00:00:00Z', 'incoming', 'receipt');
+            INSERT INTO coins (coin_id, parent_coin_info, puzzle_hash, amount) VALUES ('0x02', '0x03', '0x04', 1000);",
+        ).unwrap();
+
+        run(&mut db_connection).unwrap();
+
+        // the same transaction can be stored for another wallet
+        db_connection.execute_batch(
+            "INSERT INTO wallet_transactions (wallet_fingerprint, `transaction`, tx_status, amount, created_at_time, flow, category)
+                VALUES (4121996123, '0x01', 'confirmed', 1000, '2023-08-31T00:00:00Z', 'internal', 'transfer');",
+        ).unwrap();
+        let fingerprints = db_connection
+            .prepare("SELECT wallet_fingerprint, amount FROM wallet_transactions ORDER BY wallet_fingerprint").unwrap()
+            .query_map(rusqlite::params![], |row| Ok((row.get(0)?, row.get(1)?))).unwrap()
+            .collect::<Result<Vec<(u32, u64)>, rusqlite::Error>>().unwrap();
+        assert_eq!(fingerprints, vec![(0, 1000), (4121996123, 1000)]);
+        let coin_fingerprint: u32 = db_connection
+            .query_row("SELECT wallet_fingerprint FROM coins WHERE coin_id = '0x02'", rusqlite::params![], |row| row.get(0))
+            .unwrap();
+        assert_eq!(coin_fingerprint, 0);
+    }
+
+    #[test]
+    fn test_failed_migration_is_rolled_back() {
+        let mut db_connection = rusqlite::Connection::open_in_memory().unwrap();
+        db_connection.execute_batch(
+            "CREATE TABLE wallet_transactions (
+                `transaction` TEXT PRIMARY KEY,
+                status BOOLEAN,
+                chia_amount TEXT,
+                to_address TEXT,
+                created_at_time TEXT,
+                flow TEXT,
+                usd_at_time TEXT
+            );
+            INSERT INTO wallet_transactions VALUES ('0x01', 1, 'not an amount', 'xch1test', '2023-08-31T00:00:00Z', 'incoming', NULL);",
+        ).unwrap();
+
+        assert!(run(&mut db_connection).is_err());
+        // the first migration went through, the amounts one didn't
+        assert_eq!(user_version(&db_connection), 1);
+        let chia_amount: String = db_connection
+            .query_row("SELECT chia_amount FROM wallet_transactions", rusqlite::params![], |row| row.get(0))
+            .unwrap();
+        assert_eq!(chia_amount, "not an amount");
+    }
+}
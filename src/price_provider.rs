@@ -0,0 +1,246 @@
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use async_trait::async_trait;
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::coin_gecko::CoinGecko;
+use crate::coin_paprika::CoinPaprika;
+use crate::prices::{PriceError, PricePoint};
+use crate::wallet_transactions::parse_created_at_time;
+use crate::Config;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PriceProviderKind {
+    /// api.coinpaprika.com
+    CoinPaprika,
+    /// api.coingecko.com
+    CoinGecko,
+    /// csv or json price file at `price_file_path`
+    File,
+}
+
+/// Resolution of the historical prices
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PriceGranularity {
+    /// The 00:00 UTC candle of the day
+    Daily,
+    /// The hourly candle closest to the transaction
+    Hourly,
+}
+
+impl PriceGranularity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PriceGranularity::Daily => "daily",
+            PriceGranularity::Hourly => "hourly",
+        }
+    }
+}
+
+#[async_trait]
+pub trait PriceProvider {
+    /// Stored as the source of the prices
+    fn name(&self) -> &'static str;
+
+    /// Current price of xch in `quote_currency`, a lowercase ISO 4217 code
+    async fn get_current_price(&self, quote_currency: &str) -> Result<Decimal, PriceError>;
+
+    /// Prices of xch in `quote_currency` from `start` on, daily ones at 00:00
+    /// UTC. Providers may return only the first day, and the finest prices
+    /// they have when they have none at `granularity`.
+    async fn get_historical_prices(
+        &self,
+        quote_currency: &str,
+        start: DateTime<Utc>,
+        granularity: PriceGranularity,
+    ) -> Result<Vec<PricePoint>, PriceError>;
+}
+
+/// The `price_providers` of the config in fallback order
+pub fn create_price_providers(config: &Config) -> Vec<Box<dyn PriceProvider + Send + Sync>> {
+    config.price_providers.iter()
+        .filter_map(|kind| -> Option<Box<dyn PriceProvider + Send + Sync>> {
+            match kind {
+                PriceProviderKind::CoinPaprika => Some(Box::new(CoinPaprika)),
+                PriceProviderKind::CoinGecko => Some(Box::new(CoinGecko)),
+                PriceProviderKind::File => config.price_file_path.as_ref().map(|path| -> Box<dyn PriceProvider + Send + Sync> {
+                    Box::new(FilePriceProvider::new(path))
+                }),
+            }
+        })
+        .collect()
+}
+
+/// Reads the prices from a local file, so the tracker can run without network
+/// access. The prices can be at any resolution, hourly lookups take the one
+/// closest to the transaction. A `.json` file is an array of `{"timestamp", "price"}` objects and
+/// anything else is csv with a `timestamp,price` header. Timestamps are RFC 3339
+/// or plain `YYYY-MM-DD` dates, the volume, market cap and quote currency
+/// columns are optional and prices without a quote currency are in usd.
+pub struct FilePriceProvider {
+    path: PathBuf,
+}
+
+#[derive(Debug, Deserialize)]
+struct FilePrice {
+    timestamp: String,
+    price: Decimal,
+    volume_24h: Option<Decimal>,
+    market_cap: Option<Decimal>,
+    quote_currency: Option<String>,
+}
+
+impl FilePriceProvider {
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self { path: path.as_ref().to_path_buf() }
+    }
+
+    /// Every price of the file in `quote_currency` in time order, read on each
+    /// call so the file can be updated while the tracker runs
+    fn read_prices(&self, quote_currency: &str) -> Result<Vec<PricePoint>, PriceError> {
+        let contents = std::fs::read_to_string(&self.path)?;
+        let file_prices = if self.path.extension().is_some_and(|extension| extension == "json") {
+            serde_json::from_str::<Vec<FilePrice>>(&contents)?
+        } else {
+            parse_csv(&contents)?
+        };
+
+        let mut price_points = file_prices.into_iter()
+            .filter(|file_price| {
+                file_price.quote_currency.as_deref().unwrap_or("usd").eq_ignore_ascii_case(quote_currency)
+            })
+            .map(|file_price| {
+                Ok(PricePoint {
+                    timestamp: parse_price_timestamp(&file_price.timestamp)?,
+                    price: file_price.price,
+                    volume_24h: file_price.volume_24h,
+                    market_cap: file_price.market_cap,
+                    source: String::from(self.name()),
+                })
+            })
+            .collect::<Result<Vec<PricePoint>, PriceError>>()?;
+        price_points.sort_by_key(|price_point| price_point.timestamp);
+
+        Ok(price_points)
+    }
+}
+
+#[async_trait]
+impl PriceProvider for FilePriceProvider {
+    fn name(&self) -> &'static str {
+        "file"
+    }
+
+    async fn get_current_price(&self, quote_currency: &str) -> Result<Decimal, PriceError> {
+        self.read_prices(quote_currency)?
+            .last()
+            .map(|price_point| price_point.price)
+            .ok_or_else(|| PriceError::UnsupportedQuoteCurrency(quote_currency.to_string()))
+    }
+
+    async fn get_historical_prices(
+        &self,
+        quote_currency: &str,
+        start: DateTime<Utc>,
+        _granularity: PriceGranularity,
+    ) -> Result<Vec<PricePoint>, PriceError> {
+        Ok(self.read_prices(quote_currency)?
+            .into_iter()
+            .filter(|price_point| price_point.timestamp >= start)
+            .collect())
+    }
+}
+
+fn parse_csv(contents: &str) -> Result<Vec<FilePrice>, PriceError> {
+    let mut lines = contents.lines().map(str::trim).filter(|line| !line.is_empty());
+    let header: Vec<&str> = lines.next().unwrap_or_default().split(',').map(str::trim).collect();
+    let column = |name: &str| header.iter().position(|column| *column == name);
+    let (Some(timestamp_column), Some(price_column)) = (column("timestamp"), column("price")) else {
+        return Err(PriceError::Parse(String::from("price csv needs timestamp and price columns")));
+    };
+    let volume_24h_column = column("volume_24h");
+    let market_cap_column = column("market_cap");
+    let quote_currency_column = column("quote_currency");
+
+    lines
+        .map(|line| {
+            let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+            let decimal = |column: Option<usize>| -> Result<Option<Decimal>, PriceError> {
+                match column.and_then(|column| fields.get(column)).filter(|field| !field.is_empty()) {
+                    Some(field) => Decimal::from_str(field)
+                        .map(Some)
+                        .map_err(|e| PriceError::Parse(format!("invalid price {}: {}", field, e))),
+                    None => Ok(None),
+                }
+            };
+
+            Ok(FilePrice {
+                timestamp: fields.get(timestamp_column).unwrap_or(&"").to_string(),
+                price: decimal(Some(price_column))?.ok_or_else(|| PriceError::Parse(format!("missing price in {}", line)))?,
+                volume_24h: decimal(volume_24h_column)?,
+                market_cap: decimal(market_cap_column)?,
+                quote_currency: quote_currency_column
+                    .and_then(|column| fields.get(column))
+                    .filter(|field| !field.is_empty())
+                    .map(|field| field.to_string()),
+            })
+        })
+        .collect()
+}
+
+fn parse_price_timestamp(timestamp: &str) -> Result<DateTime<Utc>, PriceError> {
+    if let Ok(date) = NaiveDate::parse_from_str(timestamp, "%Y-%m-%d") {
+        return Ok(Utc.from_utc_datetime(&date.and_hms_opt(0, 0, 0).unwrap()));
+    }
+
+    parse_created_at_time(timestamp).map_err(|e| PriceError::Parse(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_file_price_provider() {
+        let csv_path = std::env::temp_dir().join("file_price_provider_test.csv");
+        std::fs::write(&csv_path, "timestamp,price,volume_24h\n2023-09-01,30.1,\n2023-08-31T00:00:00Z,29.54,1520000.5\n").unwrap();
+        let json_path = std::env::temp_dir().join("file_price_provider_test.json");
+        std::fs::write(&json_path, r#"[
+            {"timestamp": "2023-08-31", "price": "29.54"},
+            {"timestamp": "2023-09-01", "price": "30.1"},
+            {"timestamp": "2023-09-01", "price": "27.8", "quote_currency": "eur"}
+        ]"#).unwrap();
+
+        for path in [csv_path, json_path] {
+            let provider = FilePriceProvider::new(&path);
+            assert_eq!(provider.get_current_price("usd").await.unwrap(), Decimal::from_str("30.1").unwrap());
+
+            let price_points = provider
+                .get_historical_prices("usd", parse_created_at_time("2023-09-01T00:00:00Z").unwrap(), PriceGranularity::Daily)
+                .await
+                .unwrap();
+            assert_eq!(price_points.len(), 1);
+            assert_eq!(price_points[0].price, Decimal::from_str("30.1").unwrap());
+            assert_eq!(price_points[0].source, "file");
+        }
+
+        let provider = FilePriceProvider::new(std::env::temp_dir().join("file_price_provider_test.json"));
+        assert_eq!(provider.get_current_price("eur").await.unwrap(), Decimal::from_str("27.8").unwrap());
+        assert!(matches!(provider.get_current_price("jpy").await, Err(PriceError::UnsupportedQuoteCurrency(_))));
+    }
+
+    #[tokio::test]
+    async fn test_invalid_price_file() {
+        let csv_path = std::env::temp_dir().join("file_price_provider_invalid_test.csv");
+        std::fs::write(&csv_path, "date,close\n2023-08-31,29.54\n").unwrap();
+        assert!(matches!(FilePriceProvider::new(&csv_path).get_current_price("usd").await, Err(PriceError::Parse(_))));
+
+        let missing = FilePriceProvider::new(std::env::temp_dir().join("file_price_provider_missing.csv"));
+        assert!(matches!(missing.get_current_price("usd").await, Err(PriceError::Io(_))));
+    }
+}
@@ -0,0 +1,355 @@
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use rusqlite::OptionalExtension;
+use rust_decimal::Decimal;
+
+use crate::price_provider::{PriceGranularity, PriceProvider};
+use crate::wallet_transactions::{format_created_at_time, parse_created_at_time};
+
+pub const XCH: &str = "xch";
+
+#[derive(Debug)]
+pub enum PriceError {
+    Http(reqwest::Error),
+    Json(serde_json::Error),
+    Io(std::io::Error),
+    Parse(String),
+    Db(rusqlite::Error),
+    /// The provider has no prices in the quote currency
+    UnsupportedQuoteCurrency(String),
+    /// Quote currency and time no provider has a price for
+    NotFound(String, String),
+}
+
+impl fmt::Display for PriceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PriceError::Http(e) => write!(f, "failed to fetch price: {}", e),
+            PriceError::Json(e) => write!(f, "failed to parse price: {}", e),
+            PriceError::Io(e) => write!(f, "failed to read prices: {}", e),
+            PriceError::Parse(e) => write!(f, "failed to parse price: {}", e),
+            PriceError::Db(e) => write!(f, "failed to access price cache: {}", e),
+            PriceError::UnsupportedQuoteCurrency(quote_currency) => write!(f, "no prices in {}", quote_currency),
+            PriceError::NotFound(quote_currency, date) => write!(f, "no price provider has a {} price at {}", quote_currency, date),
+        }
+    }
+}
+
+impl std::error::Error for PriceError {}
+
+impl From<reqwest::Error> for PriceError {
+    fn from(e: reqwest::Error) -> Self {
+        PriceError::Http(e)
+    }
+}
+
+impl From<serde_json::Error> for PriceError {
+    fn from(e: serde_json::Error) -> Self {
+        PriceError::Json(e)
+    }
+}
+
+impl From<std::io::Error> for PriceError {
+    fn from(e: std::io::Error) -> Self {
+        PriceError::Io(e)
+    }
+}
+
+impl From<rusqlite::Error> for PriceError {
+    fn from(e: rusqlite::Error) -> Self {
+        PriceError::Db(e)
+    }
+}
+
+/// One price of an asset, a row of the `prices` table
+#[derive(Debug, Clone, PartialEq)]
+pub struct PricePoint {
+    pub timestamp: DateTime<Utc>,
+    pub price: Decimal,
+    pub volume_24h: Option<Decimal>,
+    pub market_cap: Option<Decimal>,
+    pub source: String,
+}
+
+/// The cached price of `asset` in `quote_currency` at exactly `timestamp`
+pub fn load_price(
+    db_connection: &rusqlite::Connection,
+    asset: &str,
+    quote_currency: &str,
+    timestamp: &DateTime<Utc>,
+) -> Result<Option<Decimal>, rusqlite::Error> {
+    db_connection
+        .query_row(
+            "SELECT price FROM prices WHERE asset = ?1 AND quote_currency = ?2 AND timestamp = ?3",
+            rusqlite::params![asset, quote_currency, format_created_at_time(timestamp)],
+            |row| row.get::<_, String>(0),
+        )
+        .optional()?
+        .map(|price| {
+            Decimal::from_str(&price)
+                .map_err(|e| rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, e.into()))
+        })
+        .transpose()
+}
+
+/// The cached price of `asset` in `quote_currency` closest to `date`, at most
+/// `max_distance` away, together with its timestamp
+pub fn load_nearest_price(
+    db_connection: &rusqlite::Connection,
+    asset: &str,
+    quote_currency: &str,
+    date: &DateTime<Utc>,
+    max_distance: Duration,
+) -> Result<Option<(DateTime<Utc>, Decimal)>, rusqlite::Error> {
+    let mut stmt = db_connection.prepare(
+        "SELECT timestamp, price FROM prices
+        WHERE asset = ?1 AND quote_currency = ?2 AND timestamp BETWEEN ?3 AND ?4 ORDER BY timestamp",
+    )?;
+    let prices = stmt.query_map(
+        rusqlite::params![
+            asset,
+            quote_currency,
+            format_created_at_time(&(*date - max_distance)),
+            format_created_at_time(&(*date + max_distance)),
+        ],
+        |row| {
+            let timestamp = parse_created_at_time(&row.get::<_, String>(0)?)
+                .map_err(|e| rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, e.into()))?;
+            let price = Decimal::from_str(&row.get::<_, String>(1)?)
+                .map_err(|e| rusqlite::Error::FromSqlConversionFailure(1, rusqlite::types::Type::Text, e.into()))?;
+            Ok((timestamp, price))
+        },
+    )?
+    .collect::<Result<Vec<(DateTime<Utc>, Decimal)>, rusqlite::Error>>()?;
+
+    // the earlier one wins a tie
+    Ok(prices.into_iter().min_by_key(|(timestamp, _)| (*timestamp - *date).num_seconds().abs()))
+}
+
+/// Appends prices to the cache, prices already cached are kept
+pub fn save_prices(
+    db_connection: &rusqlite::Connection,
+    asset: &str,
+    quote_currency: &str,
+    price_points: &[PricePoint],
+) -> Result<(), rusqlite::Error> {
+    let mut stmt = db_connection.prepare(
+        "INSERT OR IGNORE INTO prices (asset, quote_currency, timestamp, price, volume_24h, market_cap, source)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+    )?;
+    for price_point in price_points {
+        stmt.execute(rusqlite::params![
+            asset,
+            quote_currency,
+            format_created_at_time(&price_point.timestamp),
+            price_point.price.normalize().to_string(),
+            price_point.volume_24h.map(|volume_24h| volume_24h.normalize().to_string()),
+            price_point.market_cap.map(|market_cap| market_cap.normalize().to_string()),
+            price_point.source,
+        ])?;
+    }
+
+    Ok(())
+}
+
+/// Price of xch in `quote_currency` at `date` and the time of the price, the
+/// `price_providers` are tried in order until one has it. Prices older than a
+/// day are read from the `prices` table: with hourly `granularity` the candle
+/// closest to `date`, falling back to the daily one when no provider has hourly
+/// prices, otherwise the daily candle of the day. Missing prices are fetched
+/// from that time on and cached.
+pub async fn get_xch_price_at_time(
+    date: DateTime<Utc>,
+    quote_currency: &str,
+    granularity: PriceGranularity,
+    db_connection: &rusqlite::Connection,
+    price_providers: &[Box<dyn PriceProvider + Send + Sync>],
+) -> Result<(DateTime<Utc>, Decimal), PriceError> {
+    // check if date transaction is just in range of 24 hour ago
+    if date > Utc::now() - Duration::days(1) {
+        for price_provider in price_providers {
+            println!("fetching {} price at {} from {}", quote_currency, date, price_provider.name());
+            match price_provider.get_current_price(quote_currency).await {
+                Ok(price) => return Ok((Utc::now(), price)),
+                Err(e) => println!("{}: {}", price_provider.name(), e),
+            }
+        }
+        return Err(PriceError::NotFound(quote_currency.to_string(), format_created_at_time(&date)));
+    }
+
+    if granularity == PriceGranularity::Hourly {
+        if let Some(price) = get_historical_price(date, quote_currency, PriceGranularity::Hourly, db_connection, price_providers).await? {
+            return Ok(price);
+        }
+        println!("no hourly {} price at {}, using the daily price", quote_currency, date);
+    }
+
+    get_historical_price(date, quote_currency, PriceGranularity::Daily, db_connection, price_providers)
+        .await?
+        .ok_or_else(|| PriceError::NotFound(quote_currency.to_string(), format_created_at_time(&date)))
+}
+
+/// The cached price of `date` at `granularity`, fetched from the first of the
+/// `price_providers` that has it when it isn't cached yet
+async fn get_historical_price(
+    date: DateTime<Utc>,
+    quote_currency: &str,
+    granularity: PriceGranularity,
+    db_connection: &rusqlite::Connection,
+    price_providers: &[Box<dyn PriceProvider + Send + Sync>],
+) -> Result<Option<(DateTime<Utc>, Decimal)>, PriceError> {
+    let day = Utc.from_utc_datetime(&date.date_naive().and_hms_opt(0, 0, 0).unwrap());
+    // hourly candles are at most half an hour away, an hour leaves room for gaps
+    let max_distance = Duration::hours(1);
+    let load_cached = || -> Result<Option<(DateTime<Utc>, Decimal)>, rusqlite::Error> {
+        match granularity {
+            PriceGranularity::Daily => Ok(load_price(db_connection, XCH, quote_currency, &day)?.map(|price| (day, price))),
+            PriceGranularity::Hourly => load_nearest_price(db_connection, XCH, quote_currency, &date, max_distance),
+        }
+    };
+    if let Some(price) = load_cached()? {
+        return Ok(Some(price));
+    }
+
+    let start = match granularity {
+        PriceGranularity::Daily => day,
+        PriceGranularity::Hourly => date - max_distance,
+    };
+    for price_provider in price_providers {
+        println!(
+            "fetching {} {} price history from {} from {}",
+            granularity.as_str(), quote_currency, format_created_at_time(&start), price_provider.name(),
+        );
+        match price_provider.get_historical_prices(quote_currency, start, granularity).await {
+            Ok(price_points) => save_prices(db_connection, XCH, quote_currency, &price_points)?,
+            Err(e) => println!("{}: {}", price_provider.name(), e),
+        }
+        if let Some(price) = load_cached()? {
+            return Ok(Some(price));
+        }
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::migrations;
+    use crate::price_provider::FilePriceProvider;
+    use crate::wallet_transactions::parse_created_at_time;
+
+    fn price_db() -> rusqlite::Connection {
+        let mut db_connection = rusqlite::Connection::open_in_memory().unwrap();
+        migrations::run(&mut db_connection).unwrap();
+        db_connection
+    }
+
+    #[tokio::test]
+    async fn test_time() {
+        let date = parse_created_at_time("2023-11-16 2:39:48").unwrap();
+
+        assert_eq!(date > Utc::now() - Duration::days(1), false);
+    }
+
+    #[tokio::test]
+    async fn test_get_xch_price_at_time() {
+        let db_connection = price_db();
+        let json_path = std::env::temp_dir().join("price_at_time_test.json");
+        std::fs::write(&json_path, r#"[{"timestamp": "2023-08-30", "price": "28.9"}, {"timestamp": "2023-08-31", "price": "29.54"}]"#).unwrap();
+        let date = parse_created_at_time("2023-08-31T00:00:00Z").unwrap();
+        let price_providers: Vec<Box<dyn PriceProvider + Send + Sync>> = vec![Box::new(FilePriceProvider::new(&json_path))];
+        let price = get_xch_price_at_time(date, "usd", PriceGranularity::Daily, &db_connection, &price_providers).await.unwrap();
+        assert_eq!(price, (date, Decimal::from_str("29.54").unwrap()));
+    }
+
+    #[tokio::test]
+    async fn test_cached_price() {
+        let db_connection = price_db();
+        let day = parse_created_at_time("2023-08-31T00:00:00Z").unwrap();
+        save_prices(&db_connection, XCH, "usd", &[PricePoint {
+            timestamp: day,
+            price: Decimal::from_str("29.540000").unwrap(),
+            volume_24h: Some(Decimal::from_str("1520000.5").unwrap()),
+            market_cap: None,
+            source: String::from("coinpaprika"),
+        }]).unwrap();
+        // cached prices aren't replaced
+        save_prices(&db_connection, XCH, "usd", &[PricePoint {
+            timestamp: day,
+            price: Decimal::from_str("1").unwrap(),
+            volume_24h: None,
+            market_cap: None,
+            source: String::from("coinpaprika"),
+        }]).unwrap();
+
+        // read from the cache without fetching
+        let date = parse_created_at_time("2023-08-31T15:20:00Z").unwrap();
+        let (timestamp, price) = get_xch_price_at_time(date, "usd", PriceGranularity::Daily, &db_connection, &[]).await.unwrap();
+        assert_eq!(timestamp, day);
+        assert_eq!(price.to_string(), "29.54");
+        // no hourly prices anywhere, the daily one is used
+        let (timestamp, _) = get_xch_price_at_time(date, "usd", PriceGranularity::Hourly, &db_connection, &[]).await.unwrap();
+        assert_eq!(timestamp, day);
+
+        let volume_24h: String = db_connection
+            .query_row("SELECT volume_24h FROM prices", rusqlite::params![], |row| row.get(0))
+            .unwrap();
+        assert_eq!(volume_24h, "1520000.5");
+    }
+
+    #[tokio::test]
+    async fn test_price_provider_fallback() {
+        let db_connection = price_db();
+        let csv_path = std::env::temp_dir().join("price_provider_fallback_test.csv");
+        std::fs::write(&csv_path, "timestamp,price,quote_currency\n2023-08-30,28.9,usd\n2023-08-31,29.54,usd\n2023-08-30,26.7,eur\n").unwrap();
+        let price_providers: Vec<Box<dyn PriceProvider + Send + Sync>> = vec![
+            Box::new(FilePriceProvider::new(std::env::temp_dir().join("price_provider_fallback_missing.csv"))),
+            Box::new(FilePriceProvider::new(&csv_path)),
+        ];
+
+        let date = parse_created_at_time("2023-08-30T12:00:00Z").unwrap();
+        let (_, price) = get_xch_price_at_time(date, "usd", PriceGranularity::Daily, &db_connection, &price_providers).await.unwrap();
+        assert_eq!(price, Decimal::from_str("28.9").unwrap());
+        // the following days are cached along
+        let next_day = parse_created_at_time("2023-08-31T00:00:00Z").unwrap();
+        assert_eq!(load_price(&db_connection, XCH, "usd", &next_day).unwrap(), Some(Decimal::from_str("29.54").unwrap()));
+
+        let date = parse_created_at_time("2023-09-05T12:00:00Z").unwrap();
+        let result = get_xch_price_at_time(date, "usd", PriceGranularity::Daily, &db_connection, &price_providers).await;
+        assert!(matches!(result, Err(PriceError::NotFound(quote_currency, date)) if quote_currency == "usd" && date == "2023-09-05T12:00:00Z"));
+
+        // every quote currency is cached on its own
+        let date = parse_created_at_time("2023-08-30T12:00:00Z").unwrap();
+        let (_, price) = get_xch_price_at_time(date, "eur", PriceGranularity::Daily, &db_connection, &price_providers).await.unwrap();
+        assert_eq!(price, Decimal::from_str("26.7").unwrap());
+        let result = get_xch_price_at_time(date, "gbp", PriceGranularity::Daily, &db_connection, &price_providers).await;
+        assert!(matches!(result, Err(PriceError::NotFound(quote_currency, _)) if quote_currency == "gbp"));
+    }
+
+    #[tokio::test]
+    async fn test_hourly_price() {
+        let db_connection = price_db();
+        let csv_path = std::env::temp_dir().join("price_provider_hourly_test.csv");
+        std::fs::write(
+            &csv_path,
+            "timestamp,price\n2023-08-31,29.54\n2023-08-31T14:00:00Z,31.2\n2023-08-31T15:00:00Z,32.8\n2023-08-31T16:00:00Z,30.9\n",
+        ).unwrap();
+        let price_providers: Vec<Box<dyn PriceProvider + Send + Sync>> = vec![Box::new(FilePriceProvider::new(&csv_path))];
+
+        // the closest candle, not the one of the day
+        let date = parse_created_at_time("2023-08-31T15:20:00Z").unwrap();
+        let price = get_xch_price_at_time(date, "usd", PriceGranularity::Hourly, &db_connection, &price_providers).await.unwrap();
+        assert_eq!(price, (parse_created_at_time("2023-08-31T15:00:00Z").unwrap(), Decimal::from_str("32.8").unwrap()));
+        let date = parse_created_at_time("2023-08-31T15:40:00Z").unwrap();
+        let price = get_xch_price_at_time(date, "usd", PriceGranularity::Hourly, &db_connection, &price_providers).await.unwrap();
+        assert_eq!(price, (parse_created_at_time("2023-08-31T16:00:00Z").unwrap(), Decimal::from_str("30.9").unwrap()));
+
+        // the daily price when there's no candle within an hour
+        let date = parse_created_at_time("2023-08-31T20:00:00Z").unwrap();
+        let price = get_xch_price_at_time(date, "usd", PriceGranularity::Hourly, &db_connection, &price_providers).await.unwrap();
+        assert_eq!(price, (parse_created_at_time("2023-08-31T00:00:00Z").unwrap(), Decimal::from_str("29.54").unwrap()));
+    }
+}
@@ -0,0 +1,313 @@
+use std::collections::{HashMap, HashSet};
+
+use chrono::{Datelike, Months};
+use rust_decimal::Decimal;
+
+use crate::cost_basis::{LotAssignment, Transfer};
+use crate::wallet_transactions::{mojos_to_xch, Flow, TxStatus, WalletTransaction};
+
+/// Csv layout of `export tax`
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum TaxFormat {
+    /// IRS Form 8949 sales and dispositions, one row per lot
+    #[value(name = "form-8949")]
+    Form8949,
+    /// Koinly universal format
+    Koinly,
+    /// CoinTracker csv import
+    #[value(name = "cointracker")]
+    CoinTracker,
+}
+
+impl TaxFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TaxFormat::Form8949 => "form-8949",
+            TaxFormat::Koinly => "koinly",
+            TaxFormat::CoinTracker => "cointracker",
+        }
+    }
+}
+
+/// Disposals of `tax_year` in the layout of Form 8949. Lots held longer than
+/// a year are long term, disposals beyond the tracked lots are acquired
+/// `VARIOUS`. Transfers between the tracked wallets aren't disposals, only
+/// their fee is.
+pub fn form_8949_csv(assignments: &[LotAssignment], tax_year: i32) -> String {
+    let mut csv = csv_row(&[
+        "Description of property",
+        "Date acquired",
+        "Date sold or disposed of",
+        "Proceeds",
+        "Cost or other basis",
+        "Gain or (loss)",
+        "Term",
+    ]);
+
+    let mut assignments: Vec<&LotAssignment> = assignments.iter()
+        .filter(|assignment| assignment.disposed_at.year() == tax_year)
+        .collect();
+    assignments.sort_by_key(|assignment| (assignment.disposed_at, assignment.acquired_at));
+    for assignment in assignments {
+        let long_term = assignment.acquired_at.is_some_and(|acquired_at| {
+            acquired_at.checked_add_months(Months::new(12)).is_some_and(|one_year| assignment.disposed_at > one_year)
+        });
+
+        csv.push_str(&csv_row(&[
+            &format!("{} XCH", mojos_to_xch(assignment.amount)),
+            &assignment.acquired_at.map_or(String::from("VARIOUS"), |acquired_at| acquired_at.format("%m/%d/%Y").to_string()),
+            &assignment.disposed_at.format("%m/%d/%Y").to_string(),
+            &fiat_amount(assignment.proceeds),
+            &fiat_amount(assignment.cost_basis),
+            &fiat_amount(assignment.gain()),
+            if long_term { "long" } else { "short" },
+        ]));
+    }
+
+    csv
+}
+
+/// Transactions of `tax_year` in the Koinly universal format, farming rewards
+/// are labelled as mining income worth their value in `quote_currency`. A
+/// transfer between the tracked wallets is one row with both of its sides.
+pub fn koinly_csv(
+    wallet_transactions: &[WalletTransaction],
+    transfers: &HashMap<[u8; 32], Transfer>,
+    quote_currency: &str,
+    tax_year: i32,
+) -> String {
+    let mut csv = csv_row(&[
+        "Date",
+        "Sent Amount",
+        "Sent Currency",
+        "Received Amount",
+        "Received Currency",
+        "Fee Amount",
+        "Fee Currency",
+        "Net Worth Amount",
+        "Net Worth Currency",
+        "Label",
+        "Description",
+        "TxHash",
+    ]);
+
+    for (wallet_transaction, transfer) in taxable_transactions(wallet_transactions, transfers, tax_year) {
+        let (sent, received, fee) = amounts(wallet_transaction, transfer);
+        let net_worth = wallet_transaction.fiat_at_time.get(quote_currency)
+            .filter(|_| wallet_transaction.flow != Flow::Internal && transfer.is_none())
+            .map(|price| fiat_amount(wallet_transaction.chia_amount() * price))
+            .unwrap_or_default();
+        let net_worth_currency = if net_worth.is_empty() { String::new() } else { quote_currency.to_uppercase() };
+
+        csv.push_str(&csv_row(&[
+            &wallet_transaction.created_at_time.format("%Y-%m-%d %H:%M:%S UTC").to_string(),
+            &sent,
+            if sent.is_empty() { "" } else { "XCH" },
+            &received,
+            if received.is_empty() { "" } else { "XCH" },
+            &fee,
+            if fee.is_empty() { "" } else { "XCH" },
+            &net_worth,
+            &net_worth_currency,
+            if wallet_transaction.category.is_farming_reward() { "mining" } else { "" },
+            if transfer.is_some() { "transfer" } else { wallet_transaction.category.as_str() },
+            &wallet_transaction.transaction_id(),
+        ]));
+    }
+
+    csv
+}
+
+/// Transactions of `tax_year` in the CoinTracker csv import format, which
+/// prices them itself. Farming rewards are tagged as mined income, a transfer
+/// between the tracked wallets is one row with both of its sides.
+pub fn cointracker_csv(wallet_transactions: &[WalletTransaction], transfers: &HashMap<[u8; 32], Transfer>, tax_year: i32) -> String {
+    let mut csv = csv_row(&[
+        "Date",
+        "Received Quantity",
+        "Received Currency",
+        "Sent Quantity",
+        "Sent Currency",
+        "Fee Amount",
+        "Fee Currency",
+        "Tag",
+    ]);
+
+    for (wallet_transaction, transfer) in taxable_transactions(wallet_transactions, transfers, tax_year) {
+        let (sent, received, fee) = amounts(wallet_transaction, transfer);
+
+        csv.push_str(&csv_row(&[
+            &wallet_transaction.created_at_time.format("%m/%d/%Y %H:%M:%S").to_string(),
+            &received,
+            if received.is_empty() { "" } else { "XCH" },
+            &sent,
+            if sent.is_empty() { "" } else { "XCH" },
+            &fee,
+            if fee.is_empty() { "" } else { "XCH" },
+            if wallet_transaction.category.is_farming_reward() { "mined" } else { "" },
+        ]));
+    }
+
+    csv
+}
+
+/// Value in `quote_currency` of the farming rewards received in `tax_year`,
+/// the rewards without a price aren't counted
+pub fn farming_income(wallet_transactions: &[WalletTransaction], quote_currency: &str, tax_year: i32) -> Decimal {
+    taxable_transactions(wallet_transactions, &HashMap::new(), tax_year)
+        .filter(|(wallet_transaction, _)| wallet_transaction.category.is_farming_reward())
+        .filter_map(|(wallet_transaction, _)| {
+            wallet_transaction.fiat_at_time.get(quote_currency).map(|price| wallet_transaction.chia_amount() * price)
+        })
+        .sum()
+}
+
+/// Confirmed transactions of `tax_year` in time order with their transfer
+/// between the tracked wallets, internal transactions only when they paid a
+/// fee. A transfer is only there once when both of its sides are.
+fn taxable_transactions<'a>(
+    wallet_transactions: &'a [WalletTransaction],
+    transfers: &'a HashMap<[u8; 32], Transfer>,
+    tax_year: i32,
+) -> impl Iterator<Item = (&'a WalletTransaction, Option<&'a Transfer>)> {
+    let mut taxable: Vec<&WalletTransaction> = wallet_transactions.iter()
+        .filter(|wallet_transaction| wallet_transaction.status == TxStatus::Confirmed)
+        .filter(|wallet_transaction| wallet_transaction.created_at_time.year() == tax_year)
+        .filter(|wallet_transaction| wallet_transaction.flow != Flow::Internal || wallet_transaction.fee_amount > 0)
+        .collect();
+    taxable.sort_by_key(|wallet_transaction| wallet_transaction.created_at_time);
+
+    let mut seen_transfers = HashSet::new();
+    taxable.into_iter()
+        .map(|wallet_transaction| (wallet_transaction, transfers.get(&wallet_transaction.transaction)))
+        .filter(move |(wallet_transaction, transfer)| transfer.is_none() || seen_transfers.insert(wallet_transaction.transaction))
+}
+
+/// Sent, received and fee amount in xch, empty when there's none
+fn amounts(wallet_transaction: &WalletTransaction, transfer: Option<&Transfer>) -> (String, String, String) {
+    if let Some(transfer) = transfer {
+        let fee = if transfer.fee == 0 { String::new() } else { mojos_to_xch(transfer.fee).to_string() };
+        return (mojos_to_xch(transfer.sent).to_string(), mojos_to_xch(transfer.received).to_string(), fee);
+    }
+
+    let (sent, received) = match wallet_transaction.flow {
+        Flow::Incoming => (String::new(), wallet_transaction.chia_amount().to_string()),
+        Flow::Outgoing => (wallet_transaction.chia_amount().to_string(), String::new()),
+        Flow::Internal => (String::new(), String::new()),
+    };
+    let fee = if wallet_transaction.fee_amount == 0 || wallet_transaction.flow == Flow::Incoming {
+        String::new()
+    } else {
+        wallet_transaction.chia_fee_amount().to_string()
+    };
+
+    (sent, received, fee)
+}
+
+/// Rounded to cents
+fn fiat_amount(amount: Decimal) -> String {
+    format!("{:.2}", amount.round_dp(2))
+}
+
+/// Joins the fields into a csv line, quoting the ones that need it
+pub fn csv_row(fields: &[&str]) -> String {
+    let fields: Vec<String> = fields.iter()
+        .map(|field| {
+            if field.contains([',', '"', '\n']) {
+                format!("\"{}\"", field.replace('"', "\"\""))
+            } else {
+                field.to_string()
+            }
+        })
+        .collect();
+
+    format!("{}\n", fields.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::str::FromStr;
+
+    use super::*;
+    use crate::cost_basis::{calculate_cost_bases, find_transfers, CostBasisMethod};
+    use crate::wallet_transactions::tests::wallet_transaction;
+    use crate::wallet_transactions::TxCategory;
+
+    fn wallet_transactions() -> Vec<WalletTransaction> {
+        let priced_transaction = |id: u8, flow: Flow, category: TxCategory, amount: u64, price: &str, created_at_time: &str| WalletTransaction {
+            category,
+            fiat_at_time: BTreeMap::from([(String::from("usd"), Decimal::from_str(price).unwrap())]),
+            ..wallet_transaction(id, flow, amount, created_at_time)
+        };
+
+        let mut wallet_transactions = vec![
+            priced_transaction(1, Flow::Incoming, TxCategory::PoolReward, 1_750_000_000_000, "40", "2022-03-01T10:00:00Z"),
+            priced_transaction(2, Flow::Incoming, TxCategory::FarmerReward, 250_000_000_000, "30", "2023-02-01T10:00:00Z"),
+            priced_transaction(3, Flow::Outgoing, TxCategory::Payment, 1_000_000_000_000, "50", "2023-06-01T10:00:00Z"),
+            priced_transaction(4, Flow::Incoming, TxCategory::Receipt, 1_000_000_000_000, "20", "2024-01-01T10:00:00Z"),
+        ];
+        wallet_transactions[2].fee_amount = 1_000_000;
+        wallet_transactions
+    }
+
+    #[test]
+    fn test_form_8949_csv() {
+        let cost_bases = calculate_cost_bases(&[&wallet_transactions()], "usd", CostBasisMethod::Fifo).unwrap();
+
+        assert_eq!(form_8949_csv(&cost_bases[0].assignments, 2023), concat!(
+            "Description of property,Date acquired,Date sold or disposed of,Proceeds,Cost or other basis,Gain or (loss),Term\n",
+            "1.000001 XCH,03/01/2022,06/01/2023,50.00,40.00,10.00,long\n",
+        ));
+        assert_eq!(form_8949_csv(&cost_bases[0].assignments, 2022).lines().count(), 1);
+    }
+
+    #[test]
+    fn test_koinly_csv() {
+        let csv = koinly_csv(&wallet_transactions(), &HashMap::new(), "usd", 2023);
+        assert_eq!(csv.lines().collect::<Vec<&str>>(), vec![
+            "Date,Sent Amount,Sent Currency,Received Amount,Received Currency,Fee Amount,Fee Currency,Net Worth Amount,Net Worth Currency,Label,Description,TxHash",
+            &format!("2023-02-01 10:00:00 UTC,,,0.25,XCH,,,7.50,USD,mining,farmer_reward,0x{}", hex::encode([2u8; 32])),
+            &format!("2023-06-01 10:00:00 UTC,1,XCH,,,0.000001,XCH,50.00,USD,,payment,0x{}", hex::encode([3u8; 32])),
+        ]);
+    }
+
+    #[test]
+    fn test_cointracker_csv() {
+        let csv = cointracker_csv(&wallet_transactions(), &HashMap::new(), 2022);
+        assert_eq!(csv, concat!(
+            "Date,Received Quantity,Received Currency,Sent Quantity,Sent Currency,Fee Amount,Fee Currency,Tag\n",
+            "03/01/2022 10:00:00,1.75,XCH,,,,,mined\n",
+        ));
+        assert_eq!(farming_income(&wallet_transactions(), "usd", 2022), Decimal::from(70));
+    }
+
+    #[test]
+    fn test_transfer_between_wallets() {
+        // the payment of 1 xch went to another tracked wallet
+        let sender = wallet_transactions();
+        let receiver = vec![WalletTransaction { category: TxCategory::Receipt, ..wallet_transaction(3, Flow::Incoming, 1_000_000_000_000, "2023-06-01T10:00:00Z") }];
+        let transfers = find_transfers(&[&sender, &receiver]);
+        let w_txs: Vec<WalletTransaction> = sender.iter().chain(&receiver).cloned().collect();
+
+        let cost_bases = calculate_cost_bases(&[&sender, &receiver], "usd", CostBasisMethod::Fifo).unwrap();
+        assert_eq!(form_8949_csv(&cost_bases[0].assignments, 2023), concat!(
+            "Description of property,Date acquired,Date sold or disposed of,Proceeds,Cost or other basis,Gain or (loss),Term\n",
+            "0.000001 XCH,03/01/2022,06/01/2023,0.00,0.00,0.00,long\n",
+        ));
+        assert!(cost_bases[1].assignments.is_empty());
+
+        let csv = koinly_csv(&w_txs, &transfers, "usd", 2023);
+        assert_eq!(csv.lines().nth(2), Some(format!("2023-06-01 10:00:00 UTC,1,XCH,1,XCH,0.000001,XCH,,,,transfer,0x{}", hex::encode([3u8; 32])).as_str()));
+        assert_eq!(csv.lines().count(), 3);
+
+        let csv = cointracker_csv(&w_txs, &transfers, 2023);
+        assert_eq!(csv.lines().nth(2), Some("06/01/2023 10:00:00,1,XCH,1,XCH,0.000001,XCH,"));
+        assert_eq!(csv.lines().count(), 3);
+    }
+
+    #[test]
+    fn test_csv_row() {
+        assert_eq!(csv_row(&["a", "b,c", "say \"hi\""]), "a,\"b,c\",\"say \"\"hi\"\"\"\n");
+    }
+}
@@ -0,0 +1,232 @@
+use chrono::NaiveDate;
+use serde_json::{json, Map, Value};
+
+use crate::tax_export::csv_row;
+use crate::wallet_transactions::{format_created_at_time, Flow, TxCategory, TxStatus, WalletTransaction};
+
+/// File format of `export transactions`
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum TransactionFormat {
+    Csv,
+    Json,
+}
+
+impl TransactionFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TransactionFormat::Csv => "csv",
+            TransactionFormat::Json => "json",
+        }
+    }
+}
+
+/// Filters of `list` and `export transactions`, every transaction passes
+/// the filters that aren't given
+#[derive(clap::Args, Debug, Default, Clone)]
+pub struct TransactionFilter {
+    /// incoming, outgoing or internal
+    #[arg(long)]
+    pub flow: Option<Flow>,
+    /// pending, confirmed, dropped or reorged
+    #[arg(long)]
+    pub status: Option<TxStatus>,
+    /// receipt, payment, transfer, pool_reward, farmer_reward or fee_reward
+    #[arg(long)]
+    pub category: Option<TxCategory>,
+    /// First day of the transactions, YYYY-MM-DD in UTC
+    #[arg(long)]
+    pub since: Option<NaiveDate>,
+    /// Last day of the transactions, YYYY-MM-DD in UTC
+    #[arg(long)]
+    pub until: Option<NaiveDate>,
+}
+
+impl TransactionFilter {
+    pub fn matches(&self, wallet_transaction: &WalletTransaction) -> bool {
+        let day = wallet_transaction.created_at_time.date_naive();
+        self.flow.map_or(true, |flow| wallet_transaction.flow == flow)
+            && self.status.map_or(true, |status| wallet_transaction.status == status)
+            && self.category.map_or(true, |category| wallet_transaction.category == category)
+            && self.since.map_or(true, |since| day >= since)
+            && self.until.map_or(true, |until| day <= until)
+    }
+}
+
+/// A stored transaction and the label of its wallet
+pub struct ListedTransaction {
+    pub wallet: String,
+    pub wallet_transaction: WalletTransaction,
+}
+
+/// The transactions passing `filter` in time order
+pub fn filter_transactions(transactions: Vec<ListedTransaction>, filter: &TransactionFilter) -> Vec<ListedTransaction> {
+    let mut transactions: Vec<ListedTransaction> = transactions.into_iter()
+        .filter(|transaction| filter.matches(&transaction.wallet_transaction))
+        .collect();
+    transactions.sort_by_key(|transaction| (transaction.wallet_transaction.created_at_time, transaction.wallet_transaction.transaction));
+    transactions
+}
+
+pub fn print_transactions(transactions: &[ListedTransaction], quote_currencies: &[String]) {
+    let mut header = format!(
+        "{:<20}  {:<12}  {:<9}  {:<8}  {:<13}  {:>18}  {:>10}",
+        "created at", "wallet", "status", "flow", "category", "amount", "fee"
+    );
+    for quote_currency in quote_currencies {
+        header.push_str(&format!("  {:>10}", format!("{} price", quote_currency)));
+    }
+    header.push_str("  transaction");
+    println!("{}", header);
+
+    for transaction in transactions {
+        let wallet_transaction = &transaction.wallet_transaction;
+        let mut line = format!(
+            "{:<20}  {:<12}  {:<9}  {:<8}  {:<13}  {:>18}  {:>10}",
+            wallet_transaction.created_at_time.format("%Y-%m-%d %H:%M:%S"),
+            transaction.wallet,
+            wallet_transaction.status.as_str(),
+            wallet_transaction.flow.as_str(),
+            wallet_transaction.category.as_str(),
+            wallet_transaction.chia_amount(),
+            wallet_transaction.chia_fee_amount(),
+        );
+        for quote_currency in quote_currencies {
+            let price = wallet_transaction.fiat_at_time.get(quote_currency).map_or(String::from("-"), |price| price.to_string());
+            line.push_str(&format!("  {:>10}", price));
+        }
+        line.push_str(&format!("  {}", wallet_transaction.transaction_id()));
+        println!("{}", line);
+    }
+}
+
+/// One line per transaction with a price column per quote currency, the
+/// times are UTC
+pub fn transactions_csv(transactions: &[ListedTransaction], quote_currencies: &[String]) -> String {
+    let mut header = vec![
+        String::from("transaction"),
+        String::from("wallet"),
+        String::from("created_at_time"),
+        String::from("status"),
+        String::from("flow"),
+        String::from("category"),
+        String::from("amount"),
+        String::from("fee_amount"),
+        String::from("to_address"),
+        String::from("confirmed_at_height"),
+    ];
+    header.extend(quote_currencies.iter().map(|quote_currency| format!("{}_price", quote_currency)));
+    let mut csv = csv_row(&header.iter().map(String::as_str).collect::<Vec<&str>>());
+
+    for transaction in transactions {
+        let wallet_transaction = &transaction.wallet_transaction;
+        let mut fields = vec![
+            wallet_transaction.transaction_id(),
+            transaction.wallet.clone(),
+            format_created_at_time(&wallet_transaction.created_at_time),
+            wallet_transaction.status.as_str().to_string(),
+            wallet_transaction.flow.as_str().to_string(),
+            wallet_transaction.category.as_str().to_string(),
+            wallet_transaction.chia_amount().to_string(),
+            wallet_transaction.chia_fee_amount().to_string(),
+            wallet_transaction.to_address.clone().unwrap_or_default(),
+            wallet_transaction.confirmed_at_height.map(|height| height.to_string()).unwrap_or_default(),
+        ];
+        fields.extend(quote_currencies.iter().map(|quote_currency| {
+            wallet_transaction.fiat_at_time.get(quote_currency).map(|price| price.to_string()).unwrap_or_default()
+        }));
+        csv.push_str(&csv_row(&fields.iter().map(String::as_str).collect::<Vec<&str>>()));
+    }
+
+    csv
+}
+
+/// Array of the transactions, amounts and prices are strings so they keep
+/// every digit
+pub fn transactions_json(transactions: &[ListedTransaction]) -> String {
+    let transactions: Vec<Value> = transactions.iter()
+        .map(|transaction| {
+            let wallet_transaction = &transaction.wallet_transaction;
+            let prices: Map<String, Value> = wallet_transaction.fiat_at_time.iter()
+                .map(|(quote_currency, price)| (quote_currency.clone(), Value::String(price.to_string())))
+                .collect();
+            json!({
+                "transaction": wallet_transaction.transaction_id(),
+                "wallet": transaction.wallet,
+                "created_at_time": format_created_at_time(&wallet_transaction.created_at_time),
+                "status": wallet_transaction.status.as_str(),
+                "flow": wallet_transaction.flow.as_str(),
+                "category": wallet_transaction.category.as_str(),
+                "amount": wallet_transaction.chia_amount().to_string(),
+                "fee_amount": wallet_transaction.chia_fee_amount().to_string(),
+                "to_address": wallet_transaction.to_address,
+                "confirmed_at_height": wallet_transaction.confirmed_at_height,
+                "prices": prices,
+            })
+        })
+        .collect();
+
+    format!("{}\n", serde_json::to_string_pretty(&transactions).expect("failed to serialize transactions"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::str::FromStr;
+
+    use rust_decimal::Decimal;
+
+    use super::*;
+    use crate::wallet_transactions::tests::wallet_transaction;
+
+    fn transactions() -> Vec<ListedTransaction> {
+        let listed_transaction = |id: u8, flow: Flow, category: TxCategory, created_at_time: &str| WalletTransaction {
+            to_address: Some(String::from("xch1test")),
+            category,
+            fiat_at_time: BTreeMap::from([(String::from("usd"), Decimal::from_str("29.54").unwrap())]),
+            confirmed_at_height: Some(100),
+            ..wallet_transaction(id, flow, 1_750_000_000_000, created_at_time)
+        };
+
+        vec![
+            ListedTransaction {
+                wallet: String::from("cold storage"),
+                wallet_transaction: listed_transaction(2, Flow::Outgoing, TxCategory::Payment, "2023-09-01T10:00:00Z"),
+            },
+            ListedTransaction {
+                wallet: String::from("farming"),
+                wallet_transaction: listed_transaction(1, Flow::Incoming, TxCategory::PoolReward, "2023-08-31T10:00:00Z"),
+            },
+        ]
+    }
+
+    #[test]
+    fn test_filter_transactions() {
+        let filtered = filter_transactions(transactions(), &TransactionFilter::default());
+        assert_eq!(filtered.iter().map(|transaction| transaction.wallet_transaction.transaction[0]).collect::<Vec<u8>>(), vec![1, 2]);
+
+        let filter = TransactionFilter { flow: Some(Flow::Incoming), ..Default::default() };
+        assert_eq!(filter_transactions(transactions(), &filter).len(), 1);
+        let filter = TransactionFilter { since: NaiveDate::from_ymd_opt(2023, 9, 1), ..Default::default() };
+        assert_eq!(filter_transactions(transactions(), &filter)[0].wallet_transaction.transaction, [2u8; 32]);
+        let filter = TransactionFilter { until: NaiveDate::from_ymd_opt(2023, 8, 30), ..Default::default() };
+        assert!(filter_transactions(transactions(), &filter).is_empty());
+    }
+
+    #[test]
+    fn test_transactions_csv_and_json() {
+        let transactions = filter_transactions(transactions(), &TransactionFilter::default());
+
+        let csv = transactions_csv(&transactions, &[String::from("usd"), String::from("eur")]);
+        assert_eq!(csv.lines().collect::<Vec<&str>>(), vec![
+            "transaction,wallet,created_at_time,status,flow,category,amount,fee_amount,to_address,confirmed_at_height,usd_price,eur_price",
+            &format!("0x{},farming,2023-08-31T10:00:00Z,confirmed,incoming,pool_reward,1.75,0,xch1test,100,29.54,", hex::encode([1u8; 32])),
+            &format!("0x{},cold storage,2023-09-01T10:00:00Z,confirmed,outgoing,payment,1.75,0,xch1test,100,29.54,", hex::encode([2u8; 32])),
+        ]);
+
+        let json: Value = serde_json::from_str(&transactions_json(&transactions)).unwrap();
+        assert_eq!(json[1]["wallet"], "cold storage");
+        assert_eq!(json[1]["amount"], "1.75");
+        assert_eq!(json[1]["prices"]["usd"], "29.54");
+        assert_eq!(json[1]["confirmed_at_height"], 100);
+    }
+}
@@ -0,0 +1,212 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::chia_rpc::RpcError;
+use crate::full_node_rpc::FullNodeRpcClient;
+use crate::wallet_cmnds::WalletCommands;
+use crate::wallet_rpc::WalletRpcClient;
+use crate::wallet_transactions::RawWalletTransaction;
+use crate::{Config, WalletConfig};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransactionSourceKind {
+    /// `chia wallet get_transactions` of the custom chia-blockchain
+    Cli,
+    /// `get_transactions` of the wallet daemon rpc
+    WalletRpc,
+    /// `get_coin_records_by_puzzle_hashes` of a stock full node
+    FullNodeRpc,
+    /// get_transactions json saved to a file, or a directory of them replayed in order
+    Json,
+}
+
+#[derive(Debug)]
+pub enum SourceError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    Rpc(RpcError),
+    Command(String),
+}
+
+impl fmt::Display for SourceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SourceError::Io(e) => write!(f, "{}", e),
+            SourceError::Json(e) => write!(f, "failed to parse transactions: {}", e),
+            SourceError::Rpc(e) => write!(f, "{}", e),
+            SourceError::Command(e) => write!(f, "get_transactions command failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for SourceError {}
+
+impl From<std::io::Error> for SourceError {
+    fn from(e: std::io::Error) -> Self {
+        SourceError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for SourceError {
+    fn from(e: serde_json::Error) -> Self {
+        SourceError::Json(e)
+    }
+}
+
+impl From<RpcError> for SourceError {
+    fn from(e: RpcError) -> Self {
+        SourceError::Rpc(e)
+    }
+}
+
+#[async_trait]
+pub trait TransactionSource {
+    /// Returns every transaction of the wallet. Sources that can't list the
+    /// wallet transactions by themselves look up `wallet_addresses`.
+    async fn get_wallet_transactions(&self, wallet_addresses: &[String]) -> Result<Vec<RawWalletTransaction>, SourceError>;
+}
+
+/// Creates the source of one of the configured wallets
+pub fn create_transaction_source<'a>(
+    config: &'a Config,
+    wallet: &WalletConfig,
+) -> Result<Box<dyn TransactionSource + Send + Sync + 'a>, SourceError> {
+    Ok(match config.source {
+        TransactionSourceKind::Cli => Box::new(WalletCommands::new(config, wallet.wallet_fingerprint)),
+        TransactionSourceKind::WalletRpc => Box::new(WalletRpcClient::new(config, wallet.wallet_fingerprint)?),
+        TransactionSourceKind::FullNodeRpc => Box::new(FullNodeRpcClient::new(config)?),
+        TransactionSourceKind::Json => {
+            let path = wallet.json_source_path.as_ref().or(config.json_source_path.as_ref()).ok_or_else(|| {
+                SourceError::Io(std::io::Error::new(std::io::ErrorKind::NotFound, "json_source_path is not set in config.toml file"))
+            })?;
+            Box::new(JsonFileSource::new(path)?)
+        }
+    })
+}
+
+/// Reads the transactions from `get_transactions` json saved to disk. When
+/// given a directory, every poll returns the next file in name order and the
+/// last one keeps being returned once the replay is over.
+pub struct JsonFileSource {
+    files: Vec<PathBuf>,
+    next_file: AtomicUsize,
+}
+
+impl JsonFileSource {
+    pub fn new(path: impl AsRef<Path>) -> Result<Self, SourceError> {
+        let path = path.as_ref();
+        let files = if path.is_dir() {
+            let mut files = std::fs::read_dir(path)?
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<Result<Vec<PathBuf>, std::io::Error>>()?;
+            files.retain(|file| file.extension().is_some_and(|extension| extension == "json"));
+            files.sort();
+            files
+        } else {
+            vec![path.to_path_buf()]
+        };
+
+        if files.is_empty() {
+            return Err(SourceError::Io(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("no json files in {}", path.display()),
+            )));
+        }
+
+        Ok(Self { files, next_file: AtomicUsize::new(0) })
+    }
+}
+
+#[async_trait]
+impl TransactionSource for JsonFileSource {
+    async fn get_wallet_transactions(&self, _wallet_addresses: &[String]) -> Result<Vec<RawWalletTransaction>, SourceError> {
+        let index = self.next_file.fetch_add(1, Ordering::SeqCst).min(self.files.len() - 1);
+        let json = tokio::fs::read_to_string(&self.files[index]).await?;
+        Ok(serde_json::from_str::<Vec<RawWalletTransaction>>(&json)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chia_observer_wallet_generator::{derive_wallet, G1Element};
+    use hex::FromHex;
+
+    use super::*;
+    use crate::wallet_transactions;
+    use crate::wallet_transactions_save::WalletTransactionsSave;
+
+    #[tokio::test]
+    async fn test_json_file_replay() {
+        let replay_dir = std::env::temp_dir().join("json_file_source_replay_test");
+        std::fs::create_dir_all(&replay_dir).unwrap();
+        std::fs::write(replay_dir.join("1.json"), r#"[{"transaction": "0x01", "status": false}]"#).unwrap();
+        std::fs::write(replay_dir.join("2.json"), r#"[{"transaction": "0x01", "status": true}]"#).unwrap();
+        std::fs::write(replay_dir.join("notes.txt"), "not a snapshot").unwrap();
+
+        let source = JsonFileSource::new(&replay_dir).unwrap();
+        let statuses = [
+            source.get_wallet_transactions(&[]).await.unwrap()[0].status,
+            source.get_wallet_transactions(&[]).await.unwrap()[0].status,
+            source.get_wallet_transactions(&[]).await.unwrap()[0].status,
+        ];
+        assert_eq!(statuses, [Some(false), Some(true), Some(true)]);
+    }
+
+    #[tokio::test]
+    async fn test_json_source_to_db() {
+        let mut config = Config::default();
+        config.check_count = 5;
+        config.db_path = String::from("/tmp");
+        config.db_name = String::from("transaction_source_test.db");
+
+        let pk = G1Element::from_bytes(&<[u8; 48]>::from_hex(&config.wallet_public_key).unwrap()).unwrap();
+        let wallet_addresses = derive_wallet::generate_multiple_observe_wallet_addresses(&pk, 0, &config.check_count);
+
+        let json_path = std::env::temp_dir().join("transaction_source_test.json");
+        std::fs::write(&json_path, serde_json::json!([
+            {
+                "transaction": "0x0000000000000000000000000000000000000000000000000000000000000001",
+                "status": true,
+                "chia_amount": "1.75",
+                "to_address": wallet_addresses[1],
+                "created_at_time": "2023-08-31 10:00:00",
+            },
+            {
+                "transaction": "0x0000000000000000000000000000000000000000000000000000000000000002",
+                "status": true,
+                "chia_amount": "0.5",
+                "to_address": "xch1external",
+                "created_at_time": "2023-09-01 10:00:00",
+            },
+        ]).to_string()).unwrap();
+        config.source = TransactionSourceKind::Json;
+        config.json_source_path = Some(json_path.display().to_string());
+
+        let wallet = config.wallets().remove(0);
+        let source = create_transaction_source(&config, &wallet).unwrap();
+        let raw_w_txs = source.get_wallet_transactions(&wallet_addresses).await.unwrap();
+        let w_txs = wallet_transactions::process_raw_transactions(&raw_w_txs, &wallet_addresses);
+
+        std::fs::remove_file(format!("{}/{}", config.db_path, config.db_name)).ok();
+        let db_connection = rusqlite::Connection::open(format!("{}/{}", config.db_path, config.db_name)).unwrap();
+
+        let mut w_trans_saver = WalletTransactionsSave::new(&config);
+        w_trans_saver.save_to_db(wallet.wallet_fingerprint, &w_txs).await.expect("failed to save to db");
+
+        let mut stmt = db_connection.prepare("SELECT `transaction`, flow FROM wallet_transactions ORDER BY `transaction`").unwrap();
+        let rows = stmt.query_map(rusqlite::params![], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))
+            .unwrap()
+            .collect::<Result<Vec<(String, String)>, rusqlite::Error>>()
+            .unwrap();
+
+        assert_eq!(rows, vec![
+            (String::from("0x0000000000000000000000000000000000000000000000000000000000000001"), String::from("incoming")),
+            (String::from("0x0000000000000000000000000000000000000000000000000000000000000002"), String::from("outgoing")),
+        ]);
+    }
+}
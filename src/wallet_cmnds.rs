@@ -1,28 +1,42 @@
-use std::process::Command;
+use async_trait::async_trait;
+use tokio::process::Command;
+use crate::transaction_source::{SourceError, TransactionSource};
 use crate::wallet_transactions::RawWalletTransaction;
 use super::Config;
 
 pub struct WalletCommands<'a> {
-    config: &'a Config
+    config: &'a Config,
+    wallet_fingerprint: u32,
 }
 
 impl <'a> WalletCommands<'a> {
-    pub fn new(config: &'a Config) -> Self {
-        Self { config }
+    pub fn new(config: &'a Config, wallet_fingerprint: u32) -> Self {
+        Self { config, wallet_fingerprint }
     }
 
-    pub fn get_wallet_transactions(&self) -> Vec<RawWalletTransaction> {
+    pub async fn get_wallet_transactions(&self) -> Result<Vec<RawWalletTransaction>, SourceError> {
         let output = Command::new("bash")
             .arg("-c")
             .arg(format!(
                 ". {}/activate && chia wallet get_transactions --print-json -f {}",
                 self.config.chia_blockchain_path,
-                self.config.wallet_fingerprint
+                self.wallet_fingerprint
             ))
             .output()
-            .expect("failed to execute get_transactions command");
+            .await?;
+
+        if !output.status.success() {
+            return Err(SourceError::Command(String::from_utf8_lossy(&output.stderr).trim().to_string()));
+        }
 
         let output = String::from_utf8_lossy(&output.stdout);
-        serde_json::from_str::<Vec<RawWalletTransaction>>(&output).unwrap_or(Vec::new())
+        Ok(serde_json::from_str::<Vec<RawWalletTransaction>>(&output)?)
     }
-}
\ No newline at end of file
+}
+
+#[async_trait]
+impl TransactionSource for WalletCommands<'_> {
+    async fn get_wallet_transactions(&self, _wallet_addresses: &[String]) -> Result<Vec<RawWalletTransaction>, SourceError> {
+        WalletCommands::get_wallet_transactions(self).await
+    }
+}
@@ -0,0 +1,191 @@
+use std::path::Path;
+
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::chia_rpc::{ChiaRpcClient, RpcError};
+use crate::transaction_source::{SourceError, TransactionSource};
+use crate::wallet_transactions::{local_time_string, mojos_to_chia_amount, RawCoin, RawMemos, RawWalletTransaction};
+use crate::Config;
+
+const STANDARD_WALLET_ID: u32 = 1;
+const TRANSACTIONS_PAGE_SIZE: usize = 500;
+
+#[derive(Debug, Deserialize)]
+struct GetTransactionsResponse {
+    transactions: Vec<TransactionRecord>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TransactionRecord {
+    name: String,
+    confirmed: bool,
+    amount: u64,
+    to_address: Option<String>,
+    created_at_time: i64,
+    #[serde(rename = "type")]
+    transaction_type: u32,
+    fee_amount: u64,
+    confirmed_at_height: u32,
+    additions: Vec<RawCoin>,
+    removals: Vec<RawCoin>,
+    memos: Option<RawMemos>,
+    spend_bundle: Option<serde_json::Value>,
+}
+
+impl From<TransactionRecord> for RawWalletTransaction {
+    fn from(record: TransactionRecord) -> Self {
+        Self {
+            transaction: Some(record.name),
+            status: Some(record.confirmed),
+            chia_amount: Some(mojos_to_chia_amount(record.amount)),
+            to_address: record.to_address,
+            created_at_time: local_time_string(record.created_at_time),
+            transaction_type: Some(record.transaction_type),
+            fee_amount: Some(record.fee_amount),
+            confirmed_at_height: Some(record.confirmed_at_height),
+            additions: Some(record.additions),
+            removals: Some(record.removals),
+            memos: record.memos,
+            spend_bundle: record.spend_bundle,
+        }
+    }
+}
+
+/// Reads the wallet transactions straight from the wallet daemon RPC
+/// instead of going through the chia cli.
+pub struct WalletRpcClient {
+    wallet_fingerprint: u32,
+    rpc: ChiaRpcClient,
+}
+
+impl WalletRpcClient {
+    pub fn new(config: &Config, wallet_fingerprint: u32) -> Result<Self, RpcError> {
+        let ssl_dir = Path::new(&config.chia_root).join("config/ssl/wallet");
+        let rpc = ChiaRpcClient::new(
+            &config.wallet_rpc_host,
+            config.wallet_rpc_port,
+            &ssl_dir.join("private_wallet.crt"),
+            &ssl_dir.join("private_wallet.key"),
+        )?;
+
+        Ok(Self { wallet_fingerprint, rpc })
+    }
+
+    pub async fn get_wallet_transactions(&self) -> Result<Vec<RawWalletTransaction>, RpcError> {
+        self.rpc
+            .request::<serde_json::Value>("log_in", json!({ "fingerprint": self.wallet_fingerprint }))
+            .await?;
+
+        let mut records: Vec<TransactionRecord> = Vec::new();
+        loop {
+            let response: GetTransactionsResponse = self.rpc
+                .request("get_transactions", json!({
+                    "wallet_id": STANDARD_WALLET_ID,
+                    "start": records.len(),
+                    "end": records.len() + TRANSACTIONS_PAGE_SIZE,
+                }))
+                .await?;
+
+            let page_len = response.transactions.len();
+            records.extend(response.transactions);
+            if page_len < TRANSACTIONS_PAGE_SIZE {
+                break;
+            }
+        }
+
+        Ok(records.into_iter().map(RawWalletTransaction::from).collect())
+    }
+}
+
+#[async_trait]
+impl TransactionSource for WalletRpcClient {
+    async fn get_wallet_transactions(&self, _wallet_addresses: &[String]) -> Result<Vec<RawWalletTransaction>, SourceError> {
+        Ok(WalletRpcClient::get_wallet_transactions(self).await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::chia_rpc::test_server;
+
+    #[tokio::test]
+    async fn test_get_wallet_transactions() {
+        let chia_root = std::env::temp_dir().join("wallet_rpc_test");
+        let ca_der = test_server::write_private_certs(&chia_root, "wallet");
+
+        let responses = HashMap::from([
+            ("log_in", json!({ "success": true, "fingerprint": 4121996123u32 })),
+            ("get_transactions", json!({
+                "success": true,
+                "wallet_id": 1,
+                "transactions": [{
+                    "name": "0x3f1c2a9e0d6b4c8f7a5e2d1b0c9f8e7d6a5b4c3d2e1f0a9b8c7d6e5f4a3b2c1d",
+                    "confirmed": true,
+                    "amount": 39390000000000u64,
+                    "to_address": "xch1test",
+                    "created_at_time": 1700102388,
+                    "type": 0,
+                    "fee_amount": 0,
+                    "confirmed_at_height": 4512345,
+                    "additions": [{
+                        "parent_coin_info": "0x0202020202020202020202020202020202020202020202020202020202020202",
+                        "puzzle_hash": "0x0303030303030303030303030303030303030303030303030303030303030303",
+                        "amount": 39390000000000u64,
+                    }],
+                    "removals": [],
+                    "memos": {},
+                    "spend_bundle": null,
+                }],
+            })),
+        ]);
+        let (port, requests) = test_server::serve(ca_der, responses).await;
+
+        let mut config = Config::default();
+        config.chia_root = chia_root.display().to_string();
+        config.wallet_rpc_host = String::from("127.0.0.1");
+        config.wallet_rpc_port = port;
+
+        let client = WalletRpcClient::new(&config, config.wallet_fingerprint).unwrap();
+        let raw_w_txs = client.get_wallet_transactions().await.unwrap();
+
+        assert_eq!(raw_w_txs.len(), 1);
+        assert_eq!(raw_w_txs[0].transaction.as_deref(), Some("0x3f1c2a9e0d6b4c8f7a5e2d1b0c9f8e7d6a5b4c3d2e1f0a9b8c7d6e5f4a3b2c1d"));
+        assert_eq!(raw_w_txs[0].status, Some(true));
+        assert_eq!(raw_w_txs[0].chia_amount.as_deref(), Some("39.39"));
+        assert_eq!(raw_w_txs[0].to_address.as_deref(), Some("xch1test"));
+        assert_eq!(raw_w_txs[0].confirmed_at_height, Some(4512345));
+        assert_eq!(raw_w_txs[0].additions.as_ref().unwrap()[0].amount, 39390000000000);
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests[0].0, "log_in");
+        assert_eq!(requests[0].1["fingerprint"], json!(config.wallet_fingerprint));
+        assert_eq!(requests[1].0, "get_transactions");
+        assert_eq!(requests[1].1["wallet_id"], json!(1));
+    }
+
+    #[tokio::test]
+    async fn test_rpc_error() {
+        let chia_root = std::env::temp_dir().join("wallet_rpc_error_test");
+        let ca_der = test_server::write_private_certs(&chia_root, "wallet");
+        let responses = HashMap::from([
+            ("log_in", json!({ "success": false, "error": "fingerprint not found" })),
+        ]);
+        let (port, _) = test_server::serve(ca_der, responses).await;
+
+        let mut config = Config::default();
+        config.chia_root = chia_root.display().to_string();
+        config.wallet_rpc_host = String::from("127.0.0.1");
+        config.wallet_rpc_port = port;
+
+        let client = WalletRpcClient::new(&config, config.wallet_fingerprint).unwrap();
+        match client.get_wallet_transactions().await {
+            Err(RpcError::Rpc(e)) => assert_eq!(e, "log_in: fingerprint not found"),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
@@ -1,86 +1,742 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
 
-use chia_observer_wallet_generator::G1Element;
+use chrono::{DateTime, Local, NaiveDateTime, SecondsFormat, TimeZone, Utc};
+use hex::FromHex;
 use rust_decimal::Decimal;
 use serde::Deserialize;
+use sha2::{Digest, Sha256};
 
-use crate::Config;
-use crate::derive_wallet::generate_observe_wallet_address;
+use crate::block_rewards;
 
-#[derive(Debug, Deserialize)]
+const MOJOS_PER_XCH: i64 = 1_000_000_000_000;
+const CLI_TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+#[derive(Debug, Default, Deserialize)]
 pub struct RawWalletTransaction {
     pub transaction: Option<String>,
     pub status: Option<bool>,
     pub chia_amount: Option<String>,
     pub to_address: Option<String>,
     pub created_at_time: Option<String>,
+    #[serde(rename = "type")]
+    pub transaction_type: Option<u32>,
+    pub fee_amount: Option<u64>,
+    pub confirmed_at_height: Option<u32>,
+    pub additions: Option<Vec<RawCoin>>,
+    pub removals: Option<Vec<RawCoin>>,
+    pub memos: Option<RawMemos>,
+    pub spend_bundle: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RawCoin {
+    pub parent_coin_info: String,
+    pub puzzle_hash: String,
+    pub amount: u64,
+}
+
+/// Memos by coin id, the rpc returns them as a map and older clis as a list
+/// of pairs.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum RawMemos {
+    Map(HashMap<String, Vec<String>>),
+    List(Vec<(String, Vec<String>)>),
+}
+
+/// Formats a mojo amount as the xch `chia_amount` of the cli output.
+pub fn mojos_to_chia_amount(mojos: u64) -> String {
+    mojos_to_xch(mojos).to_string()
+}
+
+/// Formats a unix timestamp as the local `created_at_time` of the cli output.
+pub fn local_time_string(timestamp: i64) -> Option<String> {
+    Local
+        .timestamp_opt(timestamp, 0)
+        .single()
+        .map(|time| time.format(CLI_TIME_FORMAT).to_string())
+}
+
+#[derive(Debug)]
+pub enum TransactionError {
+    MissingField(&'static str),
+    InvalidTransactionId(String),
+    InvalidAmount(String),
+    InvalidTime(String),
+    InvalidFlow(String),
+    InvalidCategory(String),
+    InvalidStatus(String),
+    InvalidBytes(String),
+}
+
+impl fmt::Display for TransactionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TransactionError::MissingField(field) => write!(f, "transaction is missing {}", field),
+            TransactionError::InvalidTransactionId(id) => write!(f, "invalid transaction id {}", id),
+            TransactionError::InvalidAmount(amount) => write!(f, "invalid chia amount {}", amount),
+            TransactionError::InvalidTime(time) => write!(f, "invalid created at time {}", time),
+            TransactionError::InvalidFlow(flow) => write!(f, "invalid flow {}", flow),
+            TransactionError::InvalidCategory(category) => write!(f, "invalid category {}", category),
+            TransactionError::InvalidStatus(status) => write!(f, "invalid status {}", status),
+            TransactionError::InvalidBytes(bytes) => write!(f, "invalid hex bytes {}", bytes),
+        }
+    }
+}
+
+impl std::error::Error for TransactionError {}
+
+/// The chia wallet TransactionType enum
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransactionType {
+    IncomingTx,
+    OutgoingTx,
+    CoinbaseReward,
+    FeeReward,
+    IncomingTrade,
+    OutgoingTrade,
+    IncomingClawbackReceive,
+    IncomingClawbackSend,
+    OutgoingClawback,
+    /// A type added to the chia wallet after these, like INCOMING_CRCAT_PENDING
+    Unknown(u32),
+}
+
+impl TransactionType {
+    pub fn from_u32(transaction_type: u32) -> Self {
+        match transaction_type {
+            0 => TransactionType::IncomingTx,
+            1 => TransactionType::OutgoingTx,
+            2 => TransactionType::CoinbaseReward,
+            3 => TransactionType::FeeReward,
+            4 => TransactionType::IncomingTrade,
+            5 => TransactionType::OutgoingTrade,
+            6 => TransactionType::IncomingClawbackReceive,
+            7 => TransactionType::IncomingClawbackSend,
+            8 => TransactionType::OutgoingClawback,
+            _ => TransactionType::Unknown(transaction_type),
+        }
+    }
+
+    pub fn as_u32(&self) -> u32 {
+        match self {
+            TransactionType::IncomingTx => 0,
+            TransactionType::OutgoingTx => 1,
+            TransactionType::CoinbaseReward => 2,
+            TransactionType::FeeReward => 3,
+            TransactionType::IncomingTrade => 4,
+            TransactionType::OutgoingTrade => 5,
+            TransactionType::IncomingClawbackReceive => 6,
+            TransactionType::IncomingClawbackSend => 7,
+            TransactionType::OutgoingClawback => 8,
+            TransactionType::Unknown(transaction_type) => *transaction_type,
+        }
+    }
+
+    /// Whether the transaction was sent by the wallet
+    pub fn is_sent(&self) -> bool {
+        matches!(
+            self,
+            TransactionType::OutgoingTx | TransactionType::OutgoingTrade | TransactionType::OutgoingClawback
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Coin {
+    pub parent_coin_info: [u8; 32],
+    pub puzzle_hash: [u8; 32],
+    /// Amount in mojos
+    pub amount: u64,
+}
+
+impl Coin {
+    pub fn from_raw(raw_coin: &RawCoin) -> Result<Self, TransactionError> {
+        Ok(Self {
+            parent_coin_info: parse_bytes32(&raw_coin.parent_coin_info)?,
+            puzzle_hash: parse_bytes32(&raw_coin.puzzle_hash)?,
+            amount: raw_coin.amount,
+        })
+    }
+
+    /// The coin id is the hash of the parent coin id, the puzzle hash and the
+    /// amount encoded as a minimal big endian clvm integer.
+    pub fn coin_id(&self) -> [u8; 32] {
+        let amount_bytes = self.amount.to_be_bytes();
+        let mut amount_bytes = amount_bytes[amount_bytes.iter().position(|b| *b != 0).unwrap_or(8)..].to_vec();
+        if amount_bytes.first().is_some_and(|b| b & 0x80 != 0) {
+            amount_bytes.insert(0, 0);
+        }
+
+        let mut hasher = Sha256::new();
+        hasher.update(self.parent_coin_info);
+        hasher.update(self.puzzle_hash);
+        hasher.update(amount_bytes);
+        hasher.finalize().into()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Memo {
+    pub coin_id: [u8; 32],
+    pub memo: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flow {
+    Incoming,
+    Outgoing,
+    /// Sent from one of our addresses to another one, like change or a self
+    /// transfer. It doesn't change the balance.
+    Internal,
 }
 
-#[derive(Debug, Deserialize, Clone, PartialEq)]
+impl Flow {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Flow::Incoming => "incoming",
+            Flow::Outgoing => "outgoing",
+            Flow::Internal => "internal",
+        }
+    }
+
+    /// Classifies a transaction by who sent it and where it went. Without a
+    /// known `type` anything sent to one of our addresses is taken as incoming.
+    pub fn classify(raw_transaction: &RawWalletTransaction, wallet_addresses: &HashSet<&str>) -> Self {
+        let to_wallet_address = raw_transaction.to_address.as_deref()
+            .is_some_and(|to_address| wallet_addresses.contains(to_address));
+
+        match raw_transaction.transaction_type.map(TransactionType::from_u32) {
+            Some(TransactionType::Unknown(_)) | None if to_wallet_address => Flow::Incoming,
+            Some(TransactionType::Unknown(_)) | None => Flow::Outgoing,
+            Some(transaction_type) if transaction_type.is_sent() && to_wallet_address => Flow::Internal,
+            Some(transaction_type) if transaction_type.is_sent() => Flow::Outgoing,
+            Some(_) => Flow::Incoming,
+        }
+    }
+}
+
+impl FromStr for Flow {
+    type Err = TransactionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "incoming" => Ok(Flow::Incoming),
+            "outgoing" => Ok(Flow::Outgoing),
+            "internal" => Ok(Flow::Internal),
+            _ => Err(TransactionError::InvalidFlow(s.to_string())),
+        }
+    }
+}
+
+/// What the transaction was for, on top of its flow
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TxCategory {
+    /// Ordinary payment received
+    Receipt,
+    Payment,
+    Transfer,
+    /// Pool share of a block reward
+    PoolReward,
+    /// Farmer share of a block reward
+    FarmerReward,
+    /// Farmer reward coin that also collected the fees of the block
+    FeeReward,
+}
+
+impl TxCategory {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TxCategory::Receipt => "receipt",
+            TxCategory::Payment => "payment",
+            TxCategory::Transfer => "transfer",
+            TxCategory::PoolReward => "pool_reward",
+            TxCategory::FarmerReward => "farmer_reward",
+            TxCategory::FeeReward => "fee_reward",
+        }
+    }
+
+    /// Block rewards, taxed as income when they're received
+    pub fn is_farming_reward(&self) -> bool {
+        matches!(self, TxCategory::PoolReward | TxCategory::FarmerReward | TxCategory::FeeReward)
+    }
+
+    /// Rewards are told apart by the chia `type`. A FEE_REWARD coin holds the
+    /// base farmer reward plus the fees of the block, so it's a fee reward
+    /// when it's worth more than the base farmer reward of the rewarded block.
+    pub fn classify(
+        transaction_type: Option<TransactionType>,
+        flow: Flow,
+        amount: u64,
+        confirmed_at_height: Option<u32>,
+        additions: &[Coin],
+    ) -> Self {
+        match transaction_type {
+            Some(TransactionType::CoinbaseReward) => TxCategory::PoolReward,
+            Some(TransactionType::FeeReward) => {
+                let rewarded_height = additions.iter()
+                    .find_map(|coin| block_rewards::reward_coin(&coin.parent_coin_info))
+                    .map(|(_, height)| height)
+                    .or(confirmed_at_height);
+
+                match rewarded_height {
+                    Some(height) if amount > block_rewards::calculate_base_farmer_reward(height) => TxCategory::FeeReward,
+                    _ => TxCategory::FarmerReward,
+                }
+            }
+            _ => match flow {
+                Flow::Incoming => TxCategory::Receipt,
+                Flow::Outgoing => TxCategory::Payment,
+                Flow::Internal => TxCategory::Transfer,
+            },
+        }
+    }
+}
+
+impl FromStr for TxCategory {
+    type Err = TransactionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "receipt" => Ok(TxCategory::Receipt),
+            "payment" => Ok(TxCategory::Payment),
+            "transfer" => Ok(TxCategory::Transfer),
+            "pool_reward" => Ok(TxCategory::PoolReward),
+            "farmer_reward" => Ok(TxCategory::FarmerReward),
+            "fee_reward" => Ok(TxCategory::FeeReward),
+            _ => Err(TransactionError::InvalidCategory(s.to_string())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TxStatus {
+    Pending,
+    Confirmed,
+    /// Was pending and left the mempool without confirming
+    Dropped,
+    /// Was confirmed and the block got reorged out
+    Reorged,
+}
+
+impl TxStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TxStatus::Pending => "pending",
+            TxStatus::Confirmed => "confirmed",
+            TxStatus::Dropped => "dropped",
+            TxStatus::Reorged => "reorged",
+        }
+    }
+
+    pub fn is_confirmed(&self) -> bool {
+        *self == TxStatus::Confirmed
+    }
+}
+
+impl FromStr for TxStatus {
+    type Err = TransactionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pending" => Ok(TxStatus::Pending),
+            "confirmed" => Ok(TxStatus::Confirmed),
+            "dropped" => Ok(TxStatus::Dropped),
+            "reorged" => Ok(TxStatus::Reorged),
+            _ => Err(TransactionError::InvalidStatus(s.to_string())),
+        }
+    }
+}
+
+impl From<bool> for TxStatus {
+    fn from(confirmed: bool) -> Self {
+        if confirmed { TxStatus::Confirmed } else { TxStatus::Pending }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct WalletTransaction {
-    pub transaction: Option<String>,
-    pub status: Option<bool>,
-    pub chia_amount: Option<String>,
+    pub transaction: [u8; 32],
+    pub status: TxStatus,
+    /// Amount in mojos
+    pub amount: u64,
     pub to_address: Option<String>,
-    pub created_at_time: Option<String>,
-    pub flow: Option<String>,
-    pub usd_at_time: Option<Decimal>,
+    pub created_at_time: DateTime<Utc>,
+    pub flow: Flow,
+    pub category: TxCategory,
+    /// Xch price at the time of the transaction per quote currency
+    pub fiat_at_time: BTreeMap<String, Decimal>,
+    pub transaction_type: Option<TransactionType>,
+    /// Fee in mojos
+    pub fee_amount: u64,
+    pub confirmed_at_height: Option<u32>,
+    pub additions: Vec<Coin>,
+    pub removals: Vec<Coin>,
+    pub memos: Vec<Memo>,
+    /// Spend bundle json
+    pub spend_bundle: Option<String>,
+}
+
+impl WalletTransaction {
+    pub fn from_raw(raw_transaction: &RawWalletTransaction, flow: Flow) -> Result<Self, TransactionError> {
+        let mut wallet_transaction = Self {
+            transaction: parse_transaction_id(
+                raw_transaction.transaction.as_ref().ok_or(TransactionError::MissingField("transaction"))?,
+            )?,
+            status: raw_transaction.status.ok_or(TransactionError::MissingField("status"))?.into(),
+            amount: chia_amount_to_mojos(
+                raw_transaction.chia_amount.as_ref().ok_or(TransactionError::MissingField("chia_amount"))?,
+            )?,
+            to_address: raw_transaction.to_address.clone(),
+            created_at_time: parse_created_at_time(
+                raw_transaction.created_at_time.as_ref().ok_or(TransactionError::MissingField("created_at_time"))?,
+            )?,
+            flow,
+            category: TxCategory::Receipt,
+            fiat_at_time: BTreeMap::new(),
+            transaction_type: raw_transaction.transaction_type.map(TransactionType::from_u32),
+            fee_amount: raw_transaction.fee_amount.unwrap_or(0),
+            confirmed_at_height: raw_transaction.confirmed_at_height.filter(|height| *height > 0),
+            additions: raw_transaction.additions.iter().flatten().map(Coin::from_raw).collect::<Result<_, _>>()?,
+            removals: raw_transaction.removals.iter().flatten().map(Coin::from_raw).collect::<Result<_, _>>()?,
+            memos: match &raw_transaction.memos {
+                Some(RawMemos::Map(memos)) => parse_memos(memos.iter())?,
+                Some(RawMemos::List(memos)) => parse_memos(memos.iter().map(|(coin_id, memos)| (coin_id, memos)))?,
+                None => Vec::new(),
+            },
+            spend_bundle: raw_transaction.spend_bundle.as_ref()
+                .filter(|spend_bundle| !spend_bundle.is_null())
+                .map(|spend_bundle| spend_bundle.to_string()),
+        };
+        wallet_transaction.category = TxCategory::classify(
+            wallet_transaction.transaction_type,
+            flow,
+            wallet_transaction.amount,
+            wallet_transaction.confirmed_at_height,
+            &wallet_transaction.additions,
+        );
+
+        Ok(wallet_transaction)
+    }
+
+    pub fn chia_fee_amount(&self) -> Decimal {
+        mojos_to_xch(self.fee_amount)
+    }
+
+    pub fn transaction_id(&self) -> String {
+        format!("0x{}", hex::encode(self.transaction))
+    }
+
+    pub fn chia_amount(&self) -> Decimal {
+        mojos_to_xch(self.amount)
+    }
+}
+
+pub fn parse_transaction_id(transaction: &str) -> Result<[u8; 32], TransactionError> {
+    <[u8; 32]>::from_hex(transaction.trim_start_matches("0x"))
+        .map_err(|_| TransactionError::InvalidTransactionId(transaction.to_string()))
+}
+
+pub fn parse_bytes32(bytes: &str) -> Result<[u8; 32], TransactionError> {
+    <[u8; 32]>::from_hex(bytes.trim_start_matches("0x"))
+        .map_err(|_| TransactionError::InvalidBytes(bytes.to_string()))
 }
 
-pub fn process_raw_transactions(raw_transactions: &Vec<RawWalletTransaction>, wallet_adresses: &mut Vec<String>, config: &Config, pk: &G1Element) -> Vec<WalletTransaction> {
-    let mut wallet_transactions_hashmap: HashMap<String, WalletTransaction> = HashMap::new();
+fn parse_memos<'a>(memos: impl Iterator<Item = (&'a String, &'a Vec<String>)>) -> Result<Vec<Memo>, TransactionError> {
+    let mut parsed_memos = Vec::new();
+    for (coin_id, coin_memos) in memos {
+        let coin_id = parse_bytes32(coin_id)?;
+        for memo in coin_memos {
+            parsed_memos.push(Memo {
+                coin_id,
+                memo: hex::decode(memo.trim_start_matches("0x")).map_err(|_| TransactionError::InvalidBytes(memo.clone()))?,
+            });
+        }
+    }
+    parsed_memos.sort_by(|a, b| (a.coin_id, &a.memo).cmp(&(b.coin_id, &b.memo)));
+    Ok(parsed_memos)
+}
+
+pub fn mojos_to_xch(mojos: u64) -> Decimal {
+    Decimal::from_i128_with_scale(mojos.into(), 12).normalize()
+}
+
+/// Parses an xch amount like "39.39" to mojos, rejecting negative amounts
+/// and amounts finer than a mojo.
+pub fn chia_amount_to_mojos(chia_amount: &str) -> Result<u64, TransactionError> {
+    let invalid_amount = || TransactionError::InvalidAmount(chia_amount.to_string());
+    let mojos = Decimal::from_str(chia_amount.trim())
+        .ok()
+        .and_then(|xch| xch.checked_mul(Decimal::from(MOJOS_PER_XCH)))
+        .ok_or_else(invalid_amount)?;
+
+    if mojos.is_sign_negative() || !mojos.fract().is_zero() {
+        return Err(invalid_amount());
+    }
+    u64::try_from(mojos).map_err(|_| invalid_amount())
+}
+
+/// Parses the local time of the cli output ("2023-11-16 2:39:48") or an
+/// RFC 3339 time as stored in the database.
+pub fn parse_created_at_time(created_at_time: &str) -> Result<DateTime<Utc>, TransactionError> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(created_at_time) {
+        return Ok(time.with_timezone(&Utc));
+    }
+
+    NaiveDateTime::parse_from_str(created_at_time, CLI_TIME_FORMAT)
+        .ok()
+        .and_then(|time| Local.from_local_datetime(&time).earliest())
+        .map(|time| time.with_timezone(&Utc))
+        .ok_or_else(|| TransactionError::InvalidTime(created_at_time.to_string()))
+}
+
+pub fn format_created_at_time(created_at_time: &DateTime<Utc>) -> String {
+    created_at_time.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// Parses the raw transactions of a poll, a record that can't be parsed is
+/// skipped with a warning so that it doesn't hold up the others
+pub fn process_raw_transactions(raw_transactions: &Vec<RawWalletTransaction>, wallet_adresses: &[String]) -> Vec<WalletTransaction> {
+    let mut wallet_transaction_ids: HashSet<[u8; 32]> = HashSet::new();
     let mut wallet_transactions: Vec<WalletTransaction> = Vec::new();
-    let mut new_wallet_adresses: Vec<String> = Vec::new();
-
-    for (i, wallet_address) in wallet_adresses.iter().enumerate() {
-        for raw_transaction in raw_transactions {
-            if raw_transaction.to_address == Some(wallet_address.clone()) {
-                let w_tx = WalletTransaction {
-                    transaction: raw_transaction.transaction.clone(),
-                    status: raw_transaction.status.clone(),
-                    chia_amount: raw_transaction.chia_amount.clone(),
-                    to_address: raw_transaction.to_address.clone(),
-                    created_at_time: raw_transaction.created_at_time.clone(),
-                    flow: Some(String::from("incoming")),
-                    usd_at_time: None,
-                };
- 
-                wallet_transactions_hashmap.insert(raw_transaction.transaction.as_ref().unwrap().clone(), w_tx.clone());
-                wallet_transactions.push(w_tx);
-
-                let address_len_offset : u32 = (wallet_adresses.len() - i).try_into().unwrap();
-                if address_len_offset < config.check_count.try_into().unwrap() {
-                    new_wallet_adresses.push(generate_observe_wallet_address(pk, config.check_count + address_len_offset));
-                }
-            }
-            else if !wallet_transactions_hashmap.contains_key(raw_transaction.transaction.as_ref().unwrap()) {
-                let w_tx = WalletTransaction {
-                    transaction: raw_transaction.transaction.clone(),
-                    status: raw_transaction.status.clone(),
-                    chia_amount: raw_transaction.chia_amount.clone(),
-                    to_address: raw_transaction.to_address.clone(),
-                    created_at_time: raw_transaction.created_at_time.clone(),
-                    flow: Some(String::from("outgoing")),
-                    usd_at_time: None,
-                };
-
-                wallet_transactions_hashmap.insert(raw_transaction.transaction.as_ref().unwrap().clone(), w_tx.clone());
-                wallet_transactions.push(w_tx);
+    let wallet_addresses_set: HashSet<&str> = wallet_adresses.iter().map(String::as_str).collect();
+
+    for raw_transaction in raw_transactions {
+        let w_tx = match WalletTransaction::from_raw(raw_transaction, Flow::classify(raw_transaction, &wallet_addresses_set)) {
+            Ok(w_tx) => w_tx,
+            Err(e) => {
+                println!("skipping transaction {}: {}", raw_transaction.transaction.as_deref().unwrap_or("without id"), e);
+                continue;
             }
+        };
+        if !wallet_transaction_ids.insert(w_tx.transaction) {
+            continue;
         }
-    };
 
-    if new_wallet_adresses.len() > 0 {
-        wallet_adresses.append(&mut new_wallet_adresses);
+        wallet_transactions.push(w_tx);
     }
 
     wallet_transactions
 }
 
 pub fn sort_wallet_transactions_by_created_at_time(wallet_transactions: &mut Vec<WalletTransaction>) {
-    wallet_transactions.sort_by(|a, b| {
-        let a_created_at_time = a.created_at_time.as_ref().unwrap();
-        let b_created_at_time = b.created_at_time.as_ref().unwrap();
-        a_created_at_time.partial_cmp(b_created_at_time).unwrap()
-    });
-}
\ No newline at end of file
+    wallet_transactions.sort_by_key(|wallet_transaction| wallet_transaction.created_at_time);
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Confirmed transaction without coins or prices, tests override the
+    /// fields they care about
+    pub(crate) fn wallet_transaction(id: u8, flow: Flow, amount: u64, created_at_time: &str) -> WalletTransaction {
+        WalletTransaction {
+            transaction: [id; 32],
+            status: TxStatus::Confirmed,
+            amount,
+            to_address: None,
+            created_at_time: parse_created_at_time(created_at_time).unwrap(),
+            flow,
+            category: match flow {
+                Flow::Incoming => TxCategory::Receipt,
+                Flow::Outgoing => TxCategory::Payment,
+                Flow::Internal => TxCategory::Transfer,
+            },
+            fiat_at_time: BTreeMap::new(),
+            transaction_type: None,
+            fee_amount: 0,
+            confirmed_at_height: Some(1),
+            additions: Vec::new(),
+            removals: Vec::new(),
+            memos: Vec::new(),
+            spend_bundle: None,
+        }
+    }
+
+    fn raw_transaction(transaction: &str, chia_amount: &str, created_at_time: &str) -> RawWalletTransaction {
+        RawWalletTransaction {
+            transaction: Some(transaction.to_string()),
+            status: Some(true),
+            chia_amount: Some(chia_amount.to_string()),
+            created_at_time: Some(created_at_time.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_classify_flow() {
+        let wallet_addresses = HashSet::from(["xch1own1", "xch1own2"]);
+        let cases = [
+            (Some("xch1own1"), None, Flow::Incoming),
+            (Some("xch1other"), None, Flow::Outgoing),
+            (Some("xch1own1"), Some(TransactionType::IncomingTx.as_u32()), Flow::Incoming),
+            (Some("xch1unchecked"), Some(TransactionType::IncomingTx.as_u32()), Flow::Incoming),
+            (Some("xch1unchecked"), Some(TransactionType::FeeReward.as_u32()), Flow::Incoming),
+            (Some("xch1other"), Some(TransactionType::OutgoingTx.as_u32()), Flow::Outgoing),
+            (Some("xch1own2"), Some(TransactionType::OutgoingTx.as_u32()), Flow::Internal),
+            (None, Some(TransactionType::OutgoingTx.as_u32()), Flow::Outgoing),
+            // INCOMING_CRCAT_PENDING, newer than the types known here
+            (Some("xch1own1"), Some(11), Flow::Incoming),
+            (Some("xch1other"), Some(11), Flow::Outgoing),
+        ];
+
+        for (to_address, transaction_type, flow) in cases {
+            let mut raw = raw_transaction(&format!("0x{}", "01".repeat(32)), "1", "2023-11-16 10:00:00");
+            raw.to_address = to_address.map(String::from);
+            raw.transaction_type = transaction_type;
+            assert_eq!(Flow::classify(&raw, &wallet_addresses), flow, "{:?} {:?}", to_address, transaction_type);
+        }
+    }
+
+    #[test]
+    fn test_from_raw() {
+        let raw = raw_transaction(
+            "0x3f1c2a9e0d6b4c8f7a5e2d1b0c9f8e7d6a5b4c3d2e1f0a9b8c7d6e5f4a3b2c1d",
+            "39.39",
+            "2023-11-16T02:39:48Z",
+        );
+        let w_tx = WalletTransaction::from_raw(&raw, Flow::Incoming).unwrap();
+
+        assert_eq!(w_tx.transaction_id(), raw.transaction.unwrap());
+        assert_eq!(w_tx.status, TxStatus::Confirmed);
+        assert_eq!(w_tx.amount, 39_390_000_000_000);
+        assert_eq!(w_tx.chia_amount(), Decimal::from_str("39.39").unwrap());
+        assert_eq!(format_created_at_time(&w_tx.created_at_time), "2023-11-16T02:39:48Z");
+    }
+
+    #[test]
+    fn test_classify_category() {
+        let mut farmer_parent = <[u8; 32]>::from_hex("3ff07eb358e8255a65c30a2dce0e5fbb00000000000000000000000000000000").unwrap();
+        farmer_parent[28..].copy_from_slice(&5_045_759u32.to_be_bytes());
+        let farmer_coin = |amount| vec![Coin { parent_coin_info: farmer_parent, puzzle_hash: [1u8; 32], amount }];
+
+        let cases = [
+            (None, Flow::Incoming, 1, None, vec![], TxCategory::Receipt),
+            (Some(TransactionType::IncomingTx), Flow::Incoming, 1, Some(1), vec![], TxCategory::Receipt),
+            (Some(TransactionType::OutgoingTx), Flow::Outgoing, 1, Some(1), vec![], TxCategory::Payment),
+            (Some(TransactionType::OutgoingTx), Flow::Internal, 1, Some(1), vec![], TxCategory::Transfer),
+            (Some(TransactionType::CoinbaseReward), Flow::Incoming, 1_750_000_000_000, Some(1), vec![], TxCategory::PoolReward),
+            (Some(TransactionType::FeeReward), Flow::Incoming, 250_000_000_000, Some(1), vec![], TxCategory::FarmerReward),
+            (Some(TransactionType::FeeReward), Flow::Incoming, 250_000_100_000, Some(1), vec![], TxCategory::FeeReward),
+            (Some(TransactionType::FeeReward), Flow::Incoming, 250_000_000_000, None, vec![], TxCategory::FarmerReward),
+            // confirmed after the halving, the rewarded block is from before it
+            (Some(TransactionType::FeeReward), Flow::Incoming, 250_000_000_000, Some(5_045_770), farmer_coin(250_000_000_000), TxCategory::FarmerReward),
+            (Some(TransactionType::FeeReward), Flow::Incoming, 250_000_000_000, Some(5_045_770), vec![], TxCategory::FeeReward),
+        ];
+
+        for (transaction_type, flow, amount, height, additions, category) in cases {
+            assert_eq!(TxCategory::classify(transaction_type, flow, amount, height, &additions), category, "{:?} {}", transaction_type, amount);
+            assert_eq!(category.as_str().parse::<TxCategory>().unwrap(), category);
+        }
+    }
+
+    #[test]
+    fn test_from_raw_full_record() {
+        let raw: RawWalletTransaction = serde_json::from_value(serde_json::json!({
+            "transaction": format!("0x{}", "01".repeat(32)),
+            "status": true,
+            "chia_amount": "1",
+            "to_address": "xch1other",
+            "created_at_time": "2023-11-16T02:39:48Z",
+            "type": 1,
+            "fee_amount": 1000000,
+            "confirmed_at_height": 4500000,
+            "additions": [
+                { "parent_coin_info": format!("0x{}", "02".repeat(32)), "puzzle_hash": format!("0x{}", "03".repeat(32)), "amount": 1000000000000u64 },
+            ],
+            "removals": [
+                { "parent_coin_info": format!("0x{}", "04".repeat(32)), "puzzle_hash": format!("0x{}", "05".repeat(32)), "amount": 1000001000000u64 },
+            ],
+            "memos": { format!("0x{}", "06".repeat(32)): ["0x68656c6c6f"] },
+            "spend_bundle": { "aggregated_signature": "0xc0", "coin_spends": [] },
+        })).unwrap();
+        let w_tx = WalletTransaction::from_raw(&raw, Flow::Outgoing).unwrap();
+
+        assert_eq!(w_tx.transaction_type, Some(TransactionType::OutgoingTx));
+        assert_eq!(w_tx.fee_amount, 1_000_000);
+        assert_eq!(w_tx.chia_fee_amount(), Decimal::from_str("0.000001").unwrap());
+        assert_eq!(w_tx.confirmed_at_height, Some(4_500_000));
+        assert_eq!(w_tx.additions, vec![Coin { parent_coin_info: [2u8; 32], puzzle_hash: [3u8; 32], amount: 1_000_000_000_000 }]);
+        assert_eq!(w_tx.removals[0].amount, 1_000_001_000_000);
+        assert_eq!(w_tx.memos, vec![Memo { coin_id: [6u8; 32], memo: b"hello".to_vec() }]);
+        assert!(w_tx.spend_bundle.unwrap().contains("aggregated_signature"));
+
+        let raw: RawWalletTransaction = serde_json::from_value(serde_json::json!({
+            "memos": [[format!("0x{}", "06".repeat(32)), ["68656c6c6f"]]],
+        })).unwrap();
+        assert!(matches!(raw.memos, Some(RawMemos::List(_))));
+    }
+
+    #[test]
+    fn test_coin_id() {
+        let parent_coin_info = [2u8; 32];
+        let puzzle_hash = [1u8; 32];
+
+        for (amount, amount_bytes) in [
+            (0, vec![]),
+            (1750000000000, vec![0x01, 0x97, 0x74, 0x20, 0xdc, 0x00]),
+            (18375000000000000000, vec![0x00, 0xff, 0x01, 0x1d, 0x25, 0x23, 0xcd, 0x80, 0x00]),
+        ] {
+            let expected: [u8; 32] = Sha256::new()
+                .chain_update(parent_coin_info)
+                .chain_update(puzzle_hash)
+                .chain_update(amount_bytes)
+                .finalize()
+                .into();
+            assert_eq!(Coin { parent_coin_info, puzzle_hash, amount }.coin_id(), expected);
+        }
+
+        assert_eq!(
+            hex::encode(Coin { parent_coin_info, puzzle_hash, amount: 1750000000000 }.coin_id()),
+            "a1b2db22b9722b35dbf073818eeaeb07cc38981c5b30e0b6c1cca4b50439f7b7"
+        );
+    }
+
+    #[test]
+    fn test_from_raw_errors() {
+        let id = "0x3f1c2a9e0d6b4c8f7a5e2d1b0c9f8e7d6a5b4c3d2e1f0a9b8c7d6e5f4a3b2c1d";
+        let cases = [
+            (raw_transaction("0x1234", "1", "2023-11-16 02:39:48"), "invalid transaction id 0x1234"),
+            (raw_transaction(id, "-1", "2023-11-16 02:39:48"), "invalid chia amount -1"),
+            (raw_transaction(id, "0.0000000000001", "2023-11-16 02:39:48"), "invalid chia amount 0.0000000000001"),
+            (raw_transaction(id, "1", "16/11/2023"), "invalid created at time 16/11/2023"),
+        ];
+        for (raw, error) in cases {
+            assert_eq!(WalletTransaction::from_raw(&raw, Flow::Incoming).unwrap_err().to_string(), error);
+        }
+
+        let mut raw = raw_transaction(id, "1", "2023-11-16 02:39:48");
+        raw.status = None;
+        assert_eq!(WalletTransaction::from_raw(&raw, Flow::Incoming).unwrap_err().to_string(), "transaction is missing status");
+    }
+
+    #[test]
+    fn test_process_raw_transactions() {
+        let mut unknown_type = raw_transaction(&format!("0x{}", "01".repeat(32)), "1", "2023-11-16 10:00:00");
+        unknown_type.transaction_type = Some(11);
+        let malformed = raw_transaction(&format!("0x{}", "02".repeat(32)), "-1", "2023-11-16 10:00:00");
+        let raw_w_txs = vec![unknown_type, malformed, raw_transaction(&format!("0x{}", "03".repeat(32)), "2", "2023-11-16 11:00:00")];
+
+        // the malformed record doesn't hold up the others
+        let w_txs = process_raw_transactions(&raw_w_txs, &[]);
+        assert_eq!(w_txs.iter().map(|w_tx| w_tx.transaction[0]).collect::<Vec<u8>>(), vec![1, 3]);
+        assert_eq!(w_txs[0].transaction_type, Some(TransactionType::Unknown(11)));
+        assert_eq!(w_txs[0].transaction_type.unwrap().as_u32(), 11);
+        assert_eq!(w_txs[0].category, TxCategory::Payment);
+    }
+
+    #[test]
+    fn test_sort_by_created_at_time() {
+        // "2:39:48" sorts after "10:00:00" as a string
+        let mut w_txs = vec![
+            WalletTransaction::from_raw(&raw_transaction(&format!("0x{}", "01".repeat(32)), "1", "2023-11-16 10:00:00"), Flow::Incoming).unwrap(),
+            WalletTransaction::from_raw(&raw_transaction(&format!("0x{}", "02".repeat(32)), "1", "2023-11-16 2:39:48"), Flow::Incoming).unwrap(),
+        ];
+        sort_wallet_transactions_by_created_at_time(&mut w_txs);
+
+        assert_eq!(w_txs[0].transaction, [2u8; 32]);
+        assert_eq!(w_txs[1].transaction, [1u8; 32]);
+    }
+}
@@ -1,25 +1,76 @@
 extern crate google_sheets4 as sheets4;
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
 use std::hash::Hash;
 use std::str::FromStr;
 
+use rusqlite::OptionalExtension;
 use rust_decimal::Decimal;
-use serde::Deserialize;
 use sheets4::api::ValueRange;
 use sheets4::hyper_rustls::HttpsConnector;
 use sheets4::{hyper, hyper_rustls, oauth2, Sheets};
-use sheets4::Error;
 
 use serde_json::Value;
 
-use crate::{wallet_transactions::WalletTransaction, Config};
+use crate::address_discovery::{AddressDiscovery, DerivedAddress};
+use crate::addresses::AddressSummary;
+use crate::balance_history::{self, DailyBalance};
+use crate::coin_store::CoinSet;
+use crate::cost_basis::{self, CostBasis};
+use crate::migrations;
+use crate::price_provider::{create_price_providers, PriceGranularity, PriceProvider};
+use crate::prices::{self, PriceError};
+use crate::wallet_transactions::{
+    format_created_at_time, parse_bytes32, parse_created_at_time, parse_transaction_id, Coin,
+    Flow, Memo, TransactionError, TransactionType, TxStatus, WalletTransaction,
+};
+use crate::Config;
+
+#[derive(Debug)]
+pub enum SheetsError {
+    /// Reading the service account key or signing in with it
+    Auth(std::io::Error),
+    Db(rusqlite::Error),
+    /// Boxed, the api errors are large
+    Api(Box<sheets4::Error>),
+}
+
+impl fmt::Display for SheetsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SheetsError::Auth(e) => write!(f, "failed to authenticate with the service account: {}", e),
+            SheetsError::Db(e) => write!(f, "failed to load the sheet values: {}", e),
+            SheetsError::Api(e) => write!(f, "failed to update the sheet: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for SheetsError {}
+
+impl From<std::io::Error> for SheetsError {
+    fn from(e: std::io::Error) -> Self {
+        SheetsError::Auth(e)
+    }
+}
+
+impl From<rusqlite::Error> for SheetsError {
+    fn from(e: rusqlite::Error) -> Self {
+        SheetsError::Db(e)
+    }
+}
+
+impl From<sheets4::Error> for SheetsError {
+    fn from(e: sheets4::Error) -> Self {
+        SheetsError::Api(Box::new(e))
+    }
+}
 
 pub struct WalletTransactionsSave<'a> {
     config: &'a Config,
     gsheets_hub: Option<Sheets<HttpsConnector<hyper::client::HttpConnector>>>,
     db_connection: Option<rusqlite::Connection>,
-    all_coin_paprika_historical_data: Option<AllCoinPaprikaHistoricalData>,
+    price_providers: Vec<Box<dyn PriceProvider + Send + Sync>>,
 }
 
 impl<'a> WalletTransactionsSave<'a> 
@@ -29,84 +80,291 @@ impl<'a> WalletTransactionsSave<'a>
             config,
             gsheets_hub: None,
             db_connection: None,
-            all_coin_paprika_historical_data: Some(AllCoinPaprikaHistoricalData(HashMap::new())),
+            price_providers: create_price_providers(config),
         }
     }
 
-    pub async fn save_to_db(&mut self, wallet_transactions: &Vec<WalletTransaction>) -> Result<(), rusqlite::Error> {
+    pub async fn save_to_db(&mut self, wallet_fingerprint: u32, wallet_transactions: &Vec<WalletTransaction>) -> Result<(), rusqlite::Error> {
         if self.db_connection.is_none() {
             self.create_db_connection()?;
         }
 
-        // Create table if not exists
-        let create_table_query = "CREATE TABLE IF NOT EXISTS wallet_transactions (
-            `transaction` TEXT PRIMARY KEY,
-            status BOOLEAN,
-            chia_amount TEXT,
-            to_address TEXT,
-            created_at_time TEXT,
-            flow TEXT,
-            usd_at_time TEXT
-        )";
-
-        self.db_connection.as_ref().expect("failed to get db_connection")
-            .execute(create_table_query, rusqlite::params![])?;
-
-        // Insert values
-        let insert_query = "INSERT OR IGNORE INTO wallet_transactions (
+        // Insert new transactions and update the ones that changed
+        let upsert_query = "INSERT INTO wallet_transactions (
+            wallet_fingerprint,
             `transaction`,
-            status,
-            chia_amount,
+            tx_status,
+            amount,
             to_address,
             created_at_time,
-            flow
-        ) VALUES (?1, ?2, ?3, ?4, ?5, ?6)";
+            flow,
+            transaction_type,
+            fee_amount,
+            confirmed_at_height,
+            spend_bundle,
+            category
+        ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)
+        ON CONFLICT(wallet_fingerprint, `transaction`) DO UPDATE SET
+            tx_status = excluded.tx_status,
+            amount = excluded.amount,
+            to_address = excluded.to_address,
+            created_at_time = excluded.created_at_time,
+            flow = excluded.flow,
+            transaction_type = excluded.transaction_type,
+            fee_amount = excluded.fee_amount,
+            confirmed_at_height = excluded.confirmed_at_height,
+            spend_bundle = COALESCE(excluded.spend_bundle, spend_bundle),
+            category = excluded.category";
+
+        let query_status = "SELECT tx_status, confirmed_at_height FROM wallet_transactions
+            WHERE wallet_fingerprint = ?1 AND `transaction` = ?2";
+        let insert_coin_query = "INSERT OR IGNORE INTO transaction_coins (
+            wallet_fingerprint,
+            `transaction`,
+            direction,
+            coin_id,
+            parent_coin_info,
+            puzzle_hash,
+            amount
+        ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)";
+        let insert_memo_query = "INSERT OR IGNORE INTO transaction_memos (wallet_fingerprint, `transaction`, coin_id, memo)
+            VALUES (?1, ?2, ?3, ?4)";
+
+        let query_price = "SELECT 1 FROM transaction_prices WHERE wallet_fingerprint = ?1 AND `transaction` = ?2 AND quote_currency = ?3";
+        let insert_price_query = "INSERT INTO transaction_prices (wallet_fingerprint, `transaction`, quote_currency, price, price_timestamp)
+            VALUES (?1, ?2, ?3, ?4, ?5)";
 
-        let query_usd_at_time = "SELECT usd_at_time FROM wallet_transactions WHERE `transaction` = ?1";
-        let update_usd_query = "UPDATE wallet_transactions SET usd_at_time = ?1 WHERE `transaction` = ?2";
+        let observed_at = format_created_at_time(&Utc::now());
 
         for wallet_transaction in wallet_transactions {
-            self.db_connection.as_ref().expect("failed to get db_connection")
-                .execute(insert_query, rusqlite::params![
-                    wallet_transaction.transaction.clone().unwrap_or(String::from("")),
-                    wallet_transaction.status.unwrap_or(false),
-                    wallet_transaction.chia_amount.clone().unwrap_or(String::from("")),
-                    wallet_transaction.to_address.clone().unwrap_or(String::from("")),
-                    wallet_transaction.created_at_time.clone().unwrap_or(String::from("")),
-                    wallet_transaction.flow.clone().unwrap_or(String::from("")),
-                ])?;
-                    
-            let mut query_stmt = self.db_connection.as_ref().expect("failed to get db_connection")
-                .prepare(query_usd_at_time)?;
-            let usd_at_time_iter = query_stmt.query_map([wallet_transaction.transaction.clone().unwrap_or(String::from(""))], |row| {
-                Ok(CoinPaprikaPriceConverter {
-                    price: Decimal::from_str(&row.get::<_, String>(0).unwrap_or("0.0".to_string())).unwrap()
+            let needs_fiat_at_time = wallet_transaction.flow != Flow::Internal;
+            let db_connection = self.db_connection.as_ref().expect("failed to get db_connection");
+
+            let stored_status = db_connection
+                .query_row(query_status, rusqlite::params![wallet_fingerprint, wallet_transaction.transaction_id()], |row| {
+                    Ok((
+                        row.get::<_, String>(0)?.parse::<TxStatus>().map_err(|e| conversion_error(0, e.into()))?,
+                        row.get::<_, Option<u32>>(1)?,
+                    ))
                 })
-            })?;
-
-            for usd_at_time in usd_at_time_iter {
-                if usd_at_time.unwrap().price == Decimal::new(0, 0) {
-                    self.db_connection.as_ref().expect("failed to get db_connection")
-                        .execute(update_usd_query, rusqlite::params![
-                            get_xch_to_usd_at_time(
-                                wallet_transaction.created_at_time.clone().unwrap_or(String::from("")), 
-                                self.all_coin_paprika_historical_data.as_mut().unwrap()
-                            ).await.unwrap().to_string(),
-                            wallet_transaction.transaction.clone().unwrap_or(String::from("")),
+                .optional()?;
+
+            db_connection.execute(upsert_query, rusqlite::params![
+                wallet_fingerprint,
+                wallet_transaction.transaction_id(),
+                wallet_transaction.status.as_str(),
+                wallet_transaction.amount,
+                wallet_transaction.to_address.clone().unwrap_or(String::from("")),
+                format_created_at_time(&wallet_transaction.created_at_time),
+                wallet_transaction.flow.as_str(),
+                wallet_transaction.transaction_type.as_ref().map(TransactionType::as_u32),
+                wallet_transaction.fee_amount,
+                wallet_transaction.confirmed_at_height,
+                wallet_transaction.spend_bundle,
+                wallet_transaction.category.as_str(),
+            ])?;
+
+            let mut old_status = stored_status.map(|(status, _)| status);
+            // confirmed at another height or back to pending, the block it was in got reorged out
+            if let Some((TxStatus::Confirmed, stored_height)) = stored_status {
+                if stored_height != wallet_transaction.confirmed_at_height {
+                    println!("transaction {} was reorged", wallet_transaction.transaction_id());
+                    log_status_change(
+                        db_connection,
+                        wallet_fingerprint,
+                        &wallet_transaction.transaction_id(),
+                        old_status,
+                        TxStatus::Reorged,
+                        stored_height,
+                        &observed_at,
+                    )?;
+                    old_status = Some(TxStatus::Reorged);
+                }
+            }
+            if stored_status != Some((wallet_transaction.status, wallet_transaction.confirmed_at_height)) {
+                log_status_change(
+                    db_connection,
+                    wallet_fingerprint,
+                    &wallet_transaction.transaction_id(),
+                    old_status,
+                    wallet_transaction.status,
+                    wallet_transaction.confirmed_at_height,
+                    &observed_at,
+                )?;
+            }
+            for (direction, coins) in [(COIN_ADDITION, &wallet_transaction.additions), (COIN_REMOVAL, &wallet_transaction.removals)] {
+                for coin in coins {
+                    db_connection.execute(insert_coin_query, rusqlite::params![
+                        wallet_fingerprint,
+                        wallet_transaction.transaction_id(),
+                        direction,
+                        format!("0x{}", hex::encode(coin.coin_id())),
+                        format!("0x{}", hex::encode(coin.parent_coin_info)),
+                        format!("0x{}", hex::encode(coin.puzzle_hash)),
+                        coin.amount,
+                    ])?;
+                }
+            }
+            for memo in &wallet_transaction.memos {
+                db_connection.execute(insert_memo_query, rusqlite::params![
+                    wallet_fingerprint,
+                    wallet_transaction.transaction_id(),
+                    format!("0x{}", hex::encode(memo.coin_id)),
+                    hex::encode(&memo.memo),
+                ])?;
+            }
+
+            if !needs_fiat_at_time {
+                continue;
+            }
+            for quote_currency in &self.config.quote_currencies {
+                let has_price = db_connection
+                    .query_row(query_price, rusqlite::params![wallet_fingerprint, wallet_transaction.transaction_id(), quote_currency], |_| Ok(()))
+                    .optional()?
+                    .is_some();
+                if has_price {
+                    continue;
+                }
+
+                let price = prices::get_xch_price_at_time(
+                    wallet_transaction.created_at_time,
+                    quote_currency,
+                    self.config.price_granularity,
+                    db_connection,
+                    &self.price_providers,
+                ).await;
+                match price {
+                    Ok((price_timestamp, price)) => {
+                        db_connection.execute(insert_price_query, rusqlite::params![
+                            wallet_fingerprint,
+                            wallet_transaction.transaction_id(),
+                            quote_currency,
+                            price.normalize().to_string(),
+                            format_created_at_time(&price_timestamp),
                         ])?;
+                    }
+                    // left unset so the next refresh tries again
+                    Err(e) => println!("failed to get {} price of transaction {}: {}", quote_currency, wallet_transaction.transaction_id(), e),
                 }
             }
         }
 
+        // an empty poll more likely means the source failed than every transaction vanishing
+        if !wallet_transactions.is_empty() {
+            self.reconcile_missing_transactions(wallet_fingerprint, wallet_transactions, &observed_at)?;
+        }
+
         Ok(())
     }
 
-    pub async fn save_to_googlesheets(&mut self) {
+    /// Flags the stored transactions missing from the poll: pending ones were
+    /// dropped from the mempool and confirmed ones were reorged out. They are
+    /// kept in the db with their new status.
+    fn reconcile_missing_transactions(
+        &mut self,
+        wallet_fingerprint: u32,
+        wallet_transactions: &[WalletTransaction],
+        observed_at: &str,
+    ) -> Result<(), rusqlite::Error> {
+        let db_connection = self.db_connection.as_ref().expect("failed to get db_connection");
+        let polled: HashSet<[u8; 32]> = wallet_transactions.iter()
+            .map(|wallet_transaction| wallet_transaction.transaction)
+            .collect();
+
+        let mut stmt = db_connection.prepare(
+            "SELECT `transaction`, tx_status, confirmed_at_height FROM wallet_transactions
+            WHERE wallet_fingerprint = ?1 AND tx_status IN ('pending', 'confirmed')",
+        )?;
+        let stored = stmt.query_map([wallet_fingerprint], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?.parse::<TxStatus>().map_err(|e| conversion_error(1, e.into()))?,
+                row.get::<_, Option<u32>>(2)?,
+            ))
+        })?
+        .collect::<Result<Vec<(String, TxStatus, Option<u32>)>, rusqlite::Error>>()?;
+
+        for (transaction_id, status, confirmed_at_height) in stored {
+            let transaction = parse_transaction_id(&transaction_id).map_err(|e| conversion_error(0, e.into()))?;
+            if polled.contains(&transaction) {
+                continue;
+            }
+
+            let new_status = if status == TxStatus::Confirmed { TxStatus::Reorged } else { TxStatus::Dropped };
+            println!("transaction {} was {}", transaction_id, new_status.as_str());
+            db_connection.execute(
+                "UPDATE wallet_transactions SET tx_status = ?1 WHERE wallet_fingerprint = ?2 AND `transaction` = ?3",
+                rusqlite::params![new_status.as_str(), wallet_fingerprint, transaction_id],
+            )?;
+            log_status_change(db_connection, wallet_fingerprint, &transaction_id, Some(status), new_status, confirmed_at_height, observed_at)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn save_coins(&mut self, wallet_fingerprint: u32, coin_set: &CoinSet) -> Result<(), rusqlite::Error> {
+        if self.db_connection.is_none() {
+            self.create_db_connection()?;
+        }
+
+        let upsert_coin_query = "INSERT INTO coins (
+            wallet_fingerprint,
+            coin_id,
+            parent_coin_info,
+            puzzle_hash,
+            amount,
+            confirmed,
+            created_height,
+            spent,
+            spent_height,
+            pending_spend
+        ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+        ON CONFLICT(wallet_fingerprint, coin_id) DO UPDATE SET
+            confirmed = excluded.confirmed,
+            created_height = excluded.created_height,
+            spent = excluded.spent,
+            spent_height = excluded.spent_height,
+            pending_spend = excluded.pending_spend";
+
+        let db_connection = self.db_connection.as_ref().expect("failed to get db_connection");
+        for coin_state in coin_set.coins() {
+            db_connection.execute(upsert_coin_query, rusqlite::params![
+                wallet_fingerprint,
+                format!("0x{}", hex::encode(coin_state.coin.coin_id())),
+                format!("0x{}", hex::encode(coin_state.coin.parent_coin_info)),
+                format!("0x{}", hex::encode(coin_state.coin.puzzle_hash)),
+                coin_state.coin.amount,
+                coin_state.confirmed,
+                coin_state.created_height,
+                coin_state.spent,
+                coin_state.spent_height,
+                coin_state.pending_spend,
+            ])?;
+        }
+
+        // coins of dropped or reorged transactions are gone from the coin set
+        if !coin_set.is_empty() {
+            let coin_ids: HashSet<String> = coin_set.coins()
+                .map(|coin_state| format!("0x{}", hex::encode(coin_state.coin.coin_id())))
+                .collect();
+            let stored_coin_ids = db_connection.prepare("SELECT coin_id FROM coins WHERE wallet_fingerprint = ?1")?
+                .query_map([wallet_fingerprint], |row| row.get::<_, String>(0))?
+                .collect::<Result<Vec<String>, rusqlite::Error>>()?;
+            for coin_id in stored_coin_ids.iter().filter(|coin_id| !coin_ids.contains(*coin_id)) {
+                db_connection.execute(
+                    "DELETE FROM coins WHERE wallet_fingerprint = ?1 AND coin_id = ?2",
+                    rusqlite::params![wallet_fingerprint, coin_id],
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub async fn save_to_googlesheets(&mut self) -> Result<(), SheetsError> {
         if self.gsheets_hub.is_none() {
             // Connect to Google Sheets API
-            let secret = oauth2::read_service_account_key(self.config.google_service_account_key_path.as_ref().unwrap())
-                .await
-                .expect("failed to read credentials.json");
+            let secret = oauth2::read_service_account_key(self.config.google_service_account_key_path.as_ref().unwrap()).await?;
             let client = hyper::Client::builder().build(
                 hyper_rustls::HttpsConnectorBuilder::new()
                     .with_native_roots()
@@ -117,38 +375,17 @@ impl<'a> WalletTransactionsSave<'a>
             let auth = oauth2::ServiceAccountAuthenticator::builder(secret)
                 .hyper_client(client.clone())
                 .build()
-                .await
-                .expect("failed to create authenticator");
+                .await?;
             self.gsheets_hub = Some(Sheets::new(client, auth));
         }
 
         // Get wallet transactions
         if self.db_connection.is_none() {
-            self.create_db_connection().expect("failed to create db_connection");
-        }
-
-        let mut stmt = self.db_connection.as_ref().expect("failed to get db_connection")
-            .prepare("SELECT * FROM wallet_transactions").unwrap();
-        let wallet_transactions_iter = stmt.query_map(rusqlite::params![], |row| {
-            Ok(WalletTransaction {
-                transaction: row.get(0).unwrap(),
-                status: row.get(1).unwrap(),
-                chia_amount: row.get(2).unwrap(),
-                to_address: row.get(3).unwrap(),
-                created_at_time: row.get(4).unwrap(),
-                flow: row.get(5).unwrap(),
-                usd_at_time: Some(Decimal::from_str(&row.get::<_, String>(6).unwrap_or("0.0".to_string())).unwrap()),
-            })
-        }).unwrap();
-
-        let mut wallet_transactions: Vec<WalletTransaction> = Vec::new();
-        for wallet_transaction in wallet_transactions_iter {
-            wallet_transactions.push(wallet_transaction.unwrap());
+            self.create_db_connection()?;
         }
 
-        // sort wallet transactions by created_at_time
-        wallet_transactions.sort_by(|a, b| a.created_at_time.cmp(&b.created_at_time));
-        
+        let wallets = self.config.wallets();
+
         // Get spreadsheet ID
         let spreadsheet_id = self
             .config
@@ -173,213 +410,649 @@ impl<'a> WalletTransactionsSave<'a>
             .expect("failed to get range")
             .clone();
 
-        // Get values
-        let mut values: Vec<Vec<Value>> = Vec::new();
-        for wallet_transaction in wallet_transactions {
-            values.push(vec![
-                Value::String(
-                    wallet_transaction
-                        .transaction
-                        .clone()
-                        .unwrap_or(String::from("")),
-                ),
-                Value::Bool(wallet_transaction.status.unwrap_or(false)),
-                Value::String(
-                    wallet_transaction
-                        .chia_amount
-                        .clone()
-                        .unwrap_or(String::from("")),
-                ),
-                Value::String(
-                    wallet_transaction
-                        .to_address
-                        .clone()
-                        .unwrap_or(String::from("")),
-                ),
-                Value::String(
-                    wallet_transaction
-                        .created_at_time
-                        .clone()
-                        .unwrap_or(String::from("")),
-                ),
-                Value::String(wallet_transaction.flow.clone().unwrap_or(String::from(""))),
-                Value::String(wallet_transaction.usd_at_time.unwrap().to_string()),
-            ]);
+        // Get values, the main sheet has every wallet and a wallet with its own sheet gets its transactions there too
+        let mut rows: Vec<(DateTime<Utc>, Vec<Value>)> = Vec::new();
+        for wallet in &wallets {
+            let mut wallet_transactions = self.load_wallet_transactions(wallet.wallet_fingerprint)?;
+
+            // sort wallet transactions by created_at_time
+            wallet_transactions.sort_by_key(|wallet_transaction| wallet_transaction.created_at_time);
+
+            let wallet_rows: Vec<(DateTime<Utc>, Vec<Value>)> = wallet_transactions
+                .iter()
+                .map(|wallet_transaction| (wallet_transaction.created_at_time, transaction_sheet_row(wallet_transaction, &self.config.quote_currencies, &wallet.label())))
+                .collect();
+
+            if let Some(wallet_sheet_name) = wallet.sheet_name.as_ref().filter(|name| !name.is_empty()) {
+                let values = wallet_rows.iter().map(|(_, row)| row.clone()).collect();
+                self.update_sheet_values(&spreadsheet_id, &format!("{}!{}", wallet_sheet_name, sheet_range), values).await?;
+            }
+            rows.extend(wallet_rows);
         }
+        rows.sort_by_key(|(created_at_time, _)| *created_at_time);
+        let values: Vec<Vec<Value>> = rows.into_iter().map(|(_, row)| row).collect();
 
         let sheet_name_range = format!("{}!{}", sheet_name, sheet_range);
+        self.update_sheet_values(&spreadsheet_id, &sheet_name_range, values).await?;
+
+        // Per address breakdown on its own tab
+        if let Some(address_sheet_name) = self.config.address_sheet_name.clone().filter(|name| !name.is_empty()) {
+            let mut values: Vec<Vec<Value>> = vec![
+                ["index", "address", "received", "sent", "balance", "tx count", "first seen", "last seen", "kind", "wallet"]
+                    .iter()
+                    .map(|header| Value::String(header.to_string()))
+                    .collect(),
+            ];
+            for wallet in &wallets {
+                let addresses = self.load_addresses(wallet.wallet_fingerprint)?;
+                for address in addresses {
+                    let format_time = |time: Option<DateTime<Utc>>| {
+                        time.map_or(String::from(""), |time| time.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S").to_string())
+                    };
+                    values.push(vec![
+                        address.derivation_index.map_or(Value::Null, Value::from),
+                        Value::String(address.address.clone()),
+                        Value::String(address.chia_total_received().to_string()),
+                        address.chia_total_sent().map_or(Value::Null, |total_sent| Value::String(total_sent.to_string())),
+                        address.chia_balance().map_or(Value::Null, |balance| Value::String(balance.to_string())),
+                        Value::from(address.tx_count),
+                        Value::String(format_time(address.first_seen)),
+                        Value::String(format_time(address.last_seen)),
+                        Value::String(address.kind().as_str().to_string()),
+                        Value::String(wallet.label()),
+                    ]);
+                }
+            }
+
+            self.update_sheet_values(&spreadsheet_id, &format!("{}!A1", address_sheet_name), values).await?;
+        }
+
+        // Balance of every wallet together per day, for charting the net worth
+        if let Some(balance_sheet_name) = self.config.balance_sheet_name.clone().filter(|name| !name.is_empty()) {
+            let mut histories = Vec::new();
+            for wallet in &wallets {
+                histories.push(self.load_balance_history(wallet.wallet_fingerprint)?);
+            }
+
+            let mut header = vec![Value::String(String::from("day")), Value::String(String::from("balance"))];
+            for quote_currency in &self.config.quote_currencies {
+                header.push(Value::String(format!("{} price", quote_currency)));
+                header.push(Value::String(format!("{} value", quote_currency)));
+            }
+            let mut values: Vec<Vec<Value>> = vec![header];
+            for daily_balance in balance_history::total_daily_balances(&histories) {
+                let mut row = vec![
+                    Value::String(daily_balance.day.format("%Y-%m-%d").to_string()),
+                    Value::String(daily_balance.chia_balance().to_string()),
+                ];
+                for quote_currency in &self.config.quote_currencies {
+                    let price = daily_balance.prices.get(quote_currency);
+                    row.push(Value::String(price.map_or(String::new(), |price| price.normalize().to_string())));
+                    row.push(Value::String(daily_balance.value(quote_currency).map_or(String::new(), |value| value.round_dp(2).to_string())));
+                }
+                values.push(row);
+            }
 
+            self.update_sheet_values(&spreadsheet_id, &format!("{}!A1", balance_sheet_name), values).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn update_sheet_values(&self, spreadsheet_id: &str, range: &str, values: Vec<Vec<Value>>) -> Result<(), sheets4::Error> {
         // Update values
         let value_range = ValueRange {
-            range: Some(sheet_name_range.clone()),
+            range: Some(range.to_string()),
             major_dimension: Some(String::from("ROWS")),
             values: Some(values),
             ..Default::default()
         };
 
-        let result = self.gsheets_hub.as_ref().expect("failed to get gsheets_hub")
+        self.gsheets_hub.as_ref().expect("failed to get gsheets_hub")
             .spreadsheets()
-            .values_update(value_range, &spreadsheet_id, &sheet_name_range)
+            .values_update(value_range, spreadsheet_id, range)
             .value_input_option("USER_ENTERED")
             .doit()
-            .await;
-
-        match result {
-            Err(e) => match e {
-                // The Error enum provides details about what exactly happened.
-                // You can also just use its `Debug`, `Display` or `Error` traits
-                Error::HttpError(_)
-                | Error::Io(_)
-                | Error::MissingAPIKey
-                | Error::MissingToken(_)
-                | Error::Cancelled
-                | Error::UploadSizeLimitExceeded(_, _)
-                | Error::Failure(_)
-                | Error::BadRequest(_)
-                | Error::FieldClash(_)
-                | Error::JsonDecodeError(_, _) => println!("{}", e),
-            },
-            Ok(_) => {}
+            .await?;
+
+        Ok(())
+    }
+
+    pub fn save_addresses(&mut self, wallet_fingerprint: u32, addresses: &[AddressSummary]) -> Result<(), rusqlite::Error> {
+        if self.db_connection.is_none() {
+            self.create_db_connection()?;
         }
+
+        let upsert_address_query = "INSERT OR REPLACE INTO addresses (
+            wallet_fingerprint,
+            address,
+            kind,
+            derivation_index,
+            total_received,
+            total_sent,
+            balance,
+            first_seen,
+            last_seen,
+            tx_count
+        ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)";
+
+        let db_connection = self.db_connection.as_ref().expect("failed to get db_connection");
+        for address in addresses {
+            db_connection.execute(upsert_address_query, rusqlite::params![
+                wallet_fingerprint,
+                address.address,
+                address.kind().as_str(),
+                address.derivation_index,
+                address.total_received,
+                address.total_sent,
+                address.balance(),
+                address.first_seen.as_ref().map(format_created_at_time),
+                address.last_seen.as_ref().map(format_created_at_time),
+                address.tx_count,
+            ])?;
+        }
+
+        Ok(())
     }
 
-    fn create_db_connection(&mut self) -> Result<(), rusqlite::Error> {
-        // Connect to DB
-        let db_connection = rusqlite::Connection::open(format!("{}/{}", self.config.db_path, self.config.db_name))?;
-        self.db_connection = Some(db_connection);
+    /// Addresses derived by earlier runs in derivation order and the highest
+    /// used derivation index of the wallet
+    pub fn load_derivation_state(&mut self, wallet_fingerprint: u32) -> Result<(Vec<DerivedAddress>, Option<u32>), rusqlite::Error> {
+        if self.db_connection.is_none() {
+            self.create_db_connection()?;
+        }
+
+        let db_connection = self.db_connection.as_ref().expect("failed to get db_connection");
+        let mut stmt = db_connection.prepare(
+            "SELECT derivation_index, address, puzzle_hash FROM derived_addresses
+            WHERE wallet_fingerprint = ?1 ORDER BY derivation_index",
+        )?;
+        let derived_addresses = stmt.query_map([wallet_fingerprint], |row| {
+            Ok(DerivedAddress {
+                derivation_index: row.get(0)?,
+                address: row.get(1)?,
+                puzzle_hash: parse_bytes32(&row.get::<_, String>(2)?).map_err(|e| conversion_error(2, e.into()))?,
+            })
+        })?
+        .collect::<Result<Vec<DerivedAddress>, rusqlite::Error>>()?;
+
+        let highest_used_index = db_connection
+            .query_row(
+                "SELECT highest_used_index FROM derivation_state WHERE wallet_fingerprint = ?1",
+                [wallet_fingerprint],
+                |row| row.get::<_, Option<u32>>(0),
+            )
+            .optional()?
+            .flatten();
+
+        Ok((derived_addresses, highest_used_index))
+    }
+
+    pub fn save_derivation_state(&mut self, wallet_fingerprint: u32, address_discovery: &AddressDiscovery) -> Result<(), rusqlite::Error> {
+        if self.db_connection.is_none() {
+            self.create_db_connection()?;
+        }
+
+        let db_connection = self.db_connection.as_ref().expect("failed to get db_connection");
+        for derived_address in address_discovery.derived_addresses() {
+            db_connection.execute(
+                "INSERT OR IGNORE INTO derived_addresses (wallet_fingerprint, derivation_index, address, puzzle_hash)
+                VALUES (?1, ?2, ?3, ?4)",
+                rusqlite::params![
+                    wallet_fingerprint,
+                    derived_address.derivation_index,
+                    derived_address.address,
+                    format!("0x{}", hex::encode(derived_address.puzzle_hash)),
+                ],
+            )?;
+        }
+        db_connection.execute(
+            "INSERT OR REPLACE INTO derivation_state (wallet_fingerprint, highest_used_index) VALUES (?1, ?2)",
+            rusqlite::params![wallet_fingerprint, address_discovery.highest_used_index()],
+        )?;
 
         Ok(())
     }
-}
 
-#[derive(Debug, Deserialize)]
-struct CoinPaprikaPriceConverter {
-    price: Decimal,
-}
+    pub fn load_addresses(&mut self, wallet_fingerprint: u32) -> Result<Vec<AddressSummary>, rusqlite::Error> {
+        if self.db_connection.is_none() {
+            self.create_db_connection()?;
+        }
 
-#[derive(Debug)]
-struct CoinPaprikaHistoricalData {
-    price: Decimal,
-    volume_24h: Decimal,
-    market_cap: Decimal,
-}
+        let mut stmt = self.db_connection.as_ref().expect("failed to get db_connection").prepare(
+            "SELECT address, derivation_index, total_received, total_sent, first_seen, last_seen, tx_count
+            FROM addresses WHERE wallet_fingerprint = ?1 ORDER BY derivation_index IS NULL, derivation_index, address",
+        )?;
+        let addresses = stmt.query_map([wallet_fingerprint], |row| {
+            let parse_time = |column: usize| -> Result<Option<DateTime<Utc>>, rusqlite::Error> {
+                row.get::<_, Option<String>>(column)?
+                    .map(|time| parse_created_at_time(&time).map_err(|e| conversion_error(column, e.into())))
+                    .transpose()
+            };
 
-#[derive(Debug, Deserialize)]
-struct CoinPaprikaHistoricalResponse {
-    timestamp: Option<String>,
-    price: Decimal,
-    volume_24h: Decimal,
-    market_cap: Decimal,
-}
+            Ok(AddressSummary {
+                address: row.get(0)?,
+                derivation_index: row.get(1)?,
+                total_received: row.get(2)?,
+                total_sent: row.get(3)?,
+                first_seen: parse_time(4)?,
+                last_seen: parse_time(5)?,
+                tx_count: row.get(6)?,
+            })
+        })?
+        .collect::<Result<Vec<AddressSummary>, rusqlite::Error>>()?;
+
+        Ok(addresses)
+    }
 
-#[derive(Debug, Deserialize)]
-struct AllCoinPaprikaHistoricalData(#[serde(deserialize_with = "coin_paprika_historical_data_map")] HashMap<String, CoinPaprikaHistoricalData>);
+    /// Recalculates the cost basis of every wallet in every quote currency
+    /// and replaces their `tax_lots`, together so that xch moved between the
+    /// wallets keeps its lots. The lots of a currency with a transaction
+    /// missing its price are left as they were.
+    pub fn save_tax_lots(&mut self) -> Result<Vec<(u32, CostBasis)>, rusqlite::Error> {
+        let wallet_fingerprints: Vec<u32> = self.config.wallets().iter().map(|wallet| wallet.wallet_fingerprint).collect();
+        let mut wallets_transactions = Vec::new();
+        for wallet_fingerprint in &wallet_fingerprints {
+            wallets_transactions.push(self.load_wallet_transactions(*wallet_fingerprint)?);
+        }
+        let wallets_transactions: Vec<&[WalletTransaction]> = wallets_transactions.iter().map(Vec::as_slice).collect();
 
-fn coin_paprika_historical_data_map<'de, D>(de: D) -> Result<HashMap<String, CoinPaprikaHistoricalData>, D::Error>
-where
-    D: serde::Deserializer<'de>,
-{
-    use serde::de::{Visitor, SeqAccess};
-    struct MapVisitor;
-    impl<'de> Visitor<'de> for MapVisitor {
-        type Value = HashMap<String, CoinPaprikaHistoricalData>;
-
-        fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
-            formatter.write_str("a sequence of items")
-        }
-
-        fn visit_seq<V>(self, mut seq: V) -> Result<HashMap<String, CoinPaprikaHistoricalData>, V::Error>
-        where
-            V: SeqAccess<'de>,
-        {
-            let mut map = HashMap::with_capacity(seq.size_hint().unwrap_or(0));
-
-            while let Some(item) = seq.next_element::<CoinPaprikaHistoricalResponse>()? {
-                let CoinPaprikaHistoricalResponse {
-                    timestamp,
-                    price,
-                    volume_24h,
-                    market_cap,
-                } = item;
-                match map.entry(timestamp.unwrap()) {
-                    std::collections::hash_map::Entry::Occupied(entry) => {
-                        return Err(serde::de::Error::custom(format!(
-                            "Duplicate entry {}",
-                            entry.key()
-                        )))
+        let mut cost_bases = Vec::new();
+        for quote_currency in &self.config.quote_currencies {
+            let wallet_cost_bases = match cost_basis::calculate_cost_bases(&wallets_transactions, quote_currency, self.config.cost_basis_method) {
+                Ok(wallet_cost_bases) => wallet_cost_bases,
+                Err(e) => {
+                    println!("failed to calculate the {} cost basis: {}", quote_currency, e);
+                    continue;
+                }
+            };
+
+            let transaction = self.db_connection.as_ref().expect("failed to get db_connection").unchecked_transaction()?;
+            let mut stmt = transaction.prepare(
+                "INSERT INTO tax_lots (
+                    wallet_fingerprint,
+                    quote_currency,
+                    cost_basis_method,
+                    disposal_transaction,
+                    disposed_at,
+                    acquisition_transaction,
+                    acquired_at,
+                    amount,
+                    cost_basis,
+                    proceeds,
+                    gain
+                ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+            )?;
+            for (wallet_fingerprint, cost_basis) in wallet_fingerprints.iter().zip(wallet_cost_bases) {
+                transaction.execute(
+                    "DELETE FROM tax_lots WHERE wallet_fingerprint = ?1 AND quote_currency = ?2",
+                    rusqlite::params![wallet_fingerprint, quote_currency],
+                )?;
+                for assignment in &cost_basis.assignments {
+                    stmt.execute(rusqlite::params![
+                        wallet_fingerprint,
+                        quote_currency,
+                        cost_basis.method.as_str(),
+                        format!("0x{}", hex::encode(assignment.disposal_transaction)),
+                        format_created_at_time(&assignment.disposed_at),
+                        assignment.acquisition_transaction.map(|transaction| format!("0x{}", hex::encode(transaction))),
+                        assignment.acquired_at.as_ref().map(format_created_at_time),
+                        assignment.amount,
+                        assignment.cost_basis.normalize().to_string(),
+                        assignment.proceeds.normalize().to_string(),
+                        assignment.gain().normalize().to_string(),
+                    ])?;
+                }
+                cost_bases.push((*wallet_fingerprint, cost_basis));
+            }
+            drop(stmt);
+            transaction.commit()?;
+        }
+
+        Ok(cost_bases)
+    }
+
+    /// Current xch price in `quote_currency` from the price providers
+    pub async fn get_current_price(&mut self, quote_currency: &str) -> Result<Decimal, PriceError> {
+        if self.db_connection.is_none() {
+            self.create_db_connection()?;
+        }
+
+        let db_connection = self.db_connection.as_ref().expect("failed to get db_connection");
+        let (_, price) = prices::get_xch_price_at_time(
+            Utc::now(),
+            quote_currency,
+            self.config.price_granularity,
+            db_connection,
+            &self.price_providers,
+        ).await?;
+
+        Ok(price)
+    }
+
+    /// Recalculates the end of day balances of the wallet from its first
+    /// transaction through today and replaces its `balance_history`. The
+    /// balances are valued with the cached price closest to the end of the
+    /// day, days without one within a day are fetched. After a failed fetch
+    /// the remaining days only use the cache, the days left without a price
+    /// are reported.
+    pub async fn save_balance_history(&mut self, wallet_fingerprint: u32) -> Result<Vec<DailyBalance>, rusqlite::Error> {
+        let wallet_transactions = self.load_wallet_transactions(wallet_fingerprint)?;
+        let mut daily_balances = balance_history::daily_balances(&wallet_transactions, Utc::now().date_naive());
+
+        let db_connection = self.db_connection.as_ref().expect("failed to get db_connection");
+        for quote_currency in &self.config.quote_currencies {
+            let mut fetch = true;
+            let mut missing_days: Vec<NaiveDate> = Vec::new();
+            for daily_balance in &mut daily_balances {
+                let end_of_day = daily_balance.day.and_hms_opt(23, 59, 59).expect("failed to get end of day").and_utc();
+                let load_cached = || prices::load_nearest_price(db_connection, prices::XCH, quote_currency, &end_of_day, Duration::days(1));
+                let mut price = load_cached()?.map(|(_, price)| price);
+                if price.is_none() && fetch {
+                    match prices::get_xch_price_at_time(end_of_day, quote_currency, PriceGranularity::Daily, db_connection, &self.price_providers).await {
+                        Ok((_, fetched_price)) => price = Some(fetched_price),
+                        Err(PriceError::Db(e)) => return Err(e),
+                        Err(e) => {
+                            println!("failed to get the {} price of {}: {}", quote_currency, daily_balance.day, e);
+                            fetch = false;
+                        }
                     }
-                    std::collections::hash_map::Entry::Vacant(entry) => {
-                        entry.insert(CoinPaprikaHistoricalData { price, volume_24h, market_cap })
+                    // the history from the day on is cached now, the following days are found there
+                    price = load_cached()?.map(|(_, price)| price).or(price);
+                }
+                match price {
+                    Some(price) => {
+                        daily_balance.prices.insert(quote_currency.clone(), price);
                     }
-                };
+                    None => missing_days.push(daily_balance.day),
+                }
+            }
+
+            if let (Some(first_day), Some(last_day)) = (missing_days.first(), missing_days.last()) {
+                println!(
+                    "wallet {}: {} days of the balance history between {} and {} have no {} value",
+                    wallet_fingerprint, missing_days.len(), first_day, last_day, quote_currency
+                );
             }
-            Ok(map)
         }
+
+        let transaction = db_connection.unchecked_transaction()?;
+        transaction.execute("DELETE FROM balance_history WHERE wallet_fingerprint = ?1", [wallet_fingerprint])?;
+        let mut stmt = transaction.prepare(
+            "INSERT INTO balance_history (wallet_fingerprint, day, quote_currency, balance, price, value)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        )?;
+        for daily_balance in &daily_balances {
+            for quote_currency in &self.config.quote_currencies {
+                stmt.execute(rusqlite::params![
+                    wallet_fingerprint,
+                    daily_balance.day.format("%Y-%m-%d").to_string(),
+                    quote_currency,
+                    daily_balance.balance,
+                    daily_balance.prices.get(quote_currency).map(|price| price.normalize().to_string()),
+                    daily_balance.value(quote_currency).map(|value| value.normalize().to_string()),
+                ])?;
+            }
+        }
+        drop(stmt);
+        transaction.commit()?;
+
+        Ok(daily_balances)
     }
 
-    de.deserialize_seq(MapVisitor)
-}
+    /// End of day balances of the wallet saved by the last refresh, in day order
+    pub fn load_balance_history(&mut self, wallet_fingerprint: u32) -> Result<Vec<DailyBalance>, rusqlite::Error> {
+        if self.db_connection.is_none() {
+            self.create_db_connection()?;
+        }
 
-use chrono::{DateTime, Utc, NaiveDateTime, Local};
+        let mut stmt = self.db_connection.as_ref().expect("failed to get db_connection").prepare(
+            "SELECT day, quote_currency, balance, price FROM balance_history WHERE wallet_fingerprint = ?1 ORDER BY day",
+        )?;
+        let mut rows = stmt.query([wallet_fingerprint])?;
+        let mut daily_balances: Vec<DailyBalance> = Vec::new();
+        while let Some(row) = rows.next()? {
+            let day = NaiveDate::parse_from_str(&row.get::<_, String>(0)?, "%Y-%m-%d").map_err(|e| conversion_error(0, e.into()))?;
+            if daily_balances.last().map_or(true, |daily_balance| daily_balance.day != day) {
+                daily_balances.push(DailyBalance { day, balance: row.get(2)?, prices: BTreeMap::new() });
+            }
+            if let Some(price) = row.get::<_, Option<String>>(3)? {
+                let price = Decimal::from_str(&price).map_err(|e| conversion_error(3, e.into()))?;
+                daily_balances.last_mut().expect("failed to get daily balance").prices.insert(row.get(1)?, price);
+            }
+        }
 
-async fn get_xch_to_usd_at_time(date: String, all_coin_paprika_historical_data: &mut AllCoinPaprikaHistoricalData) -> Result<Decimal, reqwest::Error> {
-    let mut price = Decimal::new(0, 0);
-    let timestamp = NaiveDateTime::parse_from_str(&date, "%Y-%m-%d %H:%M:%S").unwrap().timestamp();
-    let now = Local::now().timestamp() + Local::now().offset().local_minus_utc() as i64;
+        Ok(daily_balances)
+    }
 
-    println!("fetching usd price at {}", date);
+    pub fn load_wallet_transactions(&mut self, wallet_fingerprint: u32) -> Result<Vec<WalletTransaction>, rusqlite::Error> {
+        if self.db_connection.is_none() {
+            self.create_db_connection()?;
+        }
+
+        let db_connection = self.db_connection.as_ref().expect("failed to get db_connection");
+
+        let mut stmt = db_connection.prepare(
+            "SELECT `transaction`, tx_status, amount, to_address, created_at_time, flow,
+                transaction_type, fee_amount, confirmed_at_height, spend_bundle, category
+            FROM wallet_transactions WHERE wallet_fingerprint = ?1",
+        )?;
+        let mut wallet_transactions = stmt.query_map([wallet_fingerprint], wallet_transaction_from_row)?
+            .collect::<Result<Vec<WalletTransaction>, rusqlite::Error>>()?;
 
-    // check if date transaction is just in range of 24 hour ago
-    if timestamp > now - 86400 {
-        let url = format!("https://api.coinpaprika.com/v1/price-converter?base_currency_id=xch-chia-&quote_currency_id=usd-us-dollars&amount=1");
-        let response = reqwest::get(&url).await?;
-        let response_json: CoinPaprikaPriceConverter = response.json().await?;
-        price = response_json.price;
-    } else {
-        // convert timestamp to date yyyy-mm-dd
-        let date = DateTime::<Utc>::from_utc(NaiveDateTime::from_timestamp(timestamp, 0), Utc).format("20%y-%m-%d").to_string();
+        let mut additions: HashMap<[u8; 32], Vec<Coin>> = HashMap::new();
+        let mut removals: HashMap<[u8; 32], Vec<Coin>> = HashMap::new();
+        let mut stmt = db_connection.prepare(
+            "SELECT `transaction`, direction, parent_coin_info, puzzle_hash, amount FROM transaction_coins
+            WHERE wallet_fingerprint = ?1 ORDER BY rowid",
+        )?;
+        let mut rows = stmt.query([wallet_fingerprint])?;
+        while let Some(row) = rows.next()? {
+            let transaction = parse_transaction_id(&row.get::<_, String>(0)?).map_err(|e| conversion_error(0, e.into()))?;
+            let coin = Coin {
+                parent_coin_info: parse_bytes32(&row.get::<_, String>(2)?).map_err(|e| conversion_error(2, e.into()))?,
+                puzzle_hash: parse_bytes32(&row.get::<_, String>(3)?).map_err(|e| conversion_error(3, e.into()))?,
+                amount: row.get(4)?,
+            };
+            match row.get::<_, String>(1)?.as_str() {
+                COIN_REMOVAL => removals.entry(transaction).or_default().push(coin),
+                _ => additions.entry(transaction).or_default().push(coin),
+            }
+        }
 
-        if !all_coin_paprika_historical_data.0.contains_key(&format!("{}T00:00:00Z", date)) {
-            let url = format!("https://api.coinpaprika.com/v1/tickers/xch-chia-/historical?interval=1d&start={}", date);
-            let response = reqwest::get(&url).await?;
-            let response_text = response.text().await?;
-            *all_coin_paprika_historical_data = serde_json::from_str::<AllCoinPaprikaHistoricalData>(&response_text).unwrap();
+        let mut memos: HashMap<[u8; 32], Vec<Memo>> = HashMap::new();
+        let mut stmt = db_connection.prepare(
+            "SELECT `transaction`, coin_id, memo FROM transaction_memos WHERE wallet_fingerprint = ?1 ORDER BY coin_id, memo",
+        )?;
+        let mut rows = stmt.query([wallet_fingerprint])?;
+        while let Some(row) = rows.next()? {
+            let transaction = parse_transaction_id(&row.get::<_, String>(0)?).map_err(|e| conversion_error(0, e.into()))?;
+            memos.entry(transaction).or_default().push(Memo {
+                coin_id: parse_bytes32(&row.get::<_, String>(1)?).map_err(|e| conversion_error(1, e.into()))?,
+                memo: hex::decode(row.get::<_, String>(2)?).map_err(|e| conversion_error(2, e.into()))?,
+            });
         }
 
-        price = all_coin_paprika_historical_data.0.get(&format!("{}T00:00:00Z", date)).unwrap().price;
+        let mut fiat_at_time: HashMap<[u8; 32], BTreeMap<String, Decimal>> = HashMap::new();
+        let mut stmt = db_connection.prepare(
+            "SELECT `transaction`, quote_currency, price FROM transaction_prices WHERE wallet_fingerprint = ?1",
+        )?;
+        let mut rows = stmt.query([wallet_fingerprint])?;
+        while let Some(row) = rows.next()? {
+            let transaction = parse_transaction_id(&row.get::<_, String>(0)?).map_err(|e| conversion_error(0, e.into()))?;
+            let price = Decimal::from_str(&row.get::<_, String>(2)?).map_err(|e| conversion_error(2, e.into()))?;
+            fiat_at_time.entry(transaction).or_default().insert(row.get(1)?, price);
+        }
+
+        for wallet_transaction in &mut wallet_transactions {
+            wallet_transaction.fiat_at_time = fiat_at_time.remove(&wallet_transaction.transaction).unwrap_or_default();
+            wallet_transaction.additions = additions.remove(&wallet_transaction.transaction).unwrap_or_default();
+            wallet_transaction.removals = removals.remove(&wallet_transaction.transaction).unwrap_or_default();
+            wallet_transaction.memos = memos.remove(&wallet_transaction.transaction).unwrap_or_default();
+        }
+
+        Ok(wallet_transactions)
     }
 
-    Ok(price)
+    fn create_db_connection(&mut self) -> Result<(), rusqlite::Error> {
+        // Connect to DB
+        let mut db_connection = rusqlite::Connection::open(format!("{}/{}", self.config.db_path, self.config.db_name))?;
+        let user_version = migrations::run(&mut db_connection)?;
+        if user_version < migrations::WALLET_FINGERPRINT_VERSION {
+            if let Some(wallet) = self.config.wallets().first() {
+                assign_unowned_rows(&db_connection, wallet.wallet_fingerprint)?;
+            }
+        }
+        self.db_connection = Some(db_connection);
+
+        Ok(())
+    }
 }
 
-#[cfg(test)]
-mod tests {
-    use std::str::FromStr;
+/// `direction` of the coins in `transaction_coins`
+const COIN_ADDITION: &str = "addition";
+const COIN_REMOVAL: &str = "removal";
 
-    use super::*;
+/// Rows saved before the wallet fingerprint was stored belong to the wallet
+/// that was tracked then, which is the first one of the config. Only needed
+/// right after the fingerprint columns were added.
+fn assign_unowned_rows(db_connection: &rusqlite::Connection, wallet_fingerprint: u32) -> Result<(), rusqlite::Error> {
+    for table in [
+        "wallet_transactions",
+        "transaction_coins",
+        "transaction_memos",
+        "transaction_prices",
+        "coins",
+        "addresses",
+        "tx_status_history",
+    ] {
+        db_connection.execute(
+            &format!("UPDATE {} SET wallet_fingerprint = ?1 WHERE wallet_fingerprint = 0", table),
+            [wallet_fingerprint],
+        )?;
+    }
 
-    #[tokio::test]
-    async fn test_time() {
-        let date = String::from("2023-11-16 2:39:48");
-        let timestamp = NaiveDateTime::parse_from_str(&date, "%Y-%m-%d %H:%M:%S").unwrap().timestamp();
-        let now = Local::now().timestamp() + Local::now().offset().local_minus_utc() as i64;
+    Ok(())
+}
 
-        assert_eq!(timestamp > now - 86400, false);
-    }
+fn log_status_change(
+    db_connection: &rusqlite::Connection,
+    wallet_fingerprint: u32,
+    transaction_id: &str,
+    old_status: Option<TxStatus>,
+    new_status: TxStatus,
+    confirmed_at_height: Option<u32>,
+    observed_at: &str,
+) -> Result<(), rusqlite::Error> {
+    db_connection.execute(
+        "INSERT INTO tx_status_history (
+            wallet_fingerprint,
+            `transaction`,
+            old_status,
+            new_status,
+            confirmed_at_height,
+            observed_at
+        ) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        rusqlite::params![
+            wallet_fingerprint,
+            transaction_id,
+            old_status.map(|status| status.as_str()),
+            new_status.as_str(),
+            confirmed_at_height,
+            observed_at,
+        ],
+    )?;
 
-    #[tokio::test]
-    async fn test_get_xch_to_usd_at_time() {
-        let mut all_coin_paprika_historical_data = AllCoinPaprikaHistoricalData(HashMap::new());
-        let date = String::from("2023-08-31 00:00:00");
-        let price = get_xch_to_usd_at_time(date, &mut all_coin_paprika_historical_data).await.unwrap();
-        assert_eq!(price, Decimal::from_str("29.54").unwrap());
+    Ok(())
+}
+
+/// One price column per quote currency, in the order of the config
+fn transaction_sheet_row(wallet_transaction: &WalletTransaction, quote_currencies: &[String], wallet_label: &str) -> Vec<Value> {
+    let mut row = vec![
+        Value::String(wallet_transaction.transaction_id()),
+        Value::String(wallet_transaction.status.as_str().to_string()),
+        Value::String(wallet_transaction.chia_amount().to_string()),
+        Value::String(
+            wallet_transaction
+                .to_address
+                .clone()
+                .unwrap_or(String::from("")),
+        ),
+        Value::String(
+            wallet_transaction
+                .created_at_time
+                .with_timezone(&Local)
+                .format("%Y-%m-%d %H:%M:%S")
+                .to_string(),
+        ),
+        Value::String(wallet_transaction.flow.as_str().to_string()),
+    ];
+    // a price that isn't fetched yet is left empty, a 0 would count in sums over the sheet
+    row.extend(quote_currencies.iter().map(|quote_currency| {
+        wallet_transaction.fiat_at_time.get(quote_currency).map_or(Value::Null, |price| Value::String(price.to_string()))
+    }));
+    row.extend([
+        Value::String(wallet_transaction.chia_fee_amount().to_string()),
+        wallet_transaction.confirmed_at_height.map_or(Value::Null, Value::from),
+        Value::String(wallet_transaction.category.as_str().to_string()),
+        Value::String(wallet_label.to_string()),
+    ]);
+    row
+}
+
+fn conversion_error(column: usize, e: Box<dyn std::error::Error + Send + Sync>) -> rusqlite::Error {
+    rusqlite::Error::FromSqlConversionFailure(column, rusqlite::types::Type::Text, e)
+}
+
+fn wallet_transaction_from_row(row: &rusqlite::Row) -> Result<WalletTransaction, rusqlite::Error> {
+    let to_address: Option<String> = row.get(3)?;
+
+    Ok(WalletTransaction {
+        transaction: parse_transaction_id(&row.get::<_, String>(0)?).map_err(|e| conversion_error(0, e.into()))?,
+        status: row.get::<_, String>(1)?.parse().map_err(|e: TransactionError| conversion_error(1, e.into()))?,
+        amount: row.get(2)?,
+        to_address: to_address.filter(|to_address| !to_address.is_empty()),
+        created_at_time: parse_created_at_time(&row.get::<_, String>(4)?).map_err(|e| conversion_error(4, e.into()))?,
+        flow: row.get::<_, String>(5)?.parse().map_err(|e: TransactionError| conversion_error(5, e.into()))?,
+        category: row.get::<_, String>(10)?.parse().map_err(|e: TransactionError| conversion_error(10, e.into()))?,
+        fiat_at_time: BTreeMap::new(),
+        transaction_type: row.get::<_, Option<u32>>(6)?.map(TransactionType::from_u32),
+        fee_amount: row.get(7)?,
+        confirmed_at_height: row.get(8)?,
+        additions: Vec::new(),
+        removals: Vec::new(),
+        memos: Vec::new(),
+        spend_bundle: row.get(9)?,
+    })
+}
+
+use chrono::{DateTime, Duration, Local, NaiveDate, Utc};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::price_provider::PriceProviderKind;
+    use crate::wallet_transactions::tests::wallet_transaction;
+    use crate::Config;
+
+    /// Config with a db of its own in /tmp, priced from a price file with
+    /// `prices` only
+    fn price_file_config(name: &str, prices: &str) -> Config {
+        let price_file_path = std::env::temp_dir().join(format!("{}.csv", name));
+        std::fs::write(&price_file_path, prices).unwrap();
+        let mut config = Config::default();
+        config.db_path = String::from("/tmp");
+        config.db_name = format!("{}.db", name);
+        config.price_providers = vec![PriceProviderKind::File];
+        config.price_file_path = Some(price_file_path.display().to_string());
+        std::fs::remove_file(format!("{}/{}", config.db_path, config.db_name)).ok();
+        config
     }
 
     #[tokio::test]
     async fn test_save_to_db() {
         use super::*;
-        use crate::wallet_transactions::WalletTransaction;
-        use crate::Config;
+        use crate::wallet_transactions::{Flow, TxCategory, TxStatus, WalletTransaction};
 
         let mut config = Config::default();
         config.db_path = String::from("/tmp");
@@ -389,48 +1062,464 @@ mod tests {
 
         let wallet_transactions = vec![
             WalletTransaction {
-                transaction: Some(String::from("test1")),
-                status: Some(false),
-                chia_amount: Some(String::from("39.39")),
+                transaction: [1u8; 32],
+                status: TxStatus::Pending,
+                amount: 39_390_000_000_000,
                 to_address: Some(String::from("test_address1")),
-                created_at_time: Some(String::from("2022-12-30 00:00:00")),
-                flow: Some(String::from("39.39")),
-                usd_at_time: None,
+                created_at_time: parse_created_at_time("2022-12-30T00:00:00Z").unwrap(),
+                flow: Flow::Incoming,
+                category: TxCategory::PoolReward,
+                fiat_at_time: BTreeMap::new(),
+                transaction_type: Some(TransactionType::CoinbaseReward),
+                fee_amount: 0,
+                confirmed_at_height: Some(3_000_000),
+                additions: vec![Coin { parent_coin_info: [3u8; 32], puzzle_hash: [4u8; 32], amount: 39_390_000_000_000 }],
+                removals: Vec::new(),
+                memos: vec![Memo { coin_id: [5u8; 32], memo: b"invoice 42".to_vec() }],
+                spend_bundle: None,
             },
             WalletTransaction {
-                transaction: Some(String::from("test2")),
-                status: Some(false),
-                chia_amount: Some(String::from("39.39")),
+                transaction: [2u8; 32],
+                status: TxStatus::Pending,
+                amount: 39_390_000_000_000,
                 to_address: Some(String::from("test_address2")),
-                created_at_time: Some(String::from("2022-12-30 00:00:00")),
-                flow: Some(String::from("39.39")),
-                usd_at_time: None,
+                created_at_time: parse_created_at_time("2022-12-30T00:00:00Z").unwrap(),
+                flow: Flow::Outgoing,
+                category: TxCategory::Payment,
+                fiat_at_time: BTreeMap::new(),
+                transaction_type: Some(TransactionType::OutgoingTx),
+                fee_amount: 1_000_000,
+                confirmed_at_height: None,
+                additions: vec![Coin { parent_coin_info: [6u8; 32], puzzle_hash: [7u8; 32], amount: 1_000_000_000_000 }],
+                removals: vec![
+                    Coin { parent_coin_info: [8u8; 32], puzzle_hash: [4u8; 32], amount: 20_000_000_000_000 },
+                    Coin { parent_coin_info: [9u8; 32], puzzle_hash: [4u8; 32], amount: 20_391_000_000_000 },
+                ],
+                memos: Vec::new(),
+                spend_bundle: Some(String::from(r#"{"aggregated_signature":"0xc0","coin_spends":[]}"#)),
             },
         ];
 
-        let db_connection = rusqlite::Connection::open(format!("{}/{}", config.db_path, config.db_name)).unwrap();
-        db_connection.execute("DROP TABLE IF EXISTS wallet_transactions", rusqlite::params![]).unwrap();
-
-        wallet_transactions_save.save_to_db(&wallet_transactions).await.expect("failed to save to db");
-
-        let mut stmt = db_connection.prepare("SELECT * FROM wallet_transactions").unwrap();
-        let wallet_transactions_from_db = stmt.query_map(rusqlite::params![], |row| {
-            Ok(WalletTransaction {
-                transaction: row.get(0).unwrap(),
-                status: row.get(1).unwrap(),
-                chia_amount: row.get(2).unwrap(),
-                to_address: row.get(3).unwrap(),
-                created_at_time: row.get(4).unwrap(),
-                flow: row.get(5).unwrap(),
-                usd_at_time: None,
-            })
-        }).unwrap();
+        std::fs::remove_file(format!("{}/{}", config.db_path, config.db_name)).ok();
 
-        let mut wallet_transactions_from_db_vec = Vec::new();
-        for wallet_transaction in wallet_transactions_from_db {
-            wallet_transactions_from_db_vec.push(wallet_transaction.unwrap());
-        }
+        wallet_transactions_save.save_to_db(config.wallet_fingerprint, &wallet_transactions).await.expect("failed to save to db");
+
+        let wallet_transactions_from_db_vec: Vec<WalletTransaction> = wallet_transactions_save
+            .load_wallet_transactions(config.wallet_fingerprint)
+            .unwrap()
+            .into_iter()
+            .map(|wallet_transaction| WalletTransaction { fiat_at_time: BTreeMap::new(), ..wallet_transaction })
+            .collect();
 
         assert_eq!(wallet_transactions, wallet_transactions_from_db_vec);
     }
+
+    #[tokio::test]
+    async fn test_save_status_transitions() {
+        let mut config = Config::default();
+        config.db_path = String::from("/tmp");
+        config.db_name = String::from("wallet_transactions_save_status_test.db");
+
+        std::fs::remove_file(format!("{}/{}", config.db_path, config.db_name)).ok();
+        let db_connection = rusqlite::Connection::open(format!("{}/{}", config.db_path, config.db_name)).unwrap();
+
+        // internal so no usd price is fetched
+        let pending = WalletTransaction {
+            status: TxStatus::Pending,
+            to_address: Some(String::from("xch1own")),
+            transaction_type: Some(TransactionType::OutgoingTx),
+            confirmed_at_height: None,
+            spend_bundle: Some(String::from("{}")),
+            ..wallet_transaction(1, Flow::Internal, 1_000_000_000_000, "2023-11-16T02:39:48Z")
+        };
+        let confirmed = WalletTransaction {
+            status: TxStatus::Confirmed,
+            confirmed_at_height: Some(4_500_000),
+            created_at_time: parse_created_at_time("2023-11-16T02:41:00Z").unwrap(),
+            spend_bundle: None,
+            ..pending.clone()
+        };
+
+        let mut wallet_transactions_save = WalletTransactionsSave::new(&config);
+        wallet_transactions_save.save_to_db(config.wallet_fingerprint, &vec![pending.clone()]).await.unwrap();
+        wallet_transactions_save.save_to_db(config.wallet_fingerprint, &vec![pending]).await.unwrap();
+        wallet_transactions_save.save_to_db(config.wallet_fingerprint, &vec![confirmed.clone()]).await.unwrap();
+
+        let stored = wallet_transactions_save.load_wallet_transactions(config.wallet_fingerprint).unwrap();
+        assert_eq!(stored, vec![WalletTransaction { spend_bundle: Some(String::from("{}")), ..confirmed }]);
+
+        let history = db_connection
+            .prepare("SELECT old_status, new_status, confirmed_at_height FROM tx_status_history ORDER BY id").unwrap()
+            .query_map(rusqlite::params![], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?))).unwrap()
+            .collect::<Result<Vec<(Option<String>, String, Option<u32>)>, rusqlite::Error>>().unwrap();
+        assert_eq!(history, vec![
+            (None, String::from("pending"), None),
+            (Some(String::from("pending")), String::from("confirmed"), Some(4_500_000)),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_save_dropped_and_reorged() {
+        let mut config = Config::default();
+        config.db_path = String::from("/tmp");
+        config.db_name = String::from("wallet_transactions_save_reorg_test.db");
+
+        std::fs::remove_file(format!("{}/{}", config.db_path, config.db_name)).ok();
+        let db_connection = rusqlite::Connection::open(format!("{}/{}", config.db_path, config.db_name)).unwrap();
+
+        let transfer = |id: u8, status: TxStatus, confirmed_at_height: Option<u32>| WalletTransaction {
+            status,
+            to_address: Some(String::from("xch1own")),
+            transaction_type: Some(TransactionType::OutgoingTx),
+            confirmed_at_height,
+            ..wallet_transaction(id, Flow::Internal, 1_000_000_000_000, "2023-11-16T02:39:48Z")
+        };
+        let statuses = |wallet_transactions_save: &mut WalletTransactionsSave| -> Vec<TxStatus> {
+            wallet_transactions_save.load_wallet_transactions(config.wallet_fingerprint).unwrap()
+                .into_iter()
+                .map(|wallet_transaction| wallet_transaction.status)
+                .collect()
+        };
+
+        let mut wallet_transactions_save = WalletTransactionsSave::new(&config);
+        wallet_transactions_save.save_to_db(config.wallet_fingerprint, &vec![
+            transfer(1, TxStatus::Confirmed, Some(100)),
+            transfer(2, TxStatus::Pending, None),
+            transfer(3, TxStatus::Confirmed, Some(200)),
+        ]).await.unwrap();
+
+        // a failed poll doesn't flag anything
+        wallet_transactions_save.save_to_db(config.wallet_fingerprint, &vec![]).await.unwrap();
+        assert_eq!(statuses(&mut wallet_transactions_save), vec![TxStatus::Confirmed, TxStatus::Pending, TxStatus::Confirmed]);
+
+        wallet_transactions_save.save_to_db(config.wallet_fingerprint, &vec![transfer(3, TxStatus::Confirmed, Some(201))]).await.unwrap();
+        assert_eq!(statuses(&mut wallet_transactions_save), vec![TxStatus::Reorged, TxStatus::Dropped, TxStatus::Confirmed]);
+
+        // back in the chain
+        wallet_transactions_save.save_to_db(config.wallet_fingerprint, &vec![
+            transfer(1, TxStatus::Confirmed, Some(105)),
+            transfer(3, TxStatus::Confirmed, Some(201)),
+        ]).await.unwrap();
+        assert_eq!(statuses(&mut wallet_transactions_save), vec![TxStatus::Confirmed, TxStatus::Dropped, TxStatus::Confirmed]);
+
+        let history = db_connection
+            .prepare("SELECT old_status, new_status, confirmed_at_height FROM tx_status_history WHERE `transaction` = ?1 ORDER BY id").unwrap()
+            .query_map([format!("0x{}", "03".repeat(32))], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?))).unwrap()
+            .collect::<Result<Vec<(Option<String>, String, Option<u32>)>, rusqlite::Error>>().unwrap();
+        assert_eq!(history, vec![
+            (None, String::from("confirmed"), Some(200)),
+            (Some(String::from("confirmed")), String::from("reorged"), Some(200)),
+            (Some(String::from("reorged")), String::from("confirmed"), Some(201)),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_save_multiple_wallets() {
+        let mut config = Config::default();
+        config.db_path = String::from("/tmp");
+        config.db_name = String::from("wallet_transactions_save_wallets_test.db");
+        std::fs::remove_file(format!("{}/{}", config.db_path, config.db_name)).ok();
+
+        let transfer = |id: u8| WalletTransaction {
+            to_address: Some(String::from("xch1cold")),
+            transaction_type: Some(TransactionType::OutgoingTx),
+            confirmed_at_height: Some(100),
+            ..wallet_transaction(id, Flow::Internal, 1_000_000_000_000, "2023-11-16T02:39:48Z")
+        };
+        let (first, second) = (1u32, 2u32);
+
+        let mut wallet_transactions_save = WalletTransactionsSave::new(&config);
+        // a transfer between the two wallets is stored for both
+        wallet_transactions_save.save_to_db(first, &vec![transfer(1)]).await.unwrap();
+        wallet_transactions_save.save_to_db(second, &vec![transfer(1), transfer(2)]).await.unwrap();
+        // refreshing one wallet doesn't flag the transactions of the other
+        wallet_transactions_save.save_to_db(first, &vec![transfer(1)]).await.unwrap();
+
+        let transactions = |wallet_transactions_save: &mut WalletTransactionsSave, wallet_fingerprint: u32| -> Vec<([u8; 32], TxStatus)> {
+            wallet_transactions_save.load_wallet_transactions(wallet_fingerprint).unwrap()
+                .into_iter()
+                .map(|wallet_transaction| (wallet_transaction.transaction, wallet_transaction.status))
+                .collect()
+        };
+        assert_eq!(transactions(&mut wallet_transactions_save, first), vec![([1u8; 32], TxStatus::Confirmed)]);
+        assert_eq!(
+            transactions(&mut wallet_transactions_save, second),
+            vec![([1u8; 32], TxStatus::Confirmed), ([2u8; 32], TxStatus::Confirmed)],
+        );
+    }
+
+    #[tokio::test]
+    async fn test_save_fiat_prices() {
+        let mut config = price_file_config(
+            "wallet_transactions_save_prices_test",
+            "timestamp,price,quote_currency\n2023-08-31,29.54,usd\n2023-08-31,27.2,eur\n",
+        );
+        config.quote_currencies = vec![String::from("usd"), String::from("eur"), String::from("jpy")];
+
+        let wallet_transaction = WalletTransaction {
+            to_address: Some(String::from("xch1test")),
+            transaction_type: Some(TransactionType::IncomingTx),
+            ..wallet_transaction(1, Flow::Incoming, 1_000_000_000_000, "2023-08-31T15:20:00Z")
+        };
+
+        let mut wallet_transactions_save = WalletTransactionsSave::new(&config);
+        wallet_transactions_save.save_to_db(config.wallet_fingerprint, &vec![wallet_transaction.clone()]).await.unwrap();
+
+        // no jpy prices in the file, it's left out
+        let wallet_transactions = wallet_transactions_save.load_wallet_transactions(config.wallet_fingerprint).unwrap();
+        assert_eq!(wallet_transactions[0].fiat_at_time, BTreeMap::from([
+            (String::from("eur"), Decimal::from_str("27.2").unwrap()),
+            (String::from("usd"), Decimal::from_str("29.54").unwrap()),
+        ]));
+
+        let price_timestamp: String = wallet_transactions_save.db_connection.as_ref().unwrap()
+            .query_row("SELECT price_timestamp FROM transaction_prices WHERE quote_currency = 'usd'", rusqlite::params![], |row| row.get(0))
+            .unwrap();
+        assert_eq!(price_timestamp, "2023-08-31T00:00:00Z");
+
+        let row = transaction_sheet_row(&wallet_transactions[0], &config.quote_currencies, "cold storage");
+        assert_eq!(row[6..9], [Value::from("29.54"), Value::from("27.2"), Value::Null]);
+    }
+
+    #[tokio::test]
+    async fn test_save_tax_lots() {
+        let config = price_file_config("wallet_transactions_save_tax_lots_test", "timestamp,price\n2023-08-31,29.54\n2023-09-01,30.1\n");
+        let wallet_transactions = vec![
+            wallet_transaction(1, Flow::Incoming, 2_000_000_000_000, "2023-08-31T10:00:00Z"),
+            wallet_transaction(2, Flow::Outgoing, 500_000_000_000, "2023-09-01T10:00:00Z"),
+        ];
+
+        let mut wallet_transactions_save = WalletTransactionsSave::new(&config);
+        wallet_transactions_save.save_to_db(config.wallet_fingerprint, &wallet_transactions).await.unwrap();
+        let cost_bases = wallet_transactions_save.save_tax_lots().unwrap();
+        assert_eq!(cost_bases.len(), 1);
+        assert_eq!(cost_bases[0].0, config.wallet_fingerprint);
+        assert_eq!(cost_bases[0].1.unrealized_gain(Decimal::from(30)), Decimal::from_str("0.69").unwrap());
+        // recalculated without piling up rows
+        wallet_transactions_save.save_tax_lots().unwrap();
+
+        let tax_lots = wallet_transactions_save.db_connection.as_ref().unwrap()
+            .prepare("SELECT cost_basis_method, disposal_transaction, acquisition_transaction, amount, cost_basis, proceeds, gain FROM tax_lots").unwrap()
+            .query_map(rusqlite::params![], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?, row.get(5)?, row.get(6)?))
+            }).unwrap()
+            .collect::<Result<Vec<(String, String, Option<String>, u64, String, String, String)>, rusqlite::Error>>().unwrap();
+        assert_eq!(tax_lots, vec![(
+            String::from("fifo"),
+            format!("0x{}", hex::encode([2u8; 32])),
+            Some(format!("0x{}", hex::encode([1u8; 32]))),
+            500_000_000_000,
+            String::from("14.77"),
+            String::from("15.05"),
+            String::from("0.28"),
+        )]);
+    }
+
+    #[tokio::test]
+    async fn test_save_balance_history() {
+        let config = price_file_config("wallet_transactions_save_balance_history_test", "timestamp,price\n2023-08-31,29.54\n2023-09-01,30.1\n");
+        let wallet_transactions = vec![
+            wallet_transaction(1, Flow::Incoming, 2_000_000_000_000, "2023-08-30T10:00:00Z"),
+            wallet_transaction(2, Flow::Outgoing, 500_000_000_000, "2023-09-01T10:00:00Z"),
+        ];
+
+        let mut wallet_transactions_save = WalletTransactionsSave::new(&config);
+        wallet_transactions_save.save_to_db(config.wallet_fingerprint, &wallet_transactions).await.unwrap();
+        // the missing prices are fetched again
+        wallet_transactions_save.db_connection.as_ref().unwrap().execute("DELETE FROM prices", rusqlite::params![]).unwrap();
+        let saved = wallet_transactions_save.save_balance_history(config.wallet_fingerprint).await.unwrap();
+        // recalculated without piling up rows
+        wallet_transactions_save.save_balance_history(config.wallet_fingerprint).await.unwrap();
+
+        let daily_balances = wallet_transactions_save.load_balance_history(config.wallet_fingerprint).unwrap();
+        assert_eq!(daily_balances, saved);
+        assert_eq!(daily_balances.last().unwrap().day, Utc::now().date_naive());
+        assert_eq!(daily_balances[0].day, NaiveDate::from_ymd_opt(2023, 8, 30).unwrap());
+        // valued with the price of the next midnight, or the day's own when there's none
+        assert_eq!(daily_balances[0].value("usd"), Some(Decimal::from_str("59.08").unwrap()));
+        assert_eq!(daily_balances[1].value("usd"), Some(Decimal::from_str("60.2").unwrap()));
+        assert_eq!(daily_balances[2].chia_balance(), Decimal::from_str("1.5").unwrap());
+        // not in the price file either
+        assert_eq!(daily_balances[3].value("usd"), None);
+
+        let value: Option<String> = wallet_transactions_save.db_connection.as_ref().unwrap()
+            .query_row("SELECT value FROM balance_history WHERE day = '2023-09-01' AND quote_currency = 'usd'", rusqlite::params![], |row| row.get(0))
+            .unwrap();
+        assert_eq!(value, Some(String::from("45.15")));
+    }
+
+    #[test]
+    fn test_save_coins() {
+        let mut config = Config::default();
+        config.db_path = String::from("/tmp");
+        config.db_name = String::from("wallet_transactions_save_coins_test.db");
+
+        std::fs::remove_file(format!("{}/{}", config.db_path, config.db_name)).ok();
+        let db_connection = rusqlite::Connection::open(format!("{}/{}", config.db_path, config.db_name)).unwrap();
+
+        let coin = Coin { parent_coin_info: [3u8; 32], puzzle_hash: [4u8; 32], amount: 39_390_000_000_000 };
+        let received = WalletTransaction {
+            transaction_type: Some(TransactionType::IncomingTx),
+            confirmed_at_height: Some(3_000_000),
+            additions: vec![coin.clone()],
+            ..wallet_transaction(1, Flow::Incoming, coin.amount, "2022-12-30T00:00:00Z")
+        };
+        let puzzle_hashes = std::collections::HashSet::from([[4u8; 32]]);
+
+        let mut wallet_transactions_save = WalletTransactionsSave::new(&config);
+        wallet_transactions_save.save_coins(config.wallet_fingerprint, &CoinSet::from_transactions(std::slice::from_ref(&received), &puzzle_hashes)).unwrap();
+
+        // spent in a later poll
+        let spend = WalletTransaction {
+            transaction: [2u8; 32],
+            confirmed_at_height: Some(3_000_100),
+            additions: Vec::new(),
+            removals: vec![coin.clone()],
+            ..received.clone()
+        };
+        wallet_transactions_save.save_coins(config.wallet_fingerprint, &CoinSet::from_transactions(&[received.clone(), spend], &puzzle_hashes)).unwrap();
+
+        let rows = db_connection
+            .prepare("SELECT coin_id, amount, confirmed, created_height, spent, spent_height FROM coins").unwrap()
+            .query_map(rusqlite::params![], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?, row.get(5)?))).unwrap()
+            .collect::<Result<Vec<(String, u64, bool, Option<u32>, bool, Option<u32>)>, rusqlite::Error>>().unwrap();
+        assert_eq!(rows, vec![(
+            format!("0x{}", hex::encode(coin.coin_id())),
+            39_390_000_000_000,
+            true,
+            Some(3_000_000),
+            true,
+            Some(3_000_100),
+        )]);
+
+        // the transaction got reorged out
+        let other = Coin { parent_coin_info: [5u8; 32], ..coin };
+        let received = WalletTransaction { additions: vec![other.clone()], ..received };
+        wallet_transactions_save.save_coins(config.wallet_fingerprint, &CoinSet::from_transactions(&[received], &puzzle_hashes)).unwrap();
+        let coin_ids = db_connection
+            .prepare("SELECT coin_id FROM coins").unwrap()
+            .query_map(rusqlite::params![], |row| row.get(0)).unwrap()
+            .collect::<Result<Vec<String>, rusqlite::Error>>().unwrap();
+        assert_eq!(coin_ids, vec![format!("0x{}", hex::encode(other.coin_id()))]);
+    }
+
+    #[test]
+    fn test_assign_unowned_rows() {
+        let mut config = Config::default();
+        config.db_path = String::from("/tmp");
+        config.db_name = String::from("wallet_transactions_save_unowned_test.db");
+
+        std::fs::remove_file(format!("{}/{}", config.db_path, config.db_name)).ok();
+        let db_connection = rusqlite::Connection::open(format!("{}/{}", config.db_path, config.db_name)).unwrap();
+        // the schema of the first release
+        db_connection.execute_batch(
+            "CREATE TABLE wallet_transactions (
+                `transaction` TEXT PRIMARY KEY,
+                status BOOLEAN,
+                chia_amount TEXT,
+                to_address TEXT,
+                created_at_time TEXT,
+                flow TEXT,
+                usd_at_time TEXT
+            );
+            INSERT INTO wallet_transactions VALUES ('0x01', 1, '39.39', 'xch1test', '2023-08-31T00:00:00Z', 'incoming', NULL);",
+        ).unwrap();
+        let fingerprints = || -> Vec<u32> {
+            db_connection
+                .prepare("SELECT wallet_fingerprint FROM wallet_transactions ORDER BY `transaction`").unwrap()
+                .query_map(rusqlite::params![], |row| row.get(0)).unwrap()
+                .collect::<Result<Vec<u32>, rusqlite::Error>>().unwrap()
+        };
+
+        WalletTransactionsSave::new(&config).load_addresses(config.wallet_fingerprint).unwrap();
+        assert_eq!(fingerprints(), vec![config.wallet_fingerprint]);
+
+        // only assigned once, when the db was migrated
+        db_connection.execute(
+            "INSERT INTO wallet_transactions (wallet_fingerprint, `transaction`, tx_status, amount, created_at_time, flow, category)
+            VALUES (0, '0x02', 'confirmed', 1000, '2023-09-01T00:00:00Z', 'incoming', 'receipt')",
+            rusqlite::params![],
+        ).unwrap();
+        WalletTransactionsSave::new(&config).load_addresses(config.wallet_fingerprint).unwrap();
+        assert_eq!(fingerprints(), vec![config.wallet_fingerprint, 0]);
+    }
+
+    #[test]
+    fn test_save_addresses() {
+        let mut config = Config::default();
+        config.db_path = String::from("/tmp");
+        config.db_name = String::from("wallet_transactions_save_addresses_test.db");
+
+        std::fs::remove_file(format!("{}/{}", config.db_path, config.db_name)).ok();
+        let db_connection = rusqlite::Connection::open(format!("{}/{}", config.db_path, config.db_name)).unwrap();
+
+        let addresses = vec![
+            AddressSummary {
+                address: String::from("xch1first"),
+                derivation_index: Some(0),
+                total_received: 2_000,
+                total_sent: Some(1_000),
+                first_seen: Some(parse_created_at_time("2023-08-31T00:00:00Z").unwrap()),
+                last_seen: Some(parse_created_at_time("2023-09-02T00:00:00Z").unwrap()),
+                tx_count: 3,
+            },
+            AddressSummary {
+                address: String::from("xch1unused"),
+                derivation_index: Some(1),
+                total_received: 0,
+                total_sent: Some(0),
+                first_seen: None,
+                last_seen: None,
+                tx_count: 0,
+            },
+            AddressSummary {
+                address: String::from("xch1watched"),
+                derivation_index: None,
+                total_received: 0,
+                total_sent: None,
+                first_seen: None,
+                last_seen: None,
+                tx_count: 0,
+            },
+        ];
+
+        let mut wallet_transactions_save = WalletTransactionsSave::new(&config);
+        wallet_transactions_save.save_addresses(config.wallet_fingerprint, &addresses).unwrap();
+        wallet_transactions_save.save_addresses(config.wallet_fingerprint, &addresses[..1]).unwrap();
+
+        assert_eq!(wallet_transactions_save.load_addresses(config.wallet_fingerprint).unwrap(), addresses);
+        let balance: u64 = db_connection
+            .query_row("SELECT balance FROM addresses WHERE address = 'xch1first'", rusqlite::params![], |row| row.get(0))
+            .unwrap();
+        assert_eq!(balance, 1_000);
+        let (kind, balance): (String, Option<u64>) = db_connection
+            .query_row("SELECT kind, balance FROM addresses WHERE address = 'xch1watched'", rusqlite::params![], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap();
+        assert_eq!((kind.as_str(), balance), ("watched", None));
+    }
+
+    #[test]
+    fn test_save_derivation_state() {
+        use chia_observer_wallet_generator::G1Element;
+        use hex::FromHex;
+
+
+        let mut config = Config::default();
+        config.db_path = String::from("/tmp");
+        config.db_name = String::from("wallet_transactions_save_derivation_test.db");
+
+        std::fs::remove_file(format!("{}/{}", config.db_path, config.db_name)).ok();
+
+        let pk = G1Element::from_bytes(&<[u8; 48]>::from_hex(&config.wallet_public_key).unwrap()).unwrap();
+        let mut wallet_transactions_save = WalletTransactionsSave::new(&config);
+        assert_eq!(wallet_transactions_save.load_derivation_state(config.wallet_fingerprint).unwrap(), (Vec::new(), None));
+
+        let address_discovery = AddressDiscovery::new(&pk, 5, Vec::new(), Some(7));
+        wallet_transactions_save.save_derivation_state(config.wallet_fingerprint, &address_discovery).unwrap();
+
+        let (derived_addresses, highest_used_index) = wallet_transactions_save.load_derivation_state(config.wallet_fingerprint).unwrap();
+        assert_eq!(derived_addresses.as_slice(), address_discovery.derived_addresses());
+        assert_eq!(highest_used_index, Some(7));
+        assert_eq!(wallet_transactions_save.load_derivation_state(config.wallet_fingerprint + 1).unwrap(), (Vec::new(), None));
+    }
 }